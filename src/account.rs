@@ -110,7 +110,7 @@ fn get_access() -> Result<Account, String> {
     info!("Starting auth callback server");
     let listener = TcpListener::bind("127.0.0.1:8888").map_err(|e| e.to_string())?;
     let challenge = base64ify(random_string(64));
-    let scope = "user-read-private user-read-email user-read-playback-state user-modify-playback-state user-read-currently-playing user-read-recently-played user-library-read user-library-modify user-top-read playlist-read-private playlist-read-collaborative playlist-modify-public playlist-modify-private";
+    let scope = "user-read-private user-read-email user-read-playback-state user-modify-playback-state user-read-currently-playing user-read-recently-played user-library-read user-library-modify user-follow-read user-follow-modify user-top-read playlist-read-private playlist-read-collaborative playlist-modify-public playlist-modify-private ugc-image-upload";
     let mut request = format!("client_id={}&response_type=code&state={}&redirect_uri=http://localhost:8888/callback.html&code_challenge_method=S256&code_challenge={}&scope={}",
 	    SPOTIFY_CLIENT_ID, random_string(16), 
 	    gen_code_challenge(&challenge), scope);
@@ -1,8 +1,11 @@
-use serde_json::Value;
+use serde_json::{json, Value};
 use ureq::{Error, Request, Response};
 
 use crate::account::Account;
-use crate::data::{Content, ContentType, Playlist, PlaylistTrack, SpotifyURI, Visibility};
+use crate::data::{
+    Album, Artist, Content, ContentType, Playlist, PlaylistTrack, Show, SpotifyURI, Visibility,
+};
+use crate::utils::{epoch_time_to_rfc3339, url_encode};
 use crate::{info, warn};
 
 pub trait RequestExt {
@@ -18,22 +21,48 @@ impl RequestExt for Request {
     }
 }
 
+/// the body of an API request. Almost everything is JSON, apart from image uploads.
+enum Payload<'a> {
+    Json(Value),
+    Jpeg(&'a str),
+}
+
 pub fn do_api(
     method: &str,
     endpoint: &str,
     account: &mut Account,
     json: impl serde::Serialize,
+) -> Result<Response, String> {
+    let json = serde_json::to_value(json).map_err(|e| e.to_string())?;
+    send(method, endpoint, account, &Payload::Json(json))
+}
+
+/// upload a base64 encoded JPEG to the given endpoint, e.g. a playlist's cover image.
+pub fn do_api_jpeg(
+    endpoint: &str,
+    account: &mut Account,
+    jpeg_base64: &str,
+) -> Result<Response, String> {
+    send("PUT", endpoint, account, &Payload::Jpeg(jpeg_base64))
+}
+
+fn send(
+    method: &str,
+    endpoint: &str,
+    account: &mut Account,
+    payload: &Payload,
 ) -> Result<Response, String> {
     let req = ureq::request(
         method,
         format!("https://api.spotify.com/v1/{}", endpoint).as_str(),
     )
     .add_auth(account);
-    let response = match method {
-        "GET" => req?.call(),
-        "POST" => req?.send_json(&json),
-        "PUT" => req?.send_json(&json),
-        "DELETE" => req?.send_json(&json),
+    let response = match (method, payload) {
+        ("GET", _) => req?.call(),
+        (_, Payload::Jpeg(data)) => req?.set("Content-Type", "image/jpeg").send_string(data),
+        ("POST", Payload::Json(json)) => req?.send_json(json),
+        ("PUT", Payload::Json(json)) => req?.send_json(json),
+        ("DELETE", Payload::Json(json)) => req?.send_json(json),
         _ => panic!("Invalid method"),
     };
     return match response {
@@ -42,7 +71,7 @@ pub fn do_api(
             Error::Status(code, response) => {
                 return match code {
                         401 => {
-                            do_api(method, endpoint, account.refresh().expect("Failed to refresh access token"), json)
+                            send(method, endpoint, account.refresh().expect("Failed to refresh access token"), payload)
                         }
                         403 => {
                             Err(format!("User account {}'s OAuth is invalid. Please try re-adding this account, then try again. Response {}", account.get_id()?, response.into_string().expect("Failed to unwrap broken 403 response")))
@@ -54,7 +83,7 @@ pub fn do_api(
                             };
                             warn!("Spotify API rate limit exceeded, retrying in {} seconds", retry_after);
                             std::thread::sleep(std::time::Duration::from_secs(retry_after));
-                            do_api(method, endpoint, account, json)
+                            send(method, endpoint, account, payload)
                         }
                         400..=499 => Err(format!("Client error: {} (code {})", &response.into_string().expect("Too many things went wrong during API request: failed to parse a 400 series error code response"), code)),
                         500..=599 => Err(format!("Server error: {} (code {})", &response.into_string().expect("Too many things went wrong during API request: failed to parse a 500 series error code response"), code)),
@@ -88,7 +117,7 @@ pub fn spotify_api_search<T: Content>(
         ContentType::Playlists => {
             let json = do_api_json(
                 "GET",
-                format!("search?q={}&type=playlist", query).as_str(),
+                format!("search?q={}&type=playlist", url_encode(query)).as_str(),
                 account,
                 "",
             )?;
@@ -109,7 +138,7 @@ pub fn spotify_api_search<T: Content>(
         _ => T::from_json_array(
             &do_api_json(
                 "GET",
-                format!("search?q={}&type={}", query, t.to_str()).as_str(),
+                format!("search?q={}&type={}", url_encode(query), t.to_str()).as_str(),
                 account,
                 "",
             )?[t.to_str_plural()]["items"],
@@ -120,9 +149,8 @@ pub fn spotify_api_search<T: Content>(
 pub fn get_playlists_for(acc: &mut Account) -> Result<Vec<Playlist>, String> {
     info!("Getting playlists. This may take a while, as we need to fetch all the tracks.");
     let mut playlists = Vec::new();
-    let _ = do_api_json("GET", "me/playlists?limit=50", acc, "")?["items"]
-        .as_array()
-        .ok_or("Failed to get playlists")?
+    let page = do_api_json("GET", "me/playlists?limit=50", acc, "")?;
+    let _ = get_all_pages(acc, &page)?
         .iter()
         .try_for_each(|p| -> Result<(), String> {
             playlists.push(Playlist::from_id(
@@ -137,7 +165,7 @@ pub fn get_playlists_for(acc: &mut Account) -> Result<Vec<Playlist>, String> {
 pub fn get_liked_songs(acc: &mut Account) -> Result<Playlist, String> {
     let mut tracks = Vec::new();
     let mut offset = 0;
-    let json = do_api_json("GET", "me/tracks?limit=50", acc, "")?;
+    let json = do_api_json("GET", "me/tracks?limit=50&market=from_token", acc, "")?;
     let total = json["total"].as_u64().ok_or("Failed to get total")?;
     info!("Getting {} liked songs. This may take a while.", total);
     let mut t = PlaylistTrack::from_json_array(&json["items"])?;
@@ -146,7 +174,7 @@ pub fn get_liked_songs(acc: &mut Account) -> Result<Playlist, String> {
     while offset < total as usize {
         let json = do_api_json(
             "GET",
-            format!("me/tracks?limit=50&offset={}", offset).as_str(),
+            format!("me/tracks?limit=50&market=from_token&offset={}", offset).as_str(),
             acc,
             "",
         )?;
@@ -163,5 +191,162 @@ pub fn get_liked_songs(acc: &mut Account) -> Result<Playlist, String> {
         uri: SpotifyURI {
             uri: "".to_string(),
         },
+        snapshot_id: "".to_string(),
+        images: Vec::new(),
+        owner: None,
     })
 }
+
+/// get the albums saved in the library of the given account, most recently saved first.
+pub fn get_saved_albums(acc: &mut Account) -> Result<Vec<Album>, String> {
+    let page = do_api_json("GET", "me/albums?limit=50", acc, "")?;
+    info!(
+        "Getting {} saved albums. This may take a while.",
+        page["total"].as_u64().unwrap_or(0)
+    );
+    let mut albums = Vec::new();
+    for item in get_all_pages(acc, &page)? {
+        let mut album = item["album"].clone();
+        if album["tracks"]["next"].is_string() {
+            album["tracks"]["items"] = Value::Array(get_all_pages(acc, &album["tracks"])?);
+        }
+        albums.push(Album::from_json(&album)?);
+    }
+    Ok(albums)
+}
+
+/// get the podcast shows saved in the library of the given account, most recently saved first.
+pub fn get_saved_shows(acc: &mut Account) -> Result<Vec<Show>, String> {
+    let page = do_api_json("GET", "me/shows?limit=50", acc, "")?;
+    get_all_pages(acc, &page)?
+        .iter()
+        .map(|item| Show::from_json(&item["show"]))
+        .collect()
+}
+
+/// get the artists followed by the given account. This endpoint pages with cursors rather than
+/// offsets, and wraps every page in an `artists` object.
+pub fn get_followed_artists(acc: &mut Account) -> Result<Vec<Artist>, String> {
+    let mut artists = Vec::new();
+    let mut endpoint = Some("me/following?type=artist&limit=50".to_string());
+    while let Some(url) = endpoint {
+        let json = do_api_json("GET", url.as_str(), acc, "")?;
+        artists.append(&mut Artist::from_json_array(&json["artists"]["items"])?);
+        endpoint = json["artists"]["next"].as_str().map(|s| {
+            s.trim_start_matches("https://api.spotify.com/v1/")
+                .to_string()
+        });
+    }
+    Ok(artists)
+}
+
+/// check which of the given IDs are in the account's library, using one of the `contains`
+/// endpoints, e.g. `me/tracks/contains?ids=`. IDs are sent in chunks of the given size.
+pub fn library_contains(
+    acc: &mut Account,
+    endpoint: &str,
+    ids: &[&str],
+    chunk: usize,
+) -> Result<Vec<bool>, String> {
+    let mut contains = Vec::new();
+    for ids in ids.chunks(chunk) {
+        let json = do_api_json(
+            "GET",
+            format!("{}{}", endpoint, ids.join(",")).as_str(),
+            acc,
+            "",
+        )?;
+        for value in json.as_array().ok_or("Failed to check library")? {
+            contains.push(value.as_bool().unwrap_or(false));
+        }
+    }
+    Ok(contains)
+}
+
+/// filter the given items down to the ones not already in the account's library.
+/// see <code>[library_contains]</code> for the endpoint and chunk size.
+pub fn not_in_library<'a, T>(
+    acc: &mut Account,
+    items: &'a [T],
+    id: impl Fn(&T) -> &str,
+    endpoint: &str,
+    chunk: usize,
+) -> Result<Vec<&'a T>, String> {
+    let ids = items.iter().map(&id).collect::<Vec<&str>>();
+    let contains = library_contains(acc, endpoint, &ids, chunk)?;
+    Ok(items
+        .iter()
+        .zip(contains)
+        .filter(|(_, contains)| !contains)
+        .map(|(item, _)| item)
+        .collect())
+}
+
+/// save the given IDs to the account's library in the order given, using an endpoint that takes
+/// them as a query parameter, e.g. `me/albums?ids=`.
+pub fn save_to_library(
+    acc: &mut Account,
+    endpoint: &str,
+    ids: &[&str],
+    chunk: usize,
+) -> Result<(), String> {
+    for (i, request) in ids.chunks(chunk).enumerate() {
+        info!("Saving to library... ({}/{})", i * chunk, ids.len());
+        do_api(
+            "PUT",
+            format!("{}{}", endpoint, request.join(",")).as_str(),
+            acc,
+            "",
+        )?;
+    }
+    Ok(())
+}
+
+/// like the given tracks, keeping their original `added_at` so liked songs stay in order.
+pub fn save_liked_songs(acc: &mut Account, tracks: &[&PlaylistTrack]) -> Result<(), String> {
+    let mut tracks = tracks.to_vec();
+    tracks.sort_by_key(|t| t.added_at);
+    for (i, chunk) in tracks.chunks(50).enumerate() {
+        info!("Saving liked songs... ({}/{})", i * 50, tracks.len());
+        do_api(
+            "PUT",
+            "me/tracks",
+            acc,
+            json!({
+                "timestamped_ids": chunk
+                    .iter()
+                    .map(|t| json!({
+                        "id": t.track.uri.get_id(),
+                        "added_at": epoch_time_to_rfc3339(t.added_at),
+                    }))
+                    .collect::<Vec<_>>(),
+            }),
+        )?;
+    }
+    Ok(())
+}
+
+/// collect the items of a paging object, following its `next` links until the end.
+pub fn get_all_pages(acc: &mut Account, page: &Value) -> Result<Vec<Value>, String> {
+    let mut items = page["items"]
+        .as_array()
+        .ok_or(format!("Expected a paging object: data = {}", page))?
+        .clone();
+    let mut next = page["next"].as_str().map(|s| s.to_string());
+    while let Some(url) = next {
+        let json = do_api_json(
+            "GET",
+            url.trim_start_matches("https://api.spotify.com/v1/"),
+            acc,
+            "",
+        )?;
+        items.append(
+            &mut json["items"]
+                .as_array()
+                .ok_or("Failed to get next page")?
+                .clone(),
+        );
+        next = json["next"].as_str().map(|s| s.to_string());
+    }
+    Ok(items)
+}
@@ -0,0 +1,282 @@
+use std::fs::File;
+use std::io::{Read, Write};
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::account::Account;
+use crate::api::{
+    do_api_json, get_all_pages, get_followed_artists, get_liked_songs, get_saved_albums,
+    not_in_library, save_liked_songs, save_to_library,
+};
+use crate::data::{upgrade_legacy_durations, Album, Artist, Content, Playlist};
+use crate::utils::epoch_time_to_rfc3339;
+use crate::{info, user_yn, warn};
+
+/// describes the contents of a backup directory. Everything else in the directory is referenced
+/// from here, relative to the directory.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Manifest {
+    pub account: String,
+    pub user_id: String,
+    /// unix time the backup was started at.
+    pub created_at: u64,
+    pub profile: String,
+    pub playlists: Vec<PlaylistEntry>,
+    pub liked_songs: String,
+    pub albums: String,
+    pub artists: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PlaylistEntry {
+    pub name: String,
+    pub uri: String,
+    pub snapshot_id: String,
+    /// whether the account owns this playlist, or only follows it.
+    pub owned: bool,
+    pub tracks: usize,
+    /// unix time this playlist was saved at.
+    pub saved_at: u64,
+    pub file: String,
+}
+
+impl Manifest {
+    pub fn from_file(path: &Path) -> Result<Manifest, String> {
+        read_json(path)
+    }
+}
+
+pub(crate) fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs()
+}
+
+pub(crate) fn write_json(path: &Path, value: &impl Serialize) -> Result<(), String> {
+    let mut file = File::create(path).map_err(|e| format!("failed to create {:?}: {}", path, e))?;
+    file.write_all(
+        serde_json::to_string_pretty(value)
+            .map_err(|e| e.to_string())?
+            .as_bytes(),
+    )
+    .map_err(|e| format!("failed to write {:?}: {}", path, e))
+}
+
+pub(crate) fn read_json<T: for<'de> Deserialize<'de>>(path: &Path) -> Result<T, String> {
+    let mut contents = String::new();
+    File::open(path)
+        .map_err(|e| format!("failed to open {:?}: {}", path, e))?
+        .read_to_string(&mut contents)
+        .map_err(|e| format!("failed to read {:?}: {}", path, e))?;
+    let mut json: Value = serde_json::from_str(&contents)
+        .map_err(|e| format!("failed to parse {:?}: {}", path, e))?;
+    upgrade_legacy_durations(&mut json);
+    serde_json::from_value(json).map_err(|e| format!("failed to parse {:?}: {}", path, e))
+}
+
+/// back up everything in the given account's library to the given directory: the profile, every
+/// owned and followed playlist, liked songs, saved albums and followed artists.
+pub fn backup(acc: &mut Account, account_name: &str, dir: &Path) -> Result<Manifest, String> {
+    std::fs::create_dir_all(dir.join("playlists"))
+        .map_err(|e| format!("failed to create backup directory {:?}: {}", dir, e))?;
+    let created_at = now();
+    let profile = do_api_json("GET", "me", acc, "")?;
+    write_json(&dir.join("profile.json"), &profile)?;
+    let user_id = acc.get_id()?.to_string();
+
+    let page = do_api_json("GET", "me/playlists?limit=50", acc, "")?;
+    let items = get_all_pages(acc, &page)?;
+    let mut playlists = Vec::new();
+    for (i, item) in items.iter().enumerate() {
+        let id = item["id"].as_str().ok_or("no ID field")?;
+        info!(
+            "Backing up playlist {}... ({}/{})",
+            item["name"].as_str().unwrap_or(id),
+            i + 1,
+            items.len()
+        );
+        let playlist = match Playlist::from_id(id, acc) {
+            Ok(p) => p,
+            Err(e) => {
+                warn!("failed to back up playlist {}: {}", id, e);
+                continue;
+            }
+        };
+        let file = format!("playlists/{}.json", id);
+        playlist.to_file(&dir.join(&file))?;
+        playlists.push(PlaylistEntry {
+            name: playlist.name.clone(),
+            uri: playlist.uri.uri.clone(),
+            snapshot_id: playlist.snapshot_id.clone(),
+            owned: item["owner"]["id"].as_str() == Some(user_id.as_str()),
+            tracks: playlist.tracks.len(),
+            saved_at: now(),
+            file,
+        });
+    }
+
+    info!("Backing up liked songs...");
+    get_liked_songs(acc)?.to_file(&dir.join("liked_songs.json"))?;
+    info!("Backing up saved albums...");
+    let albums: Vec<Album> = get_saved_albums(acc)?;
+    write_json(&dir.join("albums.json"), &albums)?;
+    info!("Backing up followed artists...");
+    let artists: Vec<Artist> = get_followed_artists(acc)?;
+    write_json(&dir.join("artists.json"), &artists)?;
+
+    let manifest = Manifest {
+        account: account_name.to_string(),
+        user_id,
+        created_at,
+        profile: "profile.json".to_string(),
+        playlists,
+        liked_songs: "liked_songs.json".to_string(),
+        albums: "albums.json".to_string(),
+        artists: "artists.json".to_string(),
+    };
+    write_json(&dir.join("manifest.json"), &manifest)?;
+    info!(
+        "Backed up {} playlists, {} albums and {} artists to {:?}",
+        manifest.playlists.len(),
+        albums.len(),
+        artists.len(),
+        dir
+    );
+    Ok(manifest)
+}
+
+/// whether the user owns a playlist with the given name, among the playlists from `me/playlists`.
+fn owns_playlist(existing: &[Value], name: &str, user_id: &str) -> bool {
+    existing
+        .iter()
+        .any(|p| p["name"].as_str() == Some(name) && p["owner"]["id"].as_str() == Some(user_id))
+}
+
+/// restore a backup onto the given account. The path is either a backup directory made by
+/// <code>[backup]</code>, or a single playlist file. Anything that already exists on the account is
+/// skipped, so restoring the same backup twice is harmless.
+pub fn restore(acc: &mut Account, path: &Path) -> Result<(), String> {
+    let user_id = acc.get_id()?.to_string();
+    let page = do_api_json("GET", "me/playlists?limit=50", acc, "")?;
+    let existing = get_all_pages(acc, &page)?;
+    if path.is_file() {
+        let playlist = Playlist::from_file(path)?;
+        if owns_playlist(&existing, &playlist.name, &user_id) {
+            info!("Skipping playlist {}, it already exists", playlist.name);
+            return Ok(());
+        }
+        if !user_yn(
+            format!(
+                "Recreate playlist {} ({} tracks)?",
+                playlist.name,
+                playlist.tracks.len()
+            )
+            .as_str(),
+            true,
+        ) {
+            return Err("Aborted".to_string());
+        }
+        playlist.recreate(acc)?;
+        return Ok(());
+    }
+    let manifest = Manifest::from_file(&path.join("manifest.json"))?;
+    info!(
+        "Restoring backup of {} made at {}",
+        manifest.account,
+        epoch_time_to_rfc3339(manifest.created_at)
+    );
+    let mut to_create = Vec::new();
+    let mut to_follow = Vec::new();
+    for entry in &manifest.playlists {
+        if entry.owned {
+            if owns_playlist(&existing, &entry.name, &user_id) {
+                info!("Skipping playlist {}, it already exists", entry.name);
+            } else {
+                to_create.push(entry);
+            }
+        } else if existing
+            .iter()
+            .any(|p| p["uri"].as_str() == Some(entry.uri.as_str()))
+        {
+            info!("Skipping playlist {}, it is already followed", entry.name);
+        } else {
+            to_follow.push(entry);
+        }
+    }
+
+    let liked = Playlist::from_file(&path.join(&manifest.liked_songs))?;
+    let liked_tracks = not_in_library(
+        acc,
+        &liked.tracks,
+        |t| t.track.uri.get_id(),
+        "me/tracks/contains?ids=",
+        50,
+    )?;
+    let albums: Vec<Album> = read_json(&path.join(&manifest.albums))?;
+    let albums = not_in_library(
+        acc,
+        &albums,
+        |a| a.uri.get_id(),
+        "me/albums/contains?ids=",
+        20,
+    )?;
+    let artists: Vec<Artist> = read_json(&path.join(&manifest.artists))?;
+    let artists = not_in_library(
+        acc,
+        &artists,
+        |a| a.uri.get_id(),
+        "me/following/contains?type=artist&ids=",
+        50,
+    )?;
+
+    println!("This will restore:");
+    for entry in &to_create {
+        println!(
+            "  recreate playlist {} ({} tracks)",
+            entry.name, entry.tracks
+        );
+    }
+    for entry in &to_follow {
+        println!("  follow playlist {}", entry.name);
+    }
+    println!("  save {} liked songs", liked_tracks.len());
+    println!("  save {} albums", albums.len());
+    println!("  follow {} artists", artists.len());
+    if !user_yn("Continue?", true) {
+        return Err("Aborted".to_string());
+    }
+
+    for entry in to_create {
+        Playlist::from_file(&path.join(&entry.file))?.recreate(acc)?;
+    }
+    for entry in to_follow {
+        Playlist::from_file(&path.join(&entry.file))?.follow_online(acc)?;
+    }
+    save_liked_songs(acc, &liked_tracks)?;
+    // albums are backed up newest first, so save the oldest first to keep the order.
+    save_to_library(
+        acc,
+        "me/albums?ids=",
+        &albums
+            .iter()
+            .rev()
+            .map(|a| a.uri.get_id())
+            .collect::<Vec<&str>>(),
+        20,
+    )?;
+    save_to_library(
+        acc,
+        "me/following?type=artist&ids=",
+        &artists
+            .iter()
+            .map(|a| a.uri.get_id())
+            .collect::<Vec<&str>>(),
+        50,
+    )?;
+    info!("Restored backup");
+    Ok(())
+}
@@ -1,17 +1,23 @@
 use std::collections::HashMap;
 use std::fs::{File, OpenOptions};
 use std::io::{BufReader, Read, Write};
-use std::path::Path;
+use std::path::{Path, PathBuf};
 
 use serde::{Deserialize, Serialize};
 
 use crate::account::Account;
+use crate::smart::SmartPlaylist;
+use crate::snapshots::TrackedPlaylist;
 use crate::utils::Pair;
 use crate::{info, Spotimine};
 
 #[derive(Serialize, Deserialize)]
 pub struct Config {
     pub(crate) accounts: HashMap<String, Account>,
+    #[serde(default)]
+    pub(crate) smart_playlists: Vec<SmartPlaylist>,
+    #[serde(default)]
+    pub(crate) tracked_playlists: Vec<TrackedPlaylist>,
 }
 
 impl Config {
@@ -34,6 +40,8 @@ impl Config {
                 .map_err(|e| e.to_string())?;
             let config = Config {
                 accounts: HashMap::new(),
+                smart_playlists: Vec::new(),
+                tracked_playlists: Vec::new(),
             };
             config.save_to(&mut file)?;
             Ok(Pair { a: file, b: config })
@@ -92,17 +100,19 @@ impl Config {
 }
 
 pub(crate) fn load() -> Result<Spotimine, String> {
-    return match std::env::consts::OS {
-        "windows" => {
-            let path = format!("{}\\spotimine", std::env::var("APPDATA").unwrap());
-            std::fs::create_dir_all(&path).expect("Failed to create config directory");
-            Spotimine::new(format!("{}\\config.json", path))
-        }
-        "linux" | "android" => {
-            let path = format!("{}/.config/spotimine", std::env::var("HOME").unwrap());
-            std::fs::create_dir_all(&path).expect("Failed to create config directory");
-            Spotimine::new(format!("{}/config.json", path))
-        }
-        _ => Err(format!("{} is not supported.", std::env::consts::OS)),
+    let dir = config_dir()?;
+    Spotimine::new(dir.join("config.json").to_string_lossy().to_string())
+}
+
+/// the directory spotimine keeps its config and other local data in, creating it if needed.
+pub(crate) fn config_dir() -> Result<PathBuf, String> {
+    let path = match std::env::consts::OS {
+        "windows" => Path::new(&std::env::var("APPDATA").unwrap()).join("spotimine"),
+        "linux" | "android" => Path::new(&std::env::var("HOME").unwrap())
+            .join(".config")
+            .join("spotimine"),
+        _ => return Err(format!("{} is not supported.", std::env::consts::OS)),
     };
+    std::fs::create_dir_all(&path).expect("Failed to create config directory");
+    Ok(path)
 }
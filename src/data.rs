@@ -1,4 +1,6 @@
 use crossterm::style::Stylize;
+use std::cmp::Reverse;
+use std::collections::HashMap;
 use std::fmt::{Display, Formatter};
 use std::fs::File;
 use std::io::{Read, Write};
@@ -9,8 +11,14 @@ use serde::{Deserialize, Serialize};
 use serde_json::{json, Value};
 
 use crate::account::Account;
-use crate::api::{do_api, do_api_json, get_liked_songs};
-use crate::utils::{format_duration, rfc3339_to_epoch_time, strip_html_tags};
+use crate::api::{
+    do_api, do_api_jpeg, do_api_json, get_all_pages, get_liked_songs, spotify_api_search,
+};
+use crate::sort::plan_moves;
+use crate::utils::{
+    encode_cover, fetch_image, format_duration, from_legacy_epoch_time, rfc3339_to_epoch_time,
+    strip_html_tags,
+};
 use crate::{info, user_yn, warn};
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -21,25 +29,99 @@ pub struct Playlist {
     pub followers: u32,
     pub tracks: Vec<PlaylistTrack>,
     pub uri: SpotifyURI,
+    #[serde(default)]
+    pub snapshot_id: String,
+    /// URLs of the cover images, largest first.
+    #[serde(default)]
+    pub images: Vec<String>,
+    /// the user who owns this playlist. Unknown for liked songs and playlists not yet created.
+    #[serde(default)]
+    pub owner: Option<User>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct PlaylistTrack {
     pub track: Track,
     pub added_at: u64,
+    /// the user who added this track. Unknown for liked songs and very old playlists.
+    #[serde(default)]
+    pub added_by: Option<User>,
+}
+
+/// how much one user has added to a playlist.
+#[derive(Debug, Clone)]
+pub struct Contributor {
+    /// `None` for the tracks whose adder is unknown.
+    pub user: Option<User>,
+    pub tracks: usize,
+    pub first_added: u64,
+    pub last_added: u64,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Track {
     pub name: String,
     pub artists: Vec<Artist>,
-    pub duration: u32,
+    #[serde(default)]
+    pub duration_ms: u32,
     pub explicit: bool,
     pub uri: SpotifyURI,
+    #[serde(default)]
+    pub popularity: u8,
+    #[serde(default)]
+    pub release_date: String,
+    /// how much of the release date is known: "year", "month" or "day".
+    #[serde(default)]
+    pub release_date_precision: String,
+    #[serde(default)]
+    pub album: String,
+    #[serde(default)]
+    pub album_uri: Option<SpotifyURI>,
+    #[serde(default)]
+    pub track_number: u32,
+    #[serde(default)]
+    pub disc_number: u32,
+    /// identifiers such as "isrc", "ean" and "upc".
+    #[serde(default)]
+    pub external_ids: HashMap<String, String>,
+    /// local files are added to playlists from the desktop app, and only exist on that device.
+    #[serde(default)]
+    pub is_local: bool,
+    /// whether the track can be played in the account's region.
+    #[serde(default = "playable_default")]
+    pub is_playable: bool,
+    /// if Spotify relinked this track to another copy that is playable in the account's region,
+    /// the URI of the track that was originally added.
+    #[serde(default)]
+    pub linked_from: Option<SpotifyURI>,
+}
+
+fn playable_default() -> bool {
+    true
+}
+
+/// tracks saved before millisecond durations were kept have their length in seconds, as
+/// `duration`. Give every such track in the JSON a `duration_ms`, so that old playlists, backups
+/// and checkpoints still know how long their tracks are.
+pub(crate) fn upgrade_legacy_durations(value: &mut Value) {
+    match value {
+        Value::Array(items) => items.iter_mut().for_each(upgrade_legacy_durations),
+        Value::Object(object) => {
+            // episodes have a duration in seconds too, but no artists
+            if object.contains_key("artists") && !object.contains_key("duration_ms") {
+                if let Some(seconds) = object.get("duration").and_then(|d| d.as_u64()) {
+                    object.insert("duration_ms".to_string(), json!(seconds * 1000));
+                }
+            }
+            object.values_mut().for_each(upgrade_legacy_durations);
+        }
+        _ => (),
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct User {
+    #[serde(default)]
     pub followers: u32,
     pub uri: SpotifyURI,
     pub name: String,
@@ -51,12 +133,48 @@ pub struct Album {
     pub artists: Vec<Artist>,
     pub tracks: Vec<Track>,
     pub uri: SpotifyURI,
+    #[serde(default)]
+    pub release_date: String,
+    #[serde(default)]
+    pub total_tracks: u32,
+    #[serde(default)]
+    pub label: String,
+    /// URLs of the cover images, largest first.
+    #[serde(default)]
+    pub images: Vec<String>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Artist {
     pub name: String,
     pub uri: SpotifyURI,
+    #[serde(default)]
+    pub genres: Vec<String>,
+    #[serde(default)]
+    pub popularity: u8,
+    #[serde(default)]
+    pub followers: u32,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Show {
+    pub name: String,
+    pub publisher: String,
+    pub description: String,
+    pub total_episodes: u32,
+    pub explicit: bool,
+    pub uri: SpotifyURI,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Episode {
+    pub name: String,
+    /// the name of the show this is an episode of.
+    pub show: String,
+    pub duration: u32,
+    pub explicit: bool,
+    pub release_date: String,
+    pub uri: SpotifyURI,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -64,15 +182,175 @@ pub struct SpotifyURI {
     pub uri: String,
 }
 
+impl Track {
+    /// the length of the track in whole seconds.
+    pub fn duration(&self) -> u32 {
+        self.duration_ms / 1000
+    }
+
+    /// the International Standard Recording Code, which identifies a recording across every
+    /// release it appears on.
+    pub fn isrc(&self) -> Option<&str> {
+        self.external_ids.get("isrc").map(|s| s.as_str())
+    }
+
+    /// whether this is a podcast episode rather than a song. Episodes can be in playlists, but
+    /// not in liked songs.
+    pub fn is_episode(&self) -> bool {
+        self.uri.get_type() == Some(ContentType::Episodes)
+    }
+
+    /// why this track can't be copied to another playlist as it is, if it can't.
+    pub fn copy_problem(&self) -> Option<&'static str> {
+        if self.is_local {
+            Some("it is a local file, which only exists on the device it was added from")
+        } else if !self.is_playable {
+            Some("it is unavailable in this account's region")
+        } else {
+            None
+        }
+    }
+
+    /// search for a playable copy of this track: one with the same ISRC, or else one with the same
+    /// name and at least one of the same artists, e.g. to replace a local file or a track that was
+    /// taken down.
+    pub fn find_replacement(&self, user: &mut Account) -> Result<Option<Track>, String> {
+        // the same recording released elsewhere is the best replacement, if there is one
+        if let Some(isrc) = self.isrc() {
+            let results = spotify_api_search::<Track>(
+                format!("isrc:{}", isrc).as_str(),
+                &ContentType::Tracks,
+                user,
+            )?;
+            if let Some(track) = results.into_iter().find(|t| t.copy_problem().is_none()) {
+                return Ok(Some(track));
+            }
+        }
+        let artist = self
+            .artists
+            .first()
+            .map(|a| a.name.as_str())
+            .unwrap_or_default();
+        let results = spotify_api_search::<Track>(
+            format!("{} {}", self.name, artist).trim(),
+            &ContentType::Tracks,
+            user,
+        )?;
+        Ok(results.into_iter().find(|t| {
+            t.copy_problem().is_none()
+                && t.name.to_lowercase() == self.name.to_lowercase()
+                && t.artists.iter().any(|a| {
+                    self.artists
+                        .iter()
+                        .any(|b| a.name.to_lowercase() == b.name.to_lowercase())
+                })
+        }))
+    }
+}
+
+impl User {
+    pub fn get_id(&self) -> &str {
+        self.uri.get_id()
+    }
+}
+
+/// whether the given string looks like a Spotify ID: 22 base62 characters.
+pub(crate) fn is_spotify_id(id: &str) -> bool {
+    id.len() == 22 && id.chars().all(|c| c.is_ascii_alphanumeric())
+}
+
 impl SpotifyURI {
+    /// wrap a URI that came from the API. Use <code>[SpotifyURI::parse]</code> for anything a user
+    /// typed or pasted.
     pub fn from_str(uri: String) -> SpotifyURI {
         SpotifyURI { uri }
     }
+
+    /// parse a URI or a share link, e.g. `spotify:track:ID`,
+    /// `https://open.spotify.com/track/ID?si=...`, `spotify:user:NAME:playlist:ID` or a local
+    /// file's `spotify:local:...` URI. The result is always a plain `spotify:<type>:<id>` URI.
+    pub fn parse(s: &str) -> Result<SpotifyURI, String> {
+        let s = s.trim();
+        let invalid = || format!("Invalid Spotify URI or link: {}", s);
+        if s.starts_with("spotify:local:") {
+            return Ok(SpotifyURI::from_str(s.to_string()));
+        }
+        let parts = match s.strip_prefix("spotify:") {
+            Some(rest) => rest.split(':').collect::<Vec<&str>>(),
+            None => {
+                let path = ["https://", "http://", ""]
+                    .iter()
+                    .find_map(|scheme| s.strip_prefix(scheme)?.strip_prefix("open.spotify.com/"))
+                    .ok_or_else(invalid)?;
+                let path = path.split(['?', '#']).next().unwrap_or_default();
+                let mut parts = path
+                    .split('/')
+                    .filter(|p| !p.is_empty())
+                    .collect::<Vec<&str>>();
+                // links from localised pages look like open.spotify.com/intl-de/track/ID
+                if parts.first().is_some_and(|p| p.starts_with("intl-")) {
+                    parts.remove(0);
+                }
+                parts
+            }
+        };
+        let (kind, id) = match parts.as_slice() {
+            // older playlist URIs include the owner
+            ["user", _, "playlist", id] => ("playlist", *id),
+            ["user", id] if !id.is_empty() => {
+                return Ok(SpotifyURI::from_str(format!("spotify:user:{}", id)))
+            }
+            [kind, id] => (*kind, *id),
+            _ => return Err(invalid()),
+        };
+        match ContentType::from_str(kind) {
+            Some(t) if t.to_str() == kind && is_spotify_id(id) => {
+                Ok(SpotifyURI::from_str(format!("spotify:{}:{}", kind, id)))
+            }
+            _ => Err(invalid()),
+        }
+    }
+
+    /// like <code>[SpotifyURI::parse]</code>, but also accepts a bare ID, and checks that the
+    /// result is of the expected type.
+    pub fn parse_as(s: &str, expected: ContentType) -> Result<SpotifyURI, String> {
+        let s = s.trim();
+        if is_spotify_id(s) {
+            return Ok(SpotifyURI::from_str(format!(
+                "spotify:{}:{}",
+                expected.to_str(),
+                s
+            )));
+        }
+        let uri = SpotifyURI::parse(s)?;
+        if uri.get_type().as_ref() != Some(&expected) {
+            return Err(format!(
+                "Expected a link of type {}, got {}",
+                expected.to_str(),
+                s
+            ));
+        }
+        Ok(uri)
+    }
+
     pub fn get_id(&self) -> &str {
         self.uri.split(':').last().unwrap()
     }
-    pub fn get_type(&self) -> ContentType {
-        ContentType::from_str(self.uri.split(':').nth(1).unwrap()).expect("Invalid URI")
+
+    /// the open.spotify.com link to this. Local files have none.
+    pub fn to_url(&self) -> Option<String> {
+        match self.uri.split(':').collect::<Vec<&str>>().as_slice() {
+            ["spotify", kind, id] if *kind != "local" => {
+                Some(format!("https://open.spotify.com/{}/{}", kind, id))
+            }
+            _ => None,
+        }
+    }
+
+    /// the type of content this points to. Local files and users have none.
+    pub fn get_type(&self) -> Option<ContentType> {
+        let kind = self.uri.split(':').nth(1)?;
+        ContentType::from_str(kind).filter(|t| t.to_str() == kind)
     }
 }
 
@@ -99,9 +377,27 @@ impl Visibility {
     pub fn is_collaborative(&self) -> bool {
         matches!(self, Visibility::Collaborative)
     }
+    pub fn from_str(s: &str) -> Option<Visibility> {
+        match s.to_lowercase().as_str() {
+            "public" => Some(Visibility::Public),
+            "private" => Some(Visibility::Private),
+            "collaborative" | "collab" => Some(Visibility::Collaborative),
+            _ => None,
+        }
+    }
 }
 
-trait Stringify {
+impl Display for Visibility {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Visibility::Public => "public",
+            Visibility::Private => "private",
+            Visibility::Collaborative => "collaborative",
+        })
+    }
+}
+
+pub(crate) trait Stringify {
     fn stringify(&self) -> String;
 }
 
@@ -114,12 +410,14 @@ impl Stringify for Vec<Artist> {
     }
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub enum ContentType {
     Tracks,
     Artists,
     Albums,
     Playlists,
+    Shows,
+    Episodes,
 }
 
 impl ContentType {
@@ -129,6 +427,8 @@ impl ContentType {
             "artist" | "singer" | "artists" | "singers" => Some(ContentType::Artists),
             "album" | "albums" => Some(ContentType::Albums),
             "playlist" | "list" | "playlists" => Some(ContentType::Playlists),
+            "show" | "podcast" | "shows" | "podcasts" => Some(ContentType::Shows),
+            "episode" | "episodes" => Some(ContentType::Episodes),
             _ => None,
         }
     }
@@ -138,6 +438,8 @@ impl ContentType {
             ContentType::Artists => "artists",
             ContentType::Albums => "albums",
             ContentType::Playlists => "playlists",
+            ContentType::Shows => "shows",
+            ContentType::Episodes => "episodes",
         }
     }
     pub fn to_str(&self) -> &str {
@@ -146,6 +448,8 @@ impl ContentType {
             ContentType::Artists => "artist",
             ContentType::Albums => "album",
             ContentType::Playlists => "playlist",
+            ContentType::Shows => "show",
+            ContentType::Episodes => "episode",
         }
     }
 }
@@ -173,20 +477,24 @@ pub trait Content: Sized {
             "",
         )?)
     }
-    /// creates an array of this from the given spotify id.
+    /// creates an array of this from the given spotify ids. IDs that don't exist are left out.
     fn from_ids(ids: &[&str], user: &mut Account) -> Result<Vec<Self>, String> {
         let mut vec = Vec::new();
-        let mut vec_ids: Vec<&str> = Vec::new();
-        for id in ids {
-            vec_ids.push(id);
-            if vec_ids.len() == 50 {
-                vec.append(&mut Self::from_json_array(&do_api_json(
-                    "GET",
-                    format!("{}s/?ids={}", Self::type_string(), vec_ids.join(",")).as_str(),
-                    user,
-                    "",
-                )?)?);
-                vec_ids.clear();
+        // 20 is the most the albums endpoint takes; the others take 50
+        for chunk in ids.chunks(20) {
+            let json = do_api_json(
+                "GET",
+                format!("{}s?ids={}", Self::type_string(), chunk.join(",")).as_str(),
+                user,
+                "",
+            )?;
+            for item in json[format!("{}s", Self::type_string())]
+                .as_array()
+                .ok_or(format!("json was not an array: {}", json))?
+            {
+                if !item.is_null() {
+                    vec.push(Self::from_json(item)?);
+                }
             }
         }
         Ok(vec)
@@ -208,23 +516,76 @@ impl Display for Track {
             "{} by {} ({})",
             self.name.as_str().blue().bold(),
             self.artists.stringify().blue(),
-            format_duration(self.duration)
+            format_duration(self.duration())
         ))
     }
 }
 
 impl Display for Artist {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-        f.write_str(&format!("{}", self.name.as_str().blue().bold()))
+        // artists inside tracks and albums are simplified, and have none of the details
+        if self.followers == 0 && self.genres.is_empty() {
+            return f.write_str(&format!("{}", self.name.as_str().blue().bold()));
+        }
+        f.write_str(&format!(
+            "{}{} ({} followers, popularity {})",
+            self.name.as_str().blue().bold(),
+            if self.genres.is_empty() {
+                String::new()
+            } else {
+                format!(" - {}", self.genres.join(", ")).blue().to_string()
+            },
+            self.followers,
+            self.popularity
+        ))
+    }
+}
+
+impl Display for User {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        // users inside playlists are simplified, and have no follower count
+        if self.followers == 0 {
+            return f.write_str(&format!("{}", self.name.as_str().blue().bold()));
+        }
+        f.write_str(&format!(
+            "{} ({} followers)",
+            self.name.as_str().blue().bold(),
+            self.followers
+        ))
     }
 }
 
 impl Display for Album {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
         f.write_str(&format!(
-            "{} by {}",
+            "{} by {} ({}, {} tracks)",
             self.name.as_str().blue().bold(),
-            self.artists.stringify().blue()
+            self.artists.stringify().blue(),
+            self.release_date.get(..4).unwrap_or("unknown year"),
+            self.total_tracks
+        ))
+    }
+}
+
+impl Display for Show {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&format!(
+            "{} by {} ({} episodes)",
+            self.name.as_str().blue().bold(),
+            self.publisher.as_str().blue(),
+            self.total_episodes
+        ))
+    }
+}
+
+impl Display for Episode {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&format!(
+            "{} from {} ({}, {})",
+            self.name.as_str().blue().bold(),
+            self.show.as_str().blue(),
+            self.release_date,
+            format_duration(self.duration)
         ))
     }
 }
@@ -248,8 +609,54 @@ impl Display for Playlist {
     }
 }
 
+/// the URLs from an array of image objects.
+fn image_urls(json: &Value) -> Vec<String> {
+    json.as_array()
+        .map(|images| {
+            images
+                .iter()
+                .filter_map(|i| i["url"].as_str().map(|s| s.to_string()))
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
 impl Content for Track {
+    /// podcast episodes in playlists are read as tracks too, with their show as the artist and
+    /// album, so that playlists containing them keep every entry.
     fn from_json(json: &Value) -> Result<Self, String> {
+        if json["type"].as_str() == Some("episode") && json["show"].is_object() {
+            let episode = Episode::from_json(json)?;
+            return Ok(Track {
+                name: episode.name,
+                artists: vec![Artist {
+                    name: episode.show.clone(),
+                    uri: SpotifyURI::from_str(
+                        json["show"]["uri"].as_str().unwrap_or_default().to_string(),
+                    ),
+                    genres: Vec::new(),
+                    popularity: 0,
+                    followers: 0,
+                }],
+                duration_ms: json["duration_ms"].as_u64().unwrap_or(0) as u32,
+                explicit: episode.explicit,
+                uri: episode.uri,
+                popularity: 0,
+                release_date: episode.release_date,
+                release_date_precision: json["release_date_precision"]
+                    .as_str()
+                    .unwrap_or_default()
+                    .to_string(),
+                album: episode.show,
+                album_uri: None,
+                track_number: 0,
+                disc_number: 0,
+                external_ids: HashMap::new(),
+                is_local: false,
+                is_playable: json["is_playable"].as_bool().unwrap_or(true),
+                linked_from: None,
+            });
+        }
         let mut artists = Vec::new();
         for artist in json["artists"].as_array().ok_or(format!(
             "Expected array deserializing artists for track: data = {}",
@@ -257,12 +664,48 @@ impl Content for Track {
         ))? {
             artists.push(Artist::from_json(artist)?);
         }
+        let uri = json["uri"]
+            .as_str()
+            .ok_or(format!("missing URI field for track: data = {}", json))?
+            .to_string();
         Ok(Track {
-            name: json["name"].as_str().unwrap().to_string(),
+            name: json["name"].as_str().unwrap_or_default().to_string(),
             artists,
-            duration: (json["duration_ms"].as_u64().unwrap() / 1000) as u32,
-            explicit: json["explicit"].as_bool().unwrap(),
-            uri: SpotifyURI::from_str(json["uri"].as_str().unwrap().to_string()),
+            duration_ms: json["duration_ms"].as_u64().unwrap_or(0) as u32,
+            explicit: json["explicit"].as_bool().unwrap_or(false),
+            is_local: json["is_local"].as_bool().unwrap_or(false)
+                || uri.starts_with("spotify:local:"),
+            uri: SpotifyURI::from_str(uri),
+            popularity: json["popularity"].as_u64().unwrap_or(0) as u8,
+            release_date: json["album"]["release_date"]
+                .as_str()
+                .unwrap_or_default()
+                .to_string(),
+            release_date_precision: json["album"]["release_date_precision"]
+                .as_str()
+                .unwrap_or_default()
+                .to_string(),
+            album: json["album"]["name"]
+                .as_str()
+                .unwrap_or_default()
+                .to_string(),
+            album_uri: json["album"]["uri"]
+                .as_str()
+                .map(|uri| SpotifyURI::from_str(uri.to_string())),
+            track_number: json["track_number"].as_u64().unwrap_or(0) as u32,
+            disc_number: json["disc_number"].as_u64().unwrap_or(0) as u32,
+            external_ids: json["external_ids"]
+                .as_object()
+                .map(|ids| {
+                    ids.iter()
+                        .filter_map(|(k, v)| Some((k.clone(), v.as_str()?.to_string())))
+                        .collect()
+                })
+                .unwrap_or_default(),
+            is_playable: json["is_playable"].as_bool().unwrap_or(true),
+            linked_from: json["linked_from"]["uri"]
+                .as_str()
+                .map(|uri| SpotifyURI::from_str(uri.to_string())),
         })
     }
     fn type_string() -> String {
@@ -273,11 +716,80 @@ impl Content for Track {
     }
 }
 
+impl Content for Show {
+    fn from_json(json: &Value) -> Result<Self, String> {
+        Ok(Show {
+            name: json["name"]
+                .as_str()
+                .ok_or("missing name field?")?
+                .to_string(),
+            publisher: json["publisher"].as_str().unwrap_or_default().to_string(),
+            description: json["description"]
+                .as_str()
+                .unwrap_or_default()
+                .trim()
+                .to_string(),
+            total_episodes: json["total_episodes"].as_u64().unwrap_or(0) as u32,
+            explicit: json["explicit"].as_bool().unwrap_or(false),
+            uri: SpotifyURI::from_str(
+                json["uri"]
+                    .as_str()
+                    .ok_or("missing URI field?")?
+                    .to_string(),
+            ),
+        })
+    }
+    fn type_string() -> String {
+        String::from("show")
+    }
+    fn get_uri(&self) -> &SpotifyURI {
+        &self.uri
+    }
+}
+
+impl Content for Episode {
+    fn from_json(json: &Value) -> Result<Self, String> {
+        Ok(Episode {
+            name: json["name"]
+                .as_str()
+                .ok_or("missing name field?")?
+                .to_string(),
+            show: json["show"]["name"]
+                .as_str()
+                .unwrap_or_default()
+                .to_string(),
+            duration: (json["duration_ms"].as_u64().unwrap_or(0) / 1000) as u32,
+            explicit: json["explicit"].as_bool().unwrap_or(false),
+            release_date: json["release_date"]
+                .as_str()
+                .unwrap_or_default()
+                .to_string(),
+            uri: SpotifyURI::from_str(
+                json["uri"]
+                    .as_str()
+                    .ok_or("missing URI field?")?
+                    .to_string(),
+            ),
+        })
+    }
+    fn type_string() -> String {
+        String::from("episode")
+    }
+    fn get_uri(&self) -> &SpotifyURI {
+        &self.uri
+    }
+}
+
 impl Content for PlaylistTrack {
     fn from_json(json: &Value) -> Result<Self, String> {
         Ok(PlaylistTrack {
             track: Track::from_json(&json["track"])?,
-            added_at: rfc3339_to_epoch_time(json["added_at"].as_str().ok_or("timestamp missing")?),
+            added_at: rfc3339_to_epoch_time(json["added_at"].as_str().ok_or("timestamp missing")?)?,
+            // tracks added before Spotify kept track of it have an empty user instead of none
+            added_by: match json["added_by"]["id"].as_str() {
+                Some(id) if !id.is_empty() => Some(User::from_json(&json["added_by"])?),
+                _ => None,
+            },
         })
     }
     fn type_string() -> String {
@@ -288,6 +800,29 @@ impl Content for PlaylistTrack {
     }
 }
 
+impl Content for User {
+    fn from_json(json: &Value) -> Result<Self, String> {
+        let id = json["id"].as_str().ok_or("missing ID field?")?;
+        Ok(User {
+            followers: json["followers"]["total"].as_u64().unwrap_or(0) as u32,
+            uri: SpotifyURI::from_str(
+                json["uri"]
+                    .as_str()
+                    .map(|uri| uri.to_string())
+                    .unwrap_or_else(|| format!("spotify:user:{}", id)),
+            ),
+            // only full user objects have a display name
+            name: json["display_name"].as_str().unwrap_or(id).to_string(),
+        })
+    }
+    fn type_string() -> String {
+        String::from("user")
+    }
+    fn get_uri(&self) -> &SpotifyURI {
+        &self.uri
+    }
+}
+
 impl Content for Artist {
     fn from_json(json: &Value) -> Result<Self, String> {
         Ok(Artist {
@@ -295,7 +830,19 @@ impl Content for Artist {
                 .as_str()
                 .ok_or("missing name field?")?
                 .to_string(),
-            uri: SpotifyURI::from_str(json["uri"].as_str().unwrap().to_string()),
+            // artists of local files have no URI
+            uri: SpotifyURI::from_str(json["uri"].as_str().unwrap_or_default().to_string()),
+            genres: json["genres"]
+                .as_array()
+                .map(|genres| {
+                    genres
+                        .iter()
+                        .filter_map(|g| g.as_str().map(|s| s.to_string()))
+                        .collect()
+                })
+                .unwrap_or_default(),
+            popularity: json["popularity"].as_u64().unwrap_or(0) as u8,
+            followers: json["followers"]["total"].as_u64().unwrap_or(0) as u32,
         })
     }
     fn type_string() -> String {
@@ -314,15 +861,33 @@ impl Content for Album {
                 .ok_or("missing name field?")?
                 .to_string(),
             artists: Artist::from_json_array(&json["artists"])?,
-            tracks: Track::from_json_array(&json["tracks"]["items"])?,
+            // simplified albums, like the ones in search results, don't include their tracks
+            tracks: if json["tracks"]["items"].is_array() {
+                Track::from_json_array(&json["tracks"]["items"])?
+            } else {
+                Vec::new()
+            },
             uri: SpotifyURI::from_str(
                 json["uri"]
                     .as_str()
                     .ok_or("missing URI field?")?
                     .to_string(),
             ),
+            release_date: json["release_date"]
+                .as_str()
+                .unwrap_or_default()
+                .to_string(),
+            total_tracks: json["total_tracks"].as_u64().unwrap_or(0) as u32,
+            label: json["label"].as_str().unwrap_or_default().to_string(),
+            images: image_urls(&json["images"]),
         })
     }
+    /// albums only embed the first page of their tracks, so follow the paging links to get the rest.
+    fn from_id(id: &str, user: &mut Account) -> Result<Self, String> {
+        let mut json = do_api_json("GET", format!("albums/{}", id).as_str(), user, "")?;
+        json["tracks"]["items"] = Value::Array(get_all_pages(user, &json["tracks"])?);
+        Self::from_json(&json)
+    }
     fn type_string() -> String {
         String::from("album")
     }
@@ -369,9 +934,31 @@ impl Content for Playlist {
                     .ok_or("missing URI field?")?
                     .to_string(),
             ),
+            snapshot_id: json["snapshot_id"].as_str().unwrap_or_default().to_string(),
+            images: image_urls(&json["images"]),
+            owner: if json["owner"].is_object() {
+                Some(User::from_json(&json["owner"])?)
+            } else {
+                None
+            },
             tracks,
         })
     }
+    /// playlists only embed the first page of their tracks, so follow the paging links to get the rest.
+    fn from_id(id: &str, user: &mut Account) -> Result<Self, String> {
+        let mut json = do_api_json(
+            "GET",
+            format!(
+                "playlists/{}?additional_types=episode&market=from_token",
+                id
+            )
+            .as_str(),
+            user,
+            "",
+        )?;
+        json["tracks"]["items"] = Value::Array(get_all_pages(user, &json["tracks"])?);
+        Self::from_json(&json)
+    }
     fn type_string() -> String {
         String::from("playlist")
     }
@@ -396,13 +983,45 @@ impl Playlist {
         let mut contents = String::new();
         file.read_to_string(&mut contents)
             .map_err(|e| e.to_string())?;
-        serde_json::from_str(&contents).map_err(|e| e.to_string())
+        let mut json: Value = serde_json::from_str(&contents).map_err(|e| e.to_string())?;
+        upgrade_legacy_durations(&mut json);
+        let mut playlist: Playlist = serde_json::from_value(json).map_err(|e| e.to_string())?;
+        for track in &mut playlist.tracks {
+            track.added_at = from_legacy_epoch_time(track.added_at);
+        }
+        Ok(playlist)
     }
 
     pub fn sort_tracks(&mut self) {
         self.tracks.sort_by(|a, b| b.added_at.cmp(&a.added_at));
     }
 
+    /// who added the tracks in this playlist, most tracks first.
+    pub fn contributors(&self) -> Vec<Contributor> {
+        let mut contributors: Vec<Contributor> = Vec::new();
+        for track in &self.tracks {
+            let id = track.added_by.as_ref().map(|u| u.get_id());
+            match contributors
+                .iter_mut()
+                .find(|c| c.user.as_ref().map(|u| u.get_id()) == id)
+            {
+                Some(c) => {
+                    c.tracks += 1;
+                    c.first_added = c.first_added.min(track.added_at);
+                    c.last_added = c.last_added.max(track.added_at);
+                }
+                None => contributors.push(Contributor {
+                    user: track.added_by.clone(),
+                    tracks: 1,
+                    first_added: track.added_at,
+                    last_added: track.added_at,
+                }),
+            }
+        }
+        contributors.sort_by_key(|c| Reverse(c.tracks));
+        contributors
+    }
+
     pub fn print_tracks_ordered(&mut self) {
         self.sort_tracks();
         for track in &self.tracks {
@@ -426,19 +1045,105 @@ impl Playlist {
             uri: SpotifyURI {
                 uri: "".to_string(),
             },
+            snapshot_id: "".to_string(),
+            images: Vec::new(),
+            owner: None,
         };
         if new_user.is_none() {
             warn!("staying on same user");
         }
         let user = new_user.unwrap_or(owner);
+        let problems = self
+            .tracks
+            .iter()
+            .filter_map(|t| t.track.copy_problem().map(|p| (t, p)))
+            .collect::<Vec<(&PlaylistTrack, &str)>>();
+        if !problems.is_empty() {
+            warn!("{} tracks can't be copied as they are:", problems.len());
+            for (track, problem) in &problems {
+                println!("  {}: {}", track.track, problem);
+            }
+            if user_yn("Search for replacements for them?", false) {
+                for (track, _) in &problems {
+                    match track.track.find_replacement(user)? {
+                        Some(replacement) => {
+                            info!("replacing {} with {}", track.track, replacement);
+                            if let Some(t) = new_playlist
+                                .tracks
+                                .iter_mut()
+                                .find(|t| t.track.uri.uri == track.track.uri.uri)
+                            {
+                                t.track = replacement;
+                            }
+                        }
+                        None => warn!("no replacement found for {}", track.track),
+                    }
+                }
+            }
+        }
         info!("Creating new playlist on account {}", user.get_id()?);
         new_playlist.create_online(user)?;
         info!("created new playlist");
         new_playlist.put_tracks_online(user, false)?;
+        self.copy_cover_to(&new_playlist, user);
         info!("copied playlist");
         Ok(new_playlist)
     }
 
+    /// create this playlist again on the given account, with the same name, description,
+    /// visibility, cover and track order. Used to restore playlists from backups.
+    pub fn recreate(&self, user: &mut Account) -> Result<Playlist, String> {
+        let new_playlist = self.create_copy(user)?;
+        self.fill_copy(&new_playlist.uri, user)?;
+        info!("recreated playlist {}", self.name);
+        Ok(new_playlist)
+    }
+
+    /// create an empty copy of this playlist on the given account, to be filled with
+    /// <code>[Playlist::fill_copy]</code>.
+    pub fn create_copy(&self, user: &mut Account) -> Result<Playlist, String> {
+        let mut new_playlist = self.clone();
+        new_playlist.followers = 0;
+        new_playlist.images = Vec::new();
+        new_playlist.create_online(user)?;
+        Ok(new_playlist)
+    }
+
+    /// add the tracks and cover of this playlist to the empty copy of it with the given URI.
+    pub fn fill_copy(&self, copy: &SpotifyURI, user: &mut Account) -> Result<(), String> {
+        let mut target = self.clone();
+        target.uri = copy.clone();
+        target.add_tracks_online(user, false)?;
+        self.copy_cover_to(&target, user);
+        Ok(())
+    }
+
+    /// copy the custom cover of this playlist, if it has one, to the given playlist.
+    /// failing to do so isn't fatal, as the tracks are what matter.
+    fn copy_cover_to(&self, target: &Playlist, user: &mut Account) {
+        if let Some(url) = self.custom_cover() {
+            info!("copying cover image");
+            if let Err(e) = fetch_image(url)
+                .and_then(|bytes| encode_cover(&bytes))
+                .and_then(|cover| target.upload_cover(user, &cover))
+            {
+                warn!("failed to copy cover image: {}", e);
+            }
+        }
+    }
+
+    /// follow this playlist on the given account, showing it on their profile if it is public.
+    pub fn follow_online(&self, user: &mut Account) -> Result<(), String> {
+        do_api(
+            "PUT",
+            format!("playlists/{}/followers", self.uri.get_id()).as_str(),
+            user,
+            json!({ "public": self.visibility.is_public() }),
+        )?;
+        info!("Followed playlist {}", self.name);
+        Ok(())
+    }
+
     pub fn copy_to_liked(&self, new_acc: &mut Account) -> Result<(), String> {
         if !user_yn(
             "This method will overwrite your liked songs on the target account. Continue?",
@@ -450,7 +1155,18 @@ impl Playlist {
         info!("clearing liked songs on account {}", new_acc.get_id()?);
         liked.clear_tracks_online(new_acc, true)?;
         info!("copying tracks");
-        liked.tracks = self.tracks.clone();
+        liked.tracks = self
+            .tracks
+            .iter()
+            .filter(|t| !t.track.is_episode())
+            .cloned()
+            .collect();
+        if liked.tracks.len() < self.tracks.len() {
+            warn!(
+                "skipping {} podcast episodes, as they can't be liked songs",
+                self.tracks.len() - liked.tracks.len()
+            );
+        }
         liked.put_tracks_online(new_acc, true)?;
         info!("copied to liked songs");
         Ok(())
@@ -464,6 +1180,10 @@ impl Playlist {
         name: String,
         description: Option<String>,
     ) -> Result<Playlist, String> {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs();
         let mut playlist = Playlist {
             name,
             description: description.unwrap_or_default(),
@@ -473,26 +1193,38 @@ impl Playlist {
                 .iter()
                 .map(|x| PlaylistTrack {
                     track: x.clone(),
-                    added_at: SystemTime::now()
-                        .duration_since(UNIX_EPOCH)
-                        .unwrap()
-                        .as_secs(),
+                    added_at: now,
+                    added_by: None,
                 })
                 .collect(),
             uri: SpotifyURI {
                 uri: "".to_string(),
             },
+            snapshot_id: "".to_string(),
+            images: Vec::new(),
+            owner: None,
         };
         playlist.create_online(user)?;
-        playlist.put_tracks_online(user, false)?;
+        // not sorted by when they were added, the tracks go online in the order they're given in
+        playlist.add_tracks_online(user, false)?;
         Ok(playlist)
     }
 
     /// put the tracks in this playlist onto its online self.
     pub fn put_tracks_online(&mut self, user: &mut Account, liked: bool) -> Result<(), String> {
         self.sort_tracks();
+        self.add_tracks_online(user, liked)
+    }
+
+    /// add the tracks in this playlist to its online self, in the order they are in now.
+    /// local files can't be added through the API, so they are left out.
+    fn add_tracks_online(&self, user: &mut Account, liked: bool) -> Result<(), String> {
         let mut requests: Vec<&str> = Vec::new();
         for track in &self.tracks {
+            if track.track.is_local {
+                warn!("skipping local file {}", track.track);
+                continue;
+            }
             if liked {
                 requests.push(track.track.uri.get_id());
             } else {
@@ -554,25 +1286,273 @@ impl Playlist {
         Ok(())
     }
 
+    /// replace every track in the online playlist with the given URIs, in order.
+    /// local files can't be added through the API, so they are left out.
+    pub fn replace_tracks_online(&self, user: &mut Account, uris: &[&str]) -> Result<(), String> {
+        let uris = uris
+            .iter()
+            .filter(|uri| !uri.starts_with("spotify:local:"))
+            .copied()
+            .collect::<Vec<&str>>();
+        let endpoint = format!("playlists/{}/tracks", self.uri.get_id());
+        let mut chunks = uris.chunks(100);
+        info!("Replacing tracks in playlist... (0/{})", uris.len());
+        do_api(
+            "PUT",
+            endpoint.as_str(),
+            user,
+            json!({ "uris": chunks.next().unwrap_or_default() }),
+        )?;
+        let mut i = uris.len().min(100);
+        for chunk in chunks {
+            info!("Replacing tracks in playlist... ({}/{})", i, uris.len());
+            do_api("POST", endpoint.as_str(), user, json!({ "uris": chunk }))?;
+            i += chunk.len();
+        }
+        info!("Replaced tracks in playlist");
+        Ok(())
+    }
+
+    /// reorder the online playlist so that its tracks end up in the given order, where
+    /// `order[new_position] = old_position`. Only the tracks that are out of place are moved,
+    /// using the reorder endpoint rather than clearing and re-adding, so `added_at` is kept.
+    pub fn reorder_online(&mut self, user: &mut Account, order: &[usize]) -> Result<(), String> {
+        let online = do_api_json(
+            "GET",
+            format!(
+                "playlists/{}?fields=snapshot_id,tracks.total",
+                self.uri.get_id()
+            )
+            .as_str(),
+            user,
+            "",
+        )?;
+        let total = online["tracks"]["total"]
+            .as_u64()
+            .ok_or("Failed to get playlist length")? as usize;
+        if total != self.tracks.len() || order.len() != total {
+            return Err(format!(
+                "Playlist has {} items online but {} could be read; refusing to reorder it",
+                total,
+                self.tracks.len()
+            ));
+        }
+        self.snapshot_id = online["snapshot_id"]
+            .as_str()
+            .ok_or("Failed to get playlist snapshot")?
+            .to_string();
+        let moves = plan_moves(order);
+        info!(
+            "Reordering playlist with {} moves ({} tracks)",
+            moves.len(),
+            total
+        );
+        for (i, (range_start, insert_before)) in moves.iter().enumerate() {
+            if i % 25 == 0 {
+                info!("Moving tracks... ({}/{})", i, moves.len());
+            }
+            self.snapshot_id = do_api_json(
+                "PUT",
+                format!("playlists/{}/tracks", self.uri.get_id()).as_str(),
+                user,
+                json!({
+                    "range_start": range_start,
+                    "insert_before": insert_before,
+                    "range_length": 1,
+                    "snapshot_id": self.snapshot_id.as_str(),
+                }),
+            )?["snapshot_id"]
+                .as_str()
+                .ok_or("missing snapshot_id when reordering playlist")?
+                .to_string();
+        }
+        self.tracks = order.iter().map(|&i| self.tracks[i].clone()).collect();
+        info!("Reordered playlist");
+        Ok(())
+    }
+
+    /// push the name, description and visibility of this playlist to its online self.
+    /// collaborative playlists are always sent as non-public, as Spotify requires.
+    pub fn update_details_online(&self, user: &mut Account) -> Result<(), String> {
+        if self.name.trim().is_empty() {
+            return Err("Playlist name cannot be empty".to_string());
+        }
+        if self.description.chars().count() > 300 {
+            return Err(format!(
+                "Playlist description is too long ({} characters, the limit is 300)",
+                self.description.chars().count()
+            ));
+        }
+        if self.description.contains('\n') {
+            return Err("Playlist description cannot contain line breaks".to_string());
+        }
+        do_api(
+            "PUT",
+            format!("playlists/{}", self.uri.get_id()).as_str(),
+            user,
+            json!({
+                "name": self.name.as_str(),
+                "description": self.description.as_str(),
+                "public": &self.visibility.is_public(),
+                "collaborative": &self.visibility.is_collaborative(),
+            }),
+        )?;
+        info!("Updated playlist {}", self.name);
+        Ok(())
+    }
+
+    /// the URL of this playlist's cover, if it has been set by the owner. Covers made from a
+    /// mosaic of the first few album covers are generated by Spotify, so they are left out.
+    pub fn custom_cover(&self) -> Option<&str> {
+        self.images
+            .first()
+            .map(|s| s.as_str())
+            .filter(|url| !url.contains("mosaic.scdn.co"))
+    }
+
+    /// set the cover image of this playlist to the given base64 encoded JPEG.
+    /// see <code>[encode_cover]</code> to get one from an image file.
+    pub fn upload_cover(&self, user: &mut Account, jpeg_base64: &str) -> Result<(), String> {
+        if !user.scope.contains("ugc-image-upload") {
+            return Err("This account can't upload images, as it was added before spotimine supported them. Re-add it with 'adduser' to allow it".to_string());
+        }
+        do_api_jpeg(
+            format!("playlists/{}/images", self.uri.get_id()).as_str(),
+            user,
+            jpeg_base64,
+        )?;
+        info!("Uploaded cover image for {}", self.name);
+        Ok(())
+    }
+
+    /// unfollow this playlist. For playlists the user owns, this is how they are deleted.
+    pub fn unfollow_online(&self, user: &mut Account) -> Result<(), String> {
+        do_api(
+            "DELETE",
+            format!("playlists/{}/followers", self.uri.get_id()).as_str(),
+            user,
+            "",
+        )?;
+        info!("Unfollowed playlist {}", self.name);
+        Ok(())
+    }
+
     /// Create a playlist on the Spotify API from this playlist.
     /// This will also set the URI of this playlist to the URI of the newly created playlist.
     fn create_online(&mut self, user: &mut Account) -> Result<(), String> {
+        let res = do_api_json(
+            "POST",
+            format!("users/{}/playlists", user.get_id()?).as_str(),
+            user,
+            json!({
+                "name": self.name.as_str(),
+                "description": self.description.as_str(),
+                "public": &self.visibility.is_public(),
+                "collaborative": &self.visibility.is_collaborative(),
+            }),
+        )?;
         self.uri = SpotifyURI::from_str(
-            do_api_json(
-                "POST",
-                format!("users/{}/playlists", user.get_id()?).as_str(),
-                user,
-                json!({
-                    "name": self.name.as_str(),
-                    "description": self.description.as_str(),
-                    "public": &self.visibility.is_public(),
-                    "collaborative": &self.visibility.is_collaborative(),
-                }),
-            )?["uri"]
+            res["uri"]
                 .as_str()
                 .ok_or("missing URI field when creating playlist: probably invalid response")?
                 .to_string(),
         );
+        self.snapshot_id = res["snapshot_id"].as_str().unwrap_or_default().to_string();
+        if res["owner"].is_object() {
+            self.owner = Some(User::from_json(&res["owner"])?);
+        }
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ID: &str = "37i9dQZF1DXcBWIGoYBM5M";
+
+    fn parsed(s: &str) -> Result<String, String> {
+        SpotifyURI::parse(s).map(|uri| uri.uri)
+    }
+
+    #[test]
+    fn parses_uris_and_links() {
+        let uri = format!("spotify:playlist:{}", ID);
+        for s in [
+            uri.clone(),
+            format!("  {}  ", uri),
+            format!("spotify:user:someone:playlist:{}", ID),
+            format!("https://open.spotify.com/playlist/{}", ID),
+            format!("http://open.spotify.com/playlist/{}?si=abc123", ID),
+            format!("open.spotify.com/playlist/{}/", ID),
+            format!("https://open.spotify.com/intl-de/playlist/{}#top", ID),
+        ] {
+            assert_eq!(parsed(&s), Ok(uri.clone()), "{}", s);
+        }
+        assert_eq!(
+            parsed(&format!("https://open.spotify.com/track/{}", ID)),
+            Ok(format!("spotify:track:{}", ID))
+        );
+        assert_eq!(
+            parsed("https://open.spotify.com/user/someone"),
+            Ok("spotify:user:someone".to_string())
+        );
+        assert_eq!(
+            parsed("spotify:local:Artist:Album:Title:215"),
+            Ok("spotify:local:Artist:Album:Title:215".to_string())
+        );
+    }
+
+    #[test]
+    fn rejects_invalid_uris_and_links() {
+        for s in [
+            "".to_string(),
+            ID.to_string(),
+            "spotify:".to_string(),
+            format!("spotify:playlist:{}x", ID),
+            format!("spotify:playlists:{}", ID),
+            format!("spotify:genre:{}", ID),
+            format!("spotify:playlist:{}:extra", ID),
+            format!("https://open.spotify.com/playlist/{}", &ID[1..]),
+            format!("https://example.com/playlist/{}", ID),
+            format!("https://open.spotify.com/{}", ID),
+            "https://open.spotify.com/playlist/".to_string(),
+        ] {
+            assert!(parsed(&s).is_err(), "{} should be rejected", s);
+        }
+    }
+
+    #[test]
+    fn parses_bare_ids_of_the_expected_type() {
+        let playlist = format!("spotify:playlist:{}", ID);
+        assert_eq!(
+            SpotifyURI::parse_as(ID, ContentType::Playlists).map(|u| u.uri),
+            Ok(playlist.clone())
+        );
+        assert_eq!(
+            SpotifyURI::parse_as(&playlist, ContentType::Playlists).map(|u| u.uri),
+            Ok(playlist.clone())
+        );
+        assert!(SpotifyURI::parse_as(&playlist, ContentType::Albums).is_err());
+        assert!(SpotifyURI::parse_as("spotify:local:a:b:c:1", ContentType::Tracks).is_err());
+        assert!(SpotifyURI::parse_as(&ID[1..], ContentType::Playlists).is_err());
+    }
+
+    #[test]
+    fn reads_legacy_durations_in_seconds() {
+        let mut json = json!({
+            "tracks": [
+                {"name": "Old", "artists": [], "duration": 215, "explicit": false,
+                 "uri": {"uri": "spotify:track:0000000000000000000001"}},
+                {"name": "New", "artists": [], "duration_ms": 215500, "explicit": false,
+                 "uri": {"uri": "spotify:track:0000000000000000000002"}}
+            ],
+            "episode": {"name": "Episode", "duration": 60}
+        });
+        upgrade_legacy_durations(&mut json);
+        let tracks: Vec<Track> = serde_json::from_value(json["tracks"].clone()).unwrap();
+        assert_eq!(tracks[0].duration_ms, 215000);
+        assert_eq!(tracks[1].duration_ms, 215500);
+        assert!(json["episode"].get("duration_ms").is_none());
+    }
+}
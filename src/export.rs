@@ -0,0 +1,284 @@
+use std::fs::File;
+use std::io::Write;
+use std::path::Path;
+
+use serde_json::{json, Map, Value};
+
+use crate::data::{Playlist, PlaylistTrack, Stringify};
+use crate::utils::{epoch_time_to_rfc3339, format_duration, strip_html_tags};
+
+#[derive(Debug, Clone, Copy)]
+pub enum ExportFormat {
+    Csv,
+    M3u8,
+    Xspf,
+    Txt,
+    Json,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum Column {
+    Name,
+    Artists,
+    Album,
+    Duration,
+    Isrc,
+    AddedAt,
+    Uri,
+}
+
+impl ExportFormat {
+    /// parse a format such as `csv` or `csv:name,artists,isrc`. Columns can only be chosen for
+    /// csv, txt and json; the other formats have fixed fields.
+    pub fn from_str(s: &str) -> Result<(ExportFormat, Vec<Column>), String> {
+        let (format, columns) = match s.split_once(':') {
+            Some((format, columns)) => (format, Some(columns)),
+            None => (s, None),
+        };
+        let format = match format.to_lowercase().as_str() {
+            "csv" => ExportFormat::Csv,
+            "m3u" | "m3u8" => ExportFormat::M3u8,
+            "xspf" => ExportFormat::Xspf,
+            "txt" | "text" => ExportFormat::Txt,
+            "json" => ExportFormat::Json,
+            _ => {
+                return Err(format!(
+                    "Invalid export format: {}. Valid formats are: 'csv', 'm3u8', 'xspf', 'txt', 'json'",
+                    format
+                ))
+            }
+        };
+        let columns = match columns {
+            None => format.default_columns(),
+            Some(_) if matches!(format, ExportFormat::M3u8 | ExportFormat::Xspf) => {
+                return Err("Columns can only be chosen for csv, txt and json".to_string())
+            }
+            Some(columns) => columns
+                .split(',')
+                .map(Column::from_str)
+                .collect::<Result<Vec<Column>, String>>()?,
+        };
+        Ok((format, columns))
+    }
+
+    fn default_columns(&self) -> Vec<Column> {
+        match self {
+            ExportFormat::Txt => vec![Column::Artists, Column::Name],
+            _ => vec![
+                Column::Name,
+                Column::Artists,
+                Column::Album,
+                Column::Duration,
+                Column::Isrc,
+                Column::AddedAt,
+                Column::Uri,
+            ],
+        }
+    }
+}
+
+impl Column {
+    pub fn from_str(s: &str) -> Result<Column, String> {
+        match s.to_lowercase().as_str() {
+            "name" | "title" => Ok(Column::Name),
+            "artist" | "artists" => Ok(Column::Artists),
+            "album" => Ok(Column::Album),
+            "duration" | "length" => Ok(Column::Duration),
+            "isrc" => Ok(Column::Isrc),
+            "added" | "added_at" => Ok(Column::AddedAt),
+            "uri" => Ok(Column::Uri),
+            _ => Err(format!(
+                "Invalid column: {}. Valid columns are: 'name', 'artists', 'album', 'duration', 'isrc', 'added_at', 'uri'",
+                s
+            )),
+        }
+    }
+
+    fn header(&self) -> &str {
+        match self {
+            Column::Name => "name",
+            Column::Artists => "artists",
+            Column::Album => "album",
+            Column::Duration => "duration",
+            Column::Isrc => "isrc",
+            Column::AddedAt => "added_at",
+            Column::Uri => "uri",
+        }
+    }
+
+    fn value(&self, track: &PlaylistTrack) -> String {
+        match self {
+            Column::Name => track.track.name.clone(),
+            Column::Artists => track.track.artists.stringify(),
+            Column::Album => track.track.album.clone(),
+            Column::Duration => format_duration(track.track.duration()),
+            Column::Isrc => track.track.isrc().unwrap_or_default().to_string(),
+            Column::AddedAt => epoch_time_to_rfc3339(track.added_at),
+            Column::Uri => track.track.uri.uri.clone(),
+        }
+    }
+}
+
+/// write the playlist to the given file in the given format, with the given columns where the
+/// format allows them.
+pub fn export(
+    playlist: &Playlist,
+    format: ExportFormat,
+    columns: &[Column],
+    path: &Path,
+) -> Result<(), String> {
+    let contents = match format {
+        ExportFormat::Csv => to_csv(playlist, columns),
+        ExportFormat::M3u8 => to_m3u8(playlist),
+        ExportFormat::Xspf => to_xspf(playlist),
+        ExportFormat::Txt => to_txt(playlist, columns),
+        ExportFormat::Json => to_json(playlist, columns)?,
+    };
+    File::create(path)
+        .map_err(|e| format!("failed to create {:?}: {}", path, e))?
+        .write_all(contents.as_bytes())
+        .map_err(|e| format!("failed to write {:?}: {}", path, e))
+}
+
+/// quote a CSV field if it needs it, as in RFC 4180.
+fn csv_field(s: &str) -> String {
+    if s.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", s.replace('"', "\"\""))
+    } else {
+        s.to_string()
+    }
+}
+
+fn to_csv(playlist: &Playlist, columns: &[Column]) -> String {
+    let mut out = columns
+        .iter()
+        .map(|c| c.header())
+        .collect::<Vec<&str>>()
+        .join(",");
+    out.push_str("\r\n");
+    for track in &playlist.tracks {
+        out.push_str(
+            &columns
+                .iter()
+                .map(|c| csv_field(&c.value(track)))
+                .collect::<Vec<String>>()
+                .join(","),
+        );
+        out.push_str("\r\n");
+    }
+    out
+}
+
+fn to_m3u8(playlist: &Playlist) -> String {
+    let mut out = format!("#EXTM3U\n#PLAYLIST:{}\n", playlist.name);
+    for track in &playlist.tracks {
+        out.push_str(&format!(
+            "#EXTINF:{},{} - {}\n{}\n",
+            track.track.duration(),
+            track.track.artists.stringify(),
+            track.track.name,
+            track
+                .track
+                .uri
+                .to_url()
+                .unwrap_or_else(|| track.track.uri.uri.clone())
+        ));
+    }
+    out
+}
+
+pub(crate) fn xml_escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&apos;")
+}
+
+fn to_xspf(playlist: &Playlist) -> String {
+    let mut out = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+    out.push_str("<playlist version=\"1\" xmlns=\"http://xspf.org/ns/0/\">\n");
+    out.push_str(&format!(
+        "  <title>{}</title>\n",
+        xml_escape(&playlist.name)
+    ));
+    if !playlist.description.is_empty() {
+        out.push_str(&format!(
+            "  <annotation>{}</annotation>\n",
+            xml_escape(&strip_html_tags(&playlist.description))
+        ));
+    }
+    out.push_str("  <trackList>\n");
+    for track in &playlist.tracks {
+        let t = &track.track;
+        out.push_str("    <track>\n");
+        if let Some(url) = t.uri.to_url() {
+            out.push_str(&format!(
+                "      <location>{}</location>\n",
+                xml_escape(&url)
+            ));
+        }
+        out.push_str(&format!(
+            "      <identifier>{}</identifier>\n",
+            xml_escape(&t.uri.uri)
+        ));
+        if let Some(isrc) = t.isrc() {
+            out.push_str(&format!(
+                "      <identifier>isrc:{}</identifier>\n",
+                xml_escape(isrc)
+            ));
+        }
+        out.push_str(&format!("      <title>{}</title>\n", xml_escape(&t.name)));
+        out.push_str(&format!(
+            "      <creator>{}</creator>\n",
+            xml_escape(&t.artists.stringify())
+        ));
+        if !t.album.is_empty() {
+            out.push_str(&format!("      <album>{}</album>\n", xml_escape(&t.album)));
+        }
+        if t.track_number > 0 {
+            out.push_str(&format!("      <trackNum>{}</trackNum>\n", t.track_number));
+        }
+        out.push_str(&format!("      <duration>{}</duration>\n", t.duration_ms));
+        out.push_str("    </track>\n");
+    }
+    out.push_str("  </trackList>\n</playlist>\n");
+    out
+}
+
+fn to_txt(playlist: &Playlist, columns: &[Column]) -> String {
+    playlist
+        .tracks
+        .iter()
+        .map(|track| {
+            columns
+                .iter()
+                .map(|c| c.value(track))
+                .collect::<Vec<String>>()
+                .join(" - ")
+                + "\n"
+        })
+        .collect()
+}
+
+fn to_json(playlist: &Playlist, columns: &[Column]) -> Result<String, String> {
+    let tracks = playlist
+        .tracks
+        .iter()
+        .map(|track| {
+            Value::Object(
+                columns
+                    .iter()
+                    .map(|c| (c.header().to_string(), Value::String(c.value(track))))
+                    .collect::<Map<String, Value>>(),
+            )
+        })
+        .collect::<Vec<Value>>();
+    serde_json::to_string_pretty(&json!({
+        "name": playlist.name,
+        "description": strip_html_tags(&playlist.description),
+        "uri": playlist.uri.uri,
+        "tracks": tracks,
+    }))
+    .map_err(|e| e.to_string())
+}
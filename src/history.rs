@@ -0,0 +1,458 @@
+use std::collections::{HashMap, HashSet};
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::account::Account;
+use crate::api::do_api_json;
+use crate::backup::read_json;
+use crate::config::config_dir;
+use crate::data::{Content, Track};
+use crate::matching::{find_clear_match, TrackQuery};
+use crate::utils::{epoch_time_to_rfc3339, rfc3339_to_epoch_time};
+use crate::{info, warn};
+
+/// Spotify only counts a stream as a play after 30 seconds.
+pub(crate) const MIN_PLAY_MS: u32 = 30_000;
+
+/// one time a track was listened to.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Play {
+    /// unix time the track stopped playing at.
+    pub played_at: u64,
+    pub ms_played: u32,
+    pub track: String,
+    pub artist: String,
+    #[serde(default)]
+    pub album: String,
+    /// the track's URI, if the source had it. The basic data export only has names.
+    #[serde(default)]
+    pub uri: Option<String>,
+    /// whether `ms_played` is the length of the track rather than how long it was played, which
+    /// the API doesn't say. Plays synced with `history sync` are estimated.
+    #[serde(default)]
+    pub estimated: bool,
+}
+
+impl Play {
+    /// identifies a play across sources. The basic data export only has the minute a track ended,
+    /// so plays are compared to the minute.
+    fn key(&self) -> (u64, String, String) {
+        (
+            self.played_at / 60,
+            self.artist.to_lowercase(),
+            self.track.to_lowercase(),
+        )
+    }
+
+    /// identifies a play synced from the API, which has the exact time, so that playing a short
+    /// track twice in a minute counts twice.
+    fn exact_key(&self) -> (u64, String, String) {
+        (
+            self.played_at,
+            self.artist.to_lowercase(),
+            self.track.to_lowercase(),
+        )
+    }
+
+    /// identifies the track that was played, whether or not the play has its URI.
+    fn track_key(&self) -> (String, String) {
+        (self.artist.to_lowercase(), self.track.to_lowercase())
+    }
+
+    pub fn year(&self) -> String {
+        epoch_time_to_rfc3339(self.played_at)[..4].to_string()
+    }
+}
+
+/// how often one track was played.
+#[derive(Debug, Clone)]
+pub struct PlayCount {
+    pub track: String,
+    pub artist: String,
+    pub uri: Option<String>,
+    pub plays: usize,
+    pub ms_played: u64,
+}
+
+fn history_file(account: &str) -> Result<PathBuf, String> {
+    let dir = config_dir()?.join("listening");
+    std::fs::create_dir_all(&dir).map_err(|e| format!("failed to create {:?}: {}", dir, e))?;
+    Ok(dir.join(format!("{}.jsonl", account)))
+}
+
+/// load every play stored for the given account, in the order they were added.
+pub fn load_plays(account: &str) -> Result<Vec<Play>, String> {
+    let path = history_file(account)?;
+    if !path.exists() {
+        return Ok(Vec::new());
+    }
+    BufReader::new(File::open(&path).map_err(|e| format!("failed to open {:?}: {}", path, e))?)
+        .lines()
+        .filter(|line| !line.as_ref().map(|l| l.trim().is_empty()).unwrap_or(false))
+        .map(|line| {
+            serde_json::from_str(&line.map_err(|e| e.to_string())?)
+                .map_err(|e| format!("corrupt listening history in {:?}: {}", path, e))
+        })
+        .collect()
+}
+
+/// store the given plays for the account, leaving out any that are already stored. Returns the
+/// number of plays that were new.
+pub fn add_plays(account: &str, plays: &[Play]) -> Result<usize, String> {
+    let stored = load_plays(account)?;
+    let mut exact = stored
+        .iter()
+        .filter(|p| p.estimated)
+        .map(|p| p.exact_key())
+        .collect::<HashSet<(u64, String, String)>>();
+    // exported plays only have the minute, so any play in the same minute is the same one
+    let mut exported = stored
+        .iter()
+        .filter(|p| !p.estimated)
+        .map(|p| p.key())
+        .collect::<HashSet<(u64, String, String)>>();
+    let mut synced = stored
+        .iter()
+        .filter(|p| p.estimated)
+        .map(|p| p.key())
+        .collect::<HashSet<(u64, String, String)>>();
+    let path = history_file(account)?;
+    let mut file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&path)
+        .map_err(|e| format!("failed to open {:?}: {}", path, e))?;
+    let mut added = 0;
+    for play in plays {
+        let duplicate = match play.estimated {
+            true => exact.contains(&play.exact_key()) || exported.contains(&play.key()),
+            false => exported.contains(&play.key()) || synced.contains(&play.key()),
+        };
+        if duplicate {
+            continue;
+        }
+        if play.estimated {
+            exact.insert(play.exact_key());
+            synced.insert(play.key());
+        } else {
+            exported.insert(play.key());
+        }
+        writeln!(
+            file,
+            "{}",
+            serde_json::to_string(play).map_err(|e| e.to_string())?
+        )
+        .map_err(|e| format!("failed to write {:?}: {}", path, e))?;
+        added += 1;
+    }
+    Ok(added)
+}
+
+/// read the plays from a file of Spotify's personal data export: either the basic
+/// `StreamingHistory*.json`, or the extended `endsong_*.json` / `Streaming_History_Audio_*.json`.
+/// Podcasts and other non-music entries are skipped.
+pub fn parse_export(path: &Path) -> Result<Vec<Play>, String> {
+    let json: Value = read_json(path)?;
+    let mut plays = Vec::new();
+    for item in json
+        .as_array()
+        .ok_or(format!("{:?} is not a listening history file", path))?
+    {
+        let play = if let Some(ts) = item["ts"].as_str() {
+            // extended streaming history
+            let (Some(track), Some(artist)) = (
+                item["master_metadata_track_name"].as_str(),
+                item["master_metadata_album_artist_name"].as_str(),
+            ) else {
+                continue;
+            };
+            Play {
+                played_at: rfc3339_to_epoch_time(ts)?,
+                ms_played: item["ms_played"].as_u64().unwrap_or(0) as u32,
+                track: track.to_string(),
+                artist: artist.to_string(),
+                album: item["master_metadata_album_album_name"]
+                    .as_str()
+                    .unwrap_or_default()
+                    .to_string(),
+                uri: item["spotify_track_uri"].as_str().map(|s| s.to_string()),
+                estimated: false,
+            }
+        } else if let Some(end_time) = item["endTime"].as_str() {
+            // basic streaming history, with times like "2023-01-31 23:59" in UTC
+            let (Some(track), Some(artist)) =
+                (item["trackName"].as_str(), item["artistName"].as_str())
+            else {
+                continue;
+            };
+            Play {
+                played_at: rfc3339_to_epoch_time(&format!("{}:00Z", end_time))?,
+                ms_played: item["msPlayed"].as_u64().unwrap_or(0) as u32,
+                track: track.to_string(),
+                artist: artist.to_string(),
+                album: String::new(),
+                uri: None,
+                estimated: false,
+            }
+        } else {
+            return Err(format!("{:?} is not a listening history file", path));
+        };
+        plays.push(play);
+    }
+    Ok(plays)
+}
+
+/// the listening history files in the given path: the file itself, or the history files in a
+/// directory, such as the unzipped data export.
+pub fn export_files(path: &Path) -> Result<Vec<PathBuf>, String> {
+    if !path.is_dir() {
+        return Ok(vec![path.to_path_buf()]);
+    }
+    let mut files = std::fs::read_dir(path)
+        .map_err(|e| format!("failed to read {:?}: {}", path, e))?
+        .filter_map(|entry| entry.ok().map(|e| e.path()))
+        .filter(|p| {
+            let name = p
+                .file_name()
+                .and_then(|n| n.to_str())
+                .unwrap_or_default()
+                .to_lowercase();
+            name.ends_with(".json")
+                && (name.starts_with("streaminghistory")
+                    || name.starts_with("streaming_history_audio")
+                    || name.starts_with("endsong"))
+        })
+        .collect::<Vec<PathBuf>>();
+    files.sort();
+    Ok(files)
+}
+
+/// count the plays of each track, most played first. Plays shorter than 30 seconds don't count.
+pub fn play_counts<'a>(plays: impl Iterator<Item = &'a Play>) -> Vec<PlayCount> {
+    let mut counts: HashMap<(String, String), PlayCount> = HashMap::new();
+    for play in plays.filter(|p| p.ms_played >= MIN_PLAY_MS) {
+        let count = counts.entry(play.track_key()).or_insert_with(|| PlayCount {
+            track: play.track.clone(),
+            artist: play.artist.clone(),
+            uri: None,
+            plays: 0,
+            ms_played: 0,
+        });
+        count.plays += 1;
+        count.ms_played += play.ms_played as u64;
+        if count.uri.is_none() {
+            count.uri = play.uri.clone();
+        }
+    }
+    let mut counts = counts.into_values().collect::<Vec<PlayCount>>();
+    counts.sort_by(|a, b| {
+        b.plays
+            .cmp(&a.plays)
+            .then(b.ms_played.cmp(&a.ms_played))
+            .then(a.track.cmp(&b.track))
+    });
+    counts
+}
+
+/// find the Spotify tracks for the given counts, in order. Counts without a URI are searched for,
+/// and left out if there is no clear match.
+pub fn resolve(acc: &mut Account, counts: &[PlayCount]) -> Result<Vec<Track>, String> {
+    let mut uris = Vec::new();
+    let mut unresolved = Vec::new();
+    for (i, count) in counts.iter().enumerate() {
+        match &count.uri {
+            Some(uri) => uris.push(uri.clone()),
+            None => {
+                info!(
+                    "Searching for {} by {}... ({}/{})",
+                    count.track,
+                    count.artist,
+                    i + 1,
+                    counts.len()
+                );
+                let query = TrackQuery {
+                    title: count.track.clone(),
+                    artist: count.artist.clone(),
+                    ..TrackQuery::default()
+                };
+                match find_clear_match(&query, acc)?.and_then(|c| c.track) {
+                    Some(track) => uris.push(track.uri.uri),
+                    None => unresolved.push(count),
+                }
+            }
+        }
+    }
+    if !unresolved.is_empty() {
+        warn!("{} tracks could not be found on Spotify:", unresolved.len());
+        for count in unresolved {
+            println!("  {} by {}", count.track, count.artist);
+        }
+    }
+    let ids = uris
+        .iter()
+        .map(|uri| uri.rsplit(':').next().unwrap_or_default())
+        .collect::<Vec<&str>>();
+    Track::from_ids(&ids, acc)
+}
+
+/// fetch the plays of the account that are newer than `since`, newest first. Spotify only keeps
+/// the last 50, so syncing regularly is the only way to keep the whole history.
+pub fn recently_played(acc: &mut Account, since: u64) -> Result<Vec<Play>, String> {
+    if !acc.scope.contains("user-read-recently-played") {
+        return Err("This account can't read its listening history, as it was added before spotimine supported it. Re-add it with 'adduser' to allow it".to_string());
+    }
+    let mut plays = Vec::new();
+    let mut endpoint = Some("me/player/recently-played?limit=50".to_string());
+    while let Some(url) = endpoint {
+        let json = do_api_json("GET", url.as_str(), acc, "")?;
+        let items = json["items"]
+            .as_array()
+            .ok_or(format!("json was not an array: {}", json["items"]))?;
+        // pages go back in time, so stop at the first play that is already stored
+        let mut reached = items.is_empty();
+        for item in items {
+            let played_at = rfc3339_to_epoch_time(
+                item["played_at"]
+                    .as_str()
+                    .ok_or("recently played item has no played_at")?,
+            )?;
+            if played_at <= since {
+                reached = true;
+                continue;
+            }
+            let track = Track::from_json(&item["track"])?;
+            plays.push(Play {
+                played_at,
+                // the API doesn't say how long the track was played, so assume all of it
+                ms_played: track.duration_ms,
+                artist: track
+                    .artists
+                    .first()
+                    .map(|a| a.name.clone())
+                    .unwrap_or_default(),
+                album: track.album,
+                track: track.name,
+                uri: Some(track.uri.uri),
+                estimated: true,
+            });
+        }
+        endpoint = json["cursors"]["before"]
+            .as_str()
+            .filter(|_| !reached)
+            .map(|before| format!("me/player/recently-played?limit=50&before={}", before));
+    }
+    Ok(plays)
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    /// write the JSON to a file with the given name, and parse it as an export.
+    fn parsed(name: &str, json: Value) -> Result<Vec<Play>, String> {
+        let path = std::env::temp_dir().join(format!("spotimine-{}-{}", std::process::id(), name));
+        std::fs::write(&path, json.to_string()).unwrap();
+        let plays = parse_export(&path);
+        std::fs::remove_file(&path).unwrap();
+        plays
+    }
+
+    #[test]
+    fn parses_basic_export() {
+        let plays = parsed(
+            "StreamingHistory0.json",
+            json!([
+                {"endTime": "2023-01-31 23:59", "artistName": "Queen",
+                 "trackName": "Bohemian Rhapsody", "msPlayed": 354320},
+                {"endTime": "2023-02-01 00:02", "artistName": "Queen",
+                 "trackName": "Under Pressure", "msPlayed": 1200}
+            ]),
+        )
+        .unwrap();
+        assert_eq!(plays.len(), 2);
+        assert_eq!(plays[0].played_at, 1675209540);
+        assert_eq!(plays[0].ms_played, 354320);
+        assert_eq!(plays[0].artist, "Queen");
+        assert_eq!(plays[0].track, "Bohemian Rhapsody");
+        assert_eq!(plays[0].uri, None);
+        assert!(!plays[0].estimated);
+    }
+
+    #[test]
+    fn parses_extended_export_without_podcasts() {
+        let plays = parsed(
+            "Streaming_History_Audio_2023.json",
+            json!([
+                {"ts": "2023-01-31T23:59:30Z", "ms_played": 354320,
+                 "master_metadata_track_name": "Bohemian Rhapsody",
+                 "master_metadata_album_artist_name": "Queen",
+                 "master_metadata_album_album_name": "A Night at the Opera",
+                 "spotify_track_uri": "spotify:track:4u7EnebtmKWzUH433cf5Qv"},
+                {"ts": "2023-02-01T00:10:00Z", "ms_played": 600000,
+                 "master_metadata_track_name": null,
+                 "master_metadata_album_artist_name": null,
+                 "episode_name": "An episode"}
+            ]),
+        )
+        .unwrap();
+        assert_eq!(plays.len(), 1);
+        assert_eq!(plays[0].played_at, 1675209570);
+        assert_eq!(plays[0].album, "A Night at the Opera");
+        assert_eq!(
+            plays[0].uri.as_deref(),
+            Some("spotify:track:4u7EnebtmKWzUH433cf5Qv")
+        );
+    }
+
+    #[test]
+    fn rejects_other_files() {
+        assert!(parsed("other.json", json!({"playlists": []})).is_err());
+        assert!(parsed("other2.json", json!([{"name": "x"}])).is_err());
+        // entries without a track are skipped before their time is read
+        assert!(parsed("bad.json", json!([{"endTime": "yesterday"}])).is_ok());
+        assert!(parsed(
+            "bad2.json",
+            json!([{"endTime": "yesterday", "artistName": "a", "trackName": "b"}])
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn counts_plays_of_30_seconds_or_more() {
+        let play = |track: &str, artist: &str, ms_played: u32| Play {
+            played_at: 0,
+            ms_played,
+            track: track.to_string(),
+            artist: artist.to_string(),
+            album: String::new(),
+            uri: None,
+            estimated: false,
+        };
+        let plays = [
+            play("One", "A", 200_000),
+            play("two", "a", 40_000),
+            play("Two", "A", 30_000),
+            play("One", "A", 29_999),
+            play("One", "B", 100_000),
+        ];
+        let counts = play_counts(plays.iter());
+        let counts = counts
+            .iter()
+            .map(|c| (c.track.as_str(), c.artist.as_str(), c.plays, c.ms_played))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            counts,
+            vec![
+                ("two", "a", 2, 70_000),
+                ("One", "A", 1, 200_000),
+                ("One", "B", 1, 100_000)
+            ]
+        );
+    }
+}
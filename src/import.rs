@@ -0,0 +1,354 @@
+use std::path::Path;
+
+use crate::account::Account;
+use crate::data::{Playlist, Track};
+use crate::matching::{find_match, TrackQuery};
+use crate::{info, user_yn, warn};
+
+/// an entry of an imported file, and where in the file it came from.
+#[derive(Debug, Clone)]
+pub struct Entry {
+    pub line: usize,
+    pub query: TrackQuery,
+}
+
+/// parse a duration written as `m:ss`, `h:mm:ss` or a number of seconds into milliseconds.
+pub(crate) fn parse_duration(s: &str) -> Option<u32> {
+    let s = s.trim();
+    if s.is_empty() {
+        return None;
+    }
+    s.split(':')
+        .try_fold(0_u32, |total, part| {
+            total
+                .checked_mul(60)?
+                .checked_add(part.trim().parse::<u32>().ok()?)
+        })
+        .and_then(|secs| secs.checked_mul(1000))
+}
+
+/// split "Artist - Title" on the first dash between spaces. Lines without one are just a title.
+pub(crate) fn split_artist_title(s: &str) -> (String, String) {
+    for separator in [" – ", " — ", " - "] {
+        if let Some((artist, title)) = s.split_once(separator) {
+            return (artist.trim().to_string(), title.trim().to_string());
+        }
+    }
+    (String::new(), s.trim().to_string())
+}
+
+/// split a line of CSV into fields, as in RFC 4180. Quoted fields can't span lines.
+pub(crate) fn csv_fields(line: &str) -> Vec<String> {
+    let mut fields = Vec::new();
+    let mut field = String::new();
+    let mut quoted = false;
+    let mut chars = line.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if quoted && chars.peek() == Some(&'"') => {
+                field.push('"');
+                chars.next();
+            }
+            '"' => quoted = !quoted,
+            ',' if !quoted => fields.push(std::mem::take(&mut field)),
+            c => field.push(c),
+        }
+    }
+    fields.push(field);
+    fields
+}
+
+/// parse a CSV file with a header row. The columns are found by name, so files exported from
+/// spreadsheets or other services work as long as they have a title column.
+pub fn parse_csv(contents: &str) -> Result<Vec<Entry>, String> {
+    let mut lines = contents.lines().enumerate();
+    let header = lines
+        .next()
+        .map(|(_, l)| csv_fields(l.trim_start_matches('\u{feff}')))
+        .ok_or("The CSV file is empty")?;
+    let column = |names: &[&str]| {
+        header
+            .iter()
+            .position(|h| names.contains(&h.trim().to_lowercase().as_str()))
+    };
+    let title = column(&["title", "name", "track", "track name", "song"])
+        .ok_or("The CSV file needs a title, name or track column")?;
+    let artist = column(&["artist", "artists", "artist name", "artist name(s)"]);
+    let album = column(&["album", "album name"]);
+    let duration = column(&["duration", "length", "time"]);
+    let duration_ms = column(&["duration_ms", "duration (ms)"]);
+    let isrc = column(&["isrc"]);
+    let mut entries = Vec::new();
+    for (i, line) in lines {
+        if line.trim().is_empty() {
+            continue;
+        }
+        let fields = csv_fields(line);
+        let field = |column: Option<usize>| {
+            column
+                .and_then(|c| fields.get(c))
+                .map(|f| f.trim().to_string())
+                .filter(|f| !f.is_empty())
+        };
+        let Some(title) = field(Some(title)) else {
+            warn!("Skipping line {}: it has no title", i + 1);
+            continue;
+        };
+        entries.push(Entry {
+            line: i + 1,
+            query: TrackQuery {
+                title,
+                artist: field(artist).unwrap_or_default(),
+                album: field(album),
+                duration_ms: field(duration_ms)
+                    .and_then(|d| d.parse().ok())
+                    .or_else(|| field(duration).and_then(|d| parse_duration(&d))),
+                isrc: field(isrc),
+            },
+        });
+    }
+    Ok(entries)
+}
+
+/// parse an M3U playlist. Entries take their artist, title and duration from the `#EXTINF` line
+/// before them, or else from the file name, as in "Artist - Title.mp3".
+pub fn parse_m3u(contents: &str) -> Vec<Entry> {
+    let mut entries = Vec::new();
+    let mut extinf = None;
+    for (i, line) in contents.lines().enumerate() {
+        let line = line.trim().trim_start_matches('\u{feff}');
+        if let Some(info) = line.strip_prefix("#EXTINF:") {
+            extinf = Some(match info.split_once(',') {
+                Some((duration, name)) => (
+                    duration
+                        .split_whitespace()
+                        .next()
+                        .and_then(|d| d.parse::<u32>().ok())
+                        .filter(|d| *d > 0)
+                        .and_then(|d| d.checked_mul(1000)),
+                    name.to_string(),
+                ),
+                None => (None, info.to_string()),
+            });
+            continue;
+        }
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let (duration_ms, name) = extinf.take().unwrap_or_else(|| {
+            let file = line.rsplit(['/', '\\']).next().unwrap_or(line);
+            let stem = file.rsplit_once('.').map(|(stem, _)| stem).unwrap_or(file);
+            (None, stem.to_string())
+        });
+        let (artist, title) = split_artist_title(&name);
+        entries.push(Entry {
+            line: i + 1,
+            query: TrackQuery {
+                title,
+                artist,
+                duration_ms,
+                ..TrackQuery::default()
+            },
+        });
+    }
+    entries
+}
+
+/// parse a text file with one "Artist - Title" per line, optionally followed by a duration in
+/// brackets, as in "Artist - Title (3:45)".
+pub fn parse_txt(contents: &str) -> Vec<Entry> {
+    let mut entries = Vec::new();
+    for (i, line) in contents.lines().enumerate() {
+        let mut line = line.trim().trim_start_matches('\u{feff}');
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let mut duration_ms = None;
+        if let Some((rest, last)) = line.rsplit_once(" (") {
+            if let Some(d) = last.strip_suffix(')').and_then(parse_duration) {
+                duration_ms = Some(d);
+                line = rest;
+            }
+        }
+        let (artist, title) = split_artist_title(line);
+        entries.push(Entry {
+            line: i + 1,
+            query: TrackQuery {
+                title,
+                artist,
+                duration_ms,
+                ..TrackQuery::default()
+            },
+        });
+    }
+    entries
+}
+
+/// read the entries of a CSV, M3U or text file, going by its extension.
+pub fn parse_file(path: &Path) -> Result<Vec<Entry>, String> {
+    let contents =
+        std::fs::read_to_string(path).map_err(|e| format!("failed to read {:?}: {}", path, e))?;
+    let extension = path
+        .extension()
+        .and_then(|e| e.to_str())
+        .unwrap_or_default()
+        .to_lowercase();
+    match extension.as_str() {
+        "csv" => parse_csv(&contents),
+        "m3u" | "m3u8" => Ok(parse_m3u(&contents)),
+        _ => Ok(parse_txt(&contents)),
+    }
+}
+
+/// find every entry on Spotify and create a playlist of the ones that were found, in order.
+/// Entries that weren't found are listed at the end.
+pub fn import(
+    acc: &mut Account,
+    name: String,
+    description: String,
+    entries: &[Entry],
+) -> Result<(), String> {
+    let mut tracks: Vec<Track> = Vec::new();
+    let mut unmatched = Vec::new();
+    for (i, entry) in entries.iter().enumerate() {
+        info!("Matching {}... ({}/{})", entry.query, i + 1, entries.len());
+        match find_match(&entry.query, acc)? {
+            Some(candidate) => tracks.extend(candidate.track),
+            None => unmatched.push(entry),
+        }
+    }
+    if !unmatched.is_empty() {
+        warn!("{} entries could not be matched:", unmatched.len());
+        for entry in &unmatched {
+            println!("  line {}: {}", entry.line, entry.query);
+        }
+    }
+    if tracks.is_empty() {
+        return Err("No tracks were matched, so no playlist was created".to_string());
+    }
+    if !user_yn(
+        format!("Create playlist {} with {} tracks?", name, tracks.len()).as_str(),
+        true,
+    ) {
+        return Err("Aborted".to_string());
+    }
+    let count = tracks.len();
+    Playlist::create_from_vec(acc, tracks, name.clone(), Some(description))?;
+    info!(
+        "Imported {} of {} entries into {}",
+        count,
+        entries.len(),
+        name
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// the parts of an entry that parsing fills in, to compare against.
+    fn summary(entry: &Entry) -> (usize, &str, &str, Option<&str>, Option<u32>, Option<&str>) {
+        let q = &entry.query;
+        (
+            entry.line,
+            q.artist.as_str(),
+            q.title.as_str(),
+            q.album.as_deref(),
+            q.duration_ms,
+            q.isrc.as_deref(),
+        )
+    }
+
+    #[test]
+    fn parses_durations() {
+        assert_eq!(parse_duration("215"), Some(215_000));
+        assert_eq!(parse_duration(" 3:35 "), Some(215_000));
+        assert_eq!(parse_duration("1:02:03"), Some(3_723_000));
+        assert_eq!(parse_duration(""), None);
+        assert_eq!(parse_duration("3m35"), None);
+        assert_eq!(parse_duration("3:"), None);
+        assert_eq!(parse_duration("-5"), None);
+        assert_eq!(parse_duration("5000000"), None);
+        assert_eq!(parse_duration("99999999:0:0"), None);
+    }
+
+    #[test]
+    fn splits_csv_fields() {
+        assert_eq!(csv_fields("a,b,,c"), vec!["a", "b", "", "c"]);
+        assert_eq!(
+            csv_fields(r#""Hello, World","say ""hi""",x"#),
+            vec!["Hello, World", r#"say "hi""#, "x"]
+        );
+        assert_eq!(csv_fields(""), vec![""]);
+    }
+
+    #[test]
+    fn parses_csv_by_column_name() {
+        let csv = "\u{feff}Track Name,Artist Name(s),Album Name,Duration (ms),ISRC\n\
+            \"Bohemian Rhapsody\",Queen,A Night at the Opera,354320,GBUM71029604\n\
+            \n\
+            ,Nobody,,,\n\
+            Under Pressure,\"Queen, David Bowie\",,,\n";
+        let entries = parse_csv(csv).unwrap();
+        assert_eq!(
+            entries.iter().map(summary).collect::<Vec<_>>(),
+            vec![
+                (
+                    2,
+                    "Queen",
+                    "Bohemian Rhapsody",
+                    Some("A Night at the Opera"),
+                    Some(354320),
+                    Some("GBUM71029604")
+                ),
+                (5, "Queen, David Bowie", "Under Pressure", None, None, None),
+            ]
+        );
+        let entries = parse_csv("title,length\nSong,3:30\n").unwrap();
+        assert_eq!(
+            summary(&entries[0]),
+            (2, "", "Song", None, Some(210_000), None)
+        );
+        assert!(parse_csv("").is_err());
+        assert!(parse_csv("artist,album\nQueen,Innuendo\n").is_err());
+    }
+
+    #[test]
+    fn parses_m3u() {
+        let m3u = "#EXTM3U\n\
+            #EXTINF:354,Queen - Bohemian Rhapsody\n\
+            music/queen/bohemian.mp3\n\
+            \n\
+            C:\\Music\\David Bowie - Heroes.flac\n\
+            #EXTINF:-1,Radio Stream\n\
+            http://example.com/stream\n";
+        let entries = parse_m3u(m3u);
+        assert_eq!(
+            entries.iter().map(summary).collect::<Vec<_>>(),
+            vec![
+                (3, "Queen", "Bohemian Rhapsody", None, Some(354_000), None),
+                (5, "David Bowie", "Heroes", None, None, None),
+                (7, "", "Radio Stream", None, None, None),
+            ]
+        );
+    }
+
+    #[test]
+    fn parses_txt() {
+        let txt = "# my list\n\
+            Queen - Bohemian Rhapsody (5:54)\n\
+            \n\
+            Sigur Rós – Hoppípolla\n\
+            Song (Live) (not a duration)\n";
+        let entries = parse_txt(txt);
+        assert_eq!(
+            entries.iter().map(summary).collect::<Vec<_>>(),
+            vec![
+                (2, "Queen", "Bohemian Rhapsody", None, Some(354_000), None),
+                (4, "Sigur Rós", "Hoppípolla", None, None, None),
+                (5, "", "Song (Live) (not a duration)", None, None, None),
+            ]
+        );
+    }
+}
@@ -0,0 +1,535 @@
+use std::collections::HashMap;
+use std::fmt::{Display, Formatter};
+use std::path::Path;
+
+use crossterm::style::Stylize;
+
+use crate::account::Account;
+use crate::data::{Playlist, Track};
+use crate::matching::{find_match, Candidate, TrackQuery, CONFIDENT_SCORE};
+use crate::{info, user_choose_multi, user_yn, warn};
+
+/// the parts of a property list that the iTunes library uses.
+#[derive(Debug, Clone)]
+enum Plist {
+    Dict(Vec<(String, Plist)>),
+    Array(Vec<Plist>),
+    String(String),
+    Integer(i64),
+    Bool(bool),
+    /// reals, dates and data, which aren't needed.
+    Other,
+}
+
+impl Plist {
+    fn get(&self, key: &str) -> Option<&Plist> {
+        match self {
+            Plist::Dict(entries) => entries.iter().find(|(k, _)| k == key).map(|(_, v)| v),
+            _ => None,
+        }
+    }
+    fn as_str(&self) -> Option<&str> {
+        match self {
+            Plist::String(s) => Some(s),
+            _ => None,
+        }
+    }
+    fn as_int(&self) -> Option<i64> {
+        match self {
+            Plist::Integer(i) => Some(*i),
+            _ => None,
+        }
+    }
+    fn as_bool(&self) -> bool {
+        matches!(self, Plist::Bool(true))
+    }
+    fn as_array(&self) -> &[Plist] {
+        match self {
+            Plist::Array(items) => items,
+            _ => &[],
+        }
+    }
+}
+
+/// replace the XML entities in text.
+fn unescape(s: &str) -> String {
+    let mut out = String::new();
+    let mut rest = s;
+    while let Some(i) = rest.find('&') {
+        out.push_str(&rest[..i]);
+        rest = &rest[i..];
+        let Some(end) = rest.find(';') else {
+            break;
+        };
+        let entity = &rest[1..end];
+        let c = match entity {
+            "amp" => Some('&'),
+            "lt" => Some('<'),
+            "gt" => Some('>'),
+            "quot" => Some('"'),
+            "apos" => Some('\''),
+            _ => entity
+                .strip_prefix("#x")
+                .map(|hex| u32::from_str_radix(hex, 16))
+                .or_else(|| entity.strip_prefix('#').map(|dec| dec.parse::<u32>()))
+                .and_then(|n| n.ok())
+                .and_then(char::from_u32),
+        };
+        match c {
+            Some(c) => {
+                out.push(c);
+                rest = &rest[end + 1..];
+            }
+            None => {
+                out.push('&');
+                rest = &rest[1..];
+            }
+        }
+    }
+    out.push_str(rest);
+    out
+}
+
+/// a tag in the XML, e.g. `<dict>`, `</dict>` or `<true/>`.
+struct Tag<'a> {
+    name: &'a str,
+    closing: bool,
+    empty: bool,
+}
+
+/// reads a property list one element at a time. Only the elements plists use are understood,
+/// and attributes are ignored.
+struct PlistParser<'a> {
+    xml: &'a str,
+    pos: usize,
+}
+
+impl<'a> PlistParser<'a> {
+    /// the next tag, skipping the text before it, declarations and comments.
+    fn next_tag(&mut self) -> Result<Tag<'a>, String> {
+        loop {
+            let start = self.xml[self.pos..]
+                .find('<')
+                .map(|i| self.pos + i)
+                .ok_or("unexpected end of file")?;
+            let rest = &self.xml[start..];
+            let end_marker = if rest.starts_with("<!--") { "-->" } else { ">" };
+            let end = rest
+                .find(end_marker)
+                .map(|i| start + i + end_marker.len())
+                .ok_or("unexpected end of file")?;
+            self.pos = end;
+            if rest.starts_with("<?") || rest.starts_with("<!") {
+                continue;
+            }
+            let inner = &self.xml[start + 1..end - 1];
+            let empty = inner.ends_with('/');
+            let inner = inner.trim_end_matches('/');
+            let closing = inner.starts_with('/');
+            let name = inner
+                .trim_start_matches('/')
+                .split_whitespace()
+                .next()
+                .unwrap_or_default();
+            return Ok(Tag {
+                name,
+                closing,
+                empty,
+            });
+        }
+    }
+
+    /// the text up to the closing tag of the element that was just opened.
+    fn text(&mut self, name: &str) -> Result<String, String> {
+        let close = format!("</{}>", name);
+        let end = self.xml[self.pos..]
+            .find(&close)
+            .map(|i| self.pos + i)
+            .ok_or(format!("missing {}", close))?;
+        let text = unescape(&self.xml[self.pos..end]);
+        self.pos = end + close.len();
+        Ok(text)
+    }
+
+    /// the value starting at the given tag, which has just been read.
+    fn value(&mut self, tag: Tag) -> Result<Plist, String> {
+        match (tag.name, tag.empty) {
+            ("dict", true) => Ok(Plist::Dict(Vec::new())),
+            ("array", true) => Ok(Plist::Array(Vec::new())),
+            ("string", true) => Ok(Plist::String(String::new())),
+            ("true", _) => Ok(Plist::Bool(true)),
+            ("false", _) => Ok(Plist::Bool(false)),
+            (_, true) => Ok(Plist::Other),
+            ("dict", false) => {
+                let mut entries = Vec::new();
+                loop {
+                    let tag = self.next_tag()?;
+                    if tag.closing {
+                        return Ok(Plist::Dict(entries));
+                    }
+                    if tag.name != "key" {
+                        return Err(format!("expected a key in dict, got <{}>", tag.name));
+                    }
+                    let key = self.text("key")?;
+                    let tag = self.next_tag()?;
+                    entries.push((key, self.value(tag)?));
+                }
+            }
+            ("array", false) => {
+                let mut items = Vec::new();
+                loop {
+                    let tag = self.next_tag()?;
+                    if tag.closing {
+                        return Ok(Plist::Array(items));
+                    }
+                    items.push(self.value(tag)?);
+                }
+            }
+            ("string", false) => Ok(Plist::String(self.text("string")?)),
+            ("integer", false) => {
+                let text = self.text("integer")?;
+                Ok(Plist::Integer(
+                    text.trim()
+                        .parse()
+                        .map_err(|_| format!("invalid integer: {}", text))?,
+                ))
+            }
+            (name, false) => {
+                self.text(name)?;
+                Ok(Plist::Other)
+            }
+        }
+    }
+
+    fn parse(xml: &'a str) -> Result<Plist, String> {
+        let mut parser = PlistParser { xml, pos: 0 };
+        let mut tag = parser.next_tag()?;
+        if tag.name == "plist" {
+            tag = parser.next_tag()?;
+        }
+        parser
+            .value(tag)
+            .map_err(|e| format!("invalid iTunes library: {}", e))
+    }
+}
+
+/// a playlist from the iTunes library, with the tracks it has, in order.
+#[derive(Debug, Clone)]
+pub struct ItunesPlaylist {
+    pub name: String,
+    pub description: String,
+    pub tracks: Vec<(i64, TrackQuery)>,
+}
+
+impl Display for ItunesPlaylist {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&format!(
+            "{} ({} tracks)",
+            self.name.as_str().blue().bold(),
+            self.tracks.len()
+        ))
+    }
+}
+
+/// read the playlists from an iTunes or Apple Music `Library.xml`. The library itself, the
+/// built-in playlists like "Music" and folders are left out.
+pub fn parse_library(path: &Path) -> Result<Vec<ItunesPlaylist>, String> {
+    let xml =
+        std::fs::read_to_string(path).map_err(|e| format!("failed to read {:?}: {}", path, e))?;
+    parse_library_xml(&xml)
+}
+
+fn parse_library_xml(xml: &str) -> Result<Vec<ItunesPlaylist>, String> {
+    let library = PlistParser::parse(xml)?;
+    let mut tracks = HashMap::new();
+    if let Some(Plist::Dict(entries)) = library.get("Tracks") {
+        for (_, track) in entries {
+            let Some(id) = track.get("Track ID").and_then(|i| i.as_int()) else {
+                continue;
+            };
+            let field = |key: &str| {
+                track
+                    .get(key)
+                    .and_then(|v| v.as_str())
+                    .map(|s| s.to_string())
+            };
+            tracks.insert(
+                id,
+                TrackQuery {
+                    title: field("Name").unwrap_or_default(),
+                    artist: field("Artist")
+                        .or_else(|| field("Album Artist"))
+                        .unwrap_or_default(),
+                    album: field("Album"),
+                    duration_ms: track
+                        .get("Total Time")
+                        .and_then(|t| t.as_int())
+                        .and_then(|t| u32::try_from(t).ok()),
+                    isrc: None,
+                },
+            );
+        }
+    }
+    let mut playlists = Vec::new();
+    for playlist in library
+        .get("Playlists")
+        .map(|p| p.as_array())
+        .unwrap_or(&[])
+    {
+        let flag = |key: &str| playlist.get(key).map(|v| v.as_bool()).unwrap_or(false);
+        if flag("Master") || flag("Folder") || playlist.get("Distinguished Kind").is_some() {
+            continue;
+        }
+        playlists.push(ItunesPlaylist {
+            name: playlist
+                .get("Name")
+                .and_then(|n| n.as_str())
+                .unwrap_or("Untitled")
+                .to_string(),
+            // Spotify descriptions are one line of at most 300 characters
+            description: playlist
+                .get("Description")
+                .and_then(|d| d.as_str())
+                .unwrap_or_default()
+                .split_whitespace()
+                .collect::<Vec<&str>>()
+                .join(" ")
+                .chars()
+                .take(300)
+                .collect(),
+            tracks: playlist
+                .get("Playlist Items")
+                .map(|items| items.as_array())
+                .unwrap_or(&[])
+                .iter()
+                .filter_map(|item| item.get("Track ID").and_then(|i| i.as_int()))
+                .filter_map(|id| tracks.get(&id).map(|t| (id, t.clone())))
+                .filter(|(_, t)| !t.title.is_empty())
+                .collect(),
+        });
+    }
+    Ok(playlists)
+}
+
+/// how a playlist's tracks were matched, for the report at the end.
+#[derive(Default)]
+struct Report {
+    confident: usize,
+    chosen: Vec<(TrackQuery, Candidate)>,
+    unmatched: Vec<TrackQuery>,
+}
+
+/// let the user choose playlists from the library, match their tracks on Spotify and recreate them
+/// on the given account. Tracks in several playlists are only matched once.
+pub fn import_library(acc: &mut Account, playlists: Vec<ItunesPlaylist>) -> Result<(), String> {
+    let playlists = user_choose_multi("Choose the playlists to import", playlists)?;
+    let mut matches: HashMap<i64, Option<Candidate>> = HashMap::new();
+    let mut reports = Vec::new();
+    for playlist in &playlists {
+        info!("Matching the tracks of {}...", playlist.name);
+        let mut report = Report::default();
+        let mut tracks: Vec<Track> = Vec::new();
+        for (i, (id, query)) in playlist.tracks.iter().enumerate() {
+            let matched = match matches.get(id) {
+                Some(matched) => matched.clone(),
+                None => {
+                    info!(
+                        "Matching {}... ({}/{})",
+                        query,
+                        i + 1,
+                        playlist.tracks.len()
+                    );
+                    let matched = find_match(query, acc)?;
+                    matches.insert(*id, matched.clone());
+                    matched
+                }
+            };
+            match matched {
+                Some(candidate) => {
+                    if candidate.score < CONFIDENT_SCORE {
+                        report.chosen.push((query.clone(), candidate.clone()));
+                    } else {
+                        report.confident += 1;
+                    }
+                    tracks.extend(candidate.track);
+                }
+                None => report.unmatched.push(query.clone()),
+            }
+        }
+        reports.push((playlist, tracks, report));
+    }
+
+    for (playlist, tracks, report) in &reports {
+        println!(
+            "{}: {} of {} tracks matched, {} confidently, {} with lower confidence",
+            playlist.name.as_str().blue().bold(),
+            tracks.len(),
+            playlist.tracks.len(),
+            report.confident,
+            report.chosen.len()
+        );
+        for (query, candidate) in &report.chosen {
+            if let Some(track) = &candidate.track {
+                println!("  {:.0}% {} -> {}", candidate.score * 100.0, query, track);
+            }
+        }
+        for query in &report.unmatched {
+            println!("  {} {}", "unmatched".red(), query);
+        }
+    }
+    if !user_yn(
+        format!("Create these {} playlists?", reports.len()).as_str(),
+        true,
+    ) {
+        return Err("Aborted".to_string());
+    }
+    for (playlist, tracks, _) in reports {
+        if tracks.is_empty() {
+            warn!(
+                "Skipping {}: none of its tracks were matched",
+                playlist.name
+            );
+            continue;
+        }
+        info!("Creating playlist {}", playlist.name);
+        Playlist::create_from_vec(
+            acc,
+            tracks,
+            playlist.name.clone(),
+            Some(if playlist.description.is_empty() {
+                "Imported from iTunes by spotimine".to_string()
+            } else {
+                playlist.description.clone()
+            }),
+        )?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn unescapes_entities() {
+        assert_eq!(unescape("Simon &amp; Garfunkel"), "Simon & Garfunkel");
+        assert_eq!(unescape("Simon &#38; Garfunkel"), "Simon & Garfunkel");
+        assert_eq!(
+            unescape("&lt;b&gt; &quot;x&quot; &apos;y&apos;"),
+            "<b> \"x\" 'y'"
+        );
+        assert_eq!(unescape("Sigur R&#xF3;s"), "Sigur Rós");
+        // anything that isn't an entity is left as it is
+        assert_eq!(unescape("R&B; &nope; a & b &"), "R&B; &nope; a & b &");
+    }
+
+    #[test]
+    fn parses_values() {
+        let xml = r#"<?xml version="1.0" encoding="UTF-8"?>
+<!DOCTYPE plist PUBLIC "-//Apple//DTD PLIST 1.0//EN" "http://www.apple.com/DTDs/PropertyList-1.0.dtd">
+<plist version="1.0">
+<!-- a <comment> with tags in it -->
+<dict>
+	<key>Yes</key><true/>
+	<key>No</key><false/>
+	<key>Empty</key><string/>
+	<key>Number</key><integer> -42 </integer>
+	<key>Date</key><date>2023-01-31T23:59:00Z</date>
+	<key>Nested</key>
+	<array>
+		<dict><key>Name</key><string>A &amp; B</string></dict>
+		<array/>
+		<dict/>
+	</array>
+</dict>
+</plist>"#;
+        let plist = PlistParser::parse(xml).unwrap();
+        assert!(plist.get("Yes").unwrap().as_bool());
+        assert!(matches!(plist.get("No"), Some(Plist::Bool(false))));
+        assert_eq!(plist.get("Empty").and_then(|s| s.as_str()), Some(""));
+        assert_eq!(plist.get("Number").and_then(|i| i.as_int()), Some(-42));
+        assert!(matches!(plist.get("Date"), Some(Plist::Other)));
+        let nested = plist.get("Nested").unwrap().as_array();
+        assert_eq!(nested.len(), 3);
+        assert_eq!(
+            nested[0].get("Name").and_then(|n| n.as_str()),
+            Some("A & B")
+        );
+        assert!(nested[1].as_array().is_empty());
+        assert!(matches!(&nested[2], Plist::Dict(entries) if entries.is_empty()));
+    }
+
+    #[test]
+    fn rejects_invalid_plists() {
+        for xml in [
+            "",
+            "<plist><dict><key>a</key>",
+            "<dict><string>no key</string></dict>",
+            "<dict><key>n</key><integer>one</integer></dict>",
+            "<dict><key>s</key><string>unclosed</dict>",
+        ] {
+            assert!(
+                PlistParser::parse(xml).is_err(),
+                "{} should be rejected",
+                xml
+            );
+        }
+    }
+
+    #[test]
+    fn parses_library() {
+        let xml = r#"<plist version="1.0"><dict>
+<key>Tracks</key>
+<dict>
+	<key>1</key>
+	<dict><key>Track ID</key><integer>1</integer><key>Name</key><string>Heroes</string>
+	<key>Artist</key><string>David Bowie</string><key>Album</key><string>Heroes</string>
+	<key>Total Time</key><integer>371000</integer></dict>
+	<key>2</key>
+	<dict><key>Track ID</key><integer>2</integer><key>Name</key><string>Innuendo</string>
+	<key>Album Artist</key><string>Queen</string>
+	<key>Total Time</key><integer>-1</integer></dict>
+	<key>3</key>
+	<dict><key>Track ID</key><integer>3</integer><key>Name</key><string>Long</string>
+	<key>Total Time</key><integer>99999999999</integer></dict>
+	<key>4</key>
+	<dict><key>Track ID</key><integer>4</integer></dict>
+</dict>
+<key>Playlists</key>
+<array>
+	<dict><key>Name</key><string>Library</string><key>Master</key><true/>
+	<key>Playlist Items</key><array><dict><key>Track ID</key><integer>1</integer></dict></array></dict>
+	<dict><key>Name</key><string>Music</string><key>Distinguished Kind</key><integer>4</integer></dict>
+	<dict><key>Name</key><string>Folder</string><key>Folder</key><true/></dict>
+	<dict><key>Name</key><string>Mix</string><key>Master</key><false/>
+	<key>Description</key><string>Line one
+	line two</string>
+	<key>Playlist Items</key><array>
+		<dict><key>Track ID</key><integer>2</integer></dict>
+		<dict><key>Track ID</key><integer>1</integer></dict>
+		<dict><key>Track ID</key><integer>4</integer></dict>
+		<dict><key>Track ID</key><integer>9</integer></dict>
+		<dict><key>Track ID</key><integer>3</integer></dict>
+	</array></dict>
+</array>
+</dict></plist>"#;
+        let playlists = parse_library_xml(xml).unwrap();
+        assert_eq!(playlists.len(), 1);
+        let mix = &playlists[0];
+        assert_eq!(mix.name, "Mix");
+        assert_eq!(mix.description, "Line one line two");
+        let tracks = mix
+            .tracks
+            .iter()
+            .map(|(id, q)| (*id, q.artist.as_str(), q.title.as_str(), q.duration_ms))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            tracks,
+            vec![
+                (2, "Queen", "Innuendo", None),
+                (1, "David Bowie", "Heroes", Some(371000)),
+                (3, "", "Long", None),
+            ]
+        );
+    }
+}
@@ -0,0 +1,342 @@
+use std::collections::HashMap;
+use std::path::Path;
+
+use serde_json::{Map, Value};
+
+use crate::account::Account;
+use crate::data::{Content, Track};
+use crate::import::csv_fields;
+use crate::matching::{find_match_cached, MatchCache, TrackQuery};
+use crate::{info, warn};
+
+/// one scrobble from a Last.fm export.
+#[derive(Debug, Clone)]
+pub struct Scrobble {
+    pub artist: String,
+    pub track: String,
+    pub album: String,
+    /// whether the track is loved. Only JSON exports of loved or extended recent tracks have this.
+    pub loved: bool,
+}
+
+/// how often one track was scrobbled.
+#[derive(Debug, Clone)]
+pub struct ScrobbleCount {
+    pub artist: String,
+    pub track: String,
+    pub album: String,
+    pub scrobbles: usize,
+    pub loved: bool,
+}
+
+impl ScrobbleCount {
+    fn query(&self) -> TrackQuery {
+        TrackQuery {
+            title: self.track.clone(),
+            artist: self.artist.clone(),
+            album: Some(self.album.clone()).filter(|a| !a.is_empty()),
+            ..TrackQuery::default()
+        }
+    }
+}
+
+/// parse a CSV export. Files with a header row have their columns found by name; files without
+/// one are read as `artist,album,track,date`, the format of most Last.fm export tools.
+pub fn parse_csv(contents: &str) -> Result<Vec<Scrobble>, String> {
+    let mut lines = contents
+        .lines()
+        .map(|l| l.trim_start_matches('\u{feff}'))
+        .filter(|l| !l.trim().is_empty())
+        .peekable();
+    let first = csv_fields(lines.peek().ok_or("The CSV file is empty")?)
+        .iter()
+        .map(|f| f.trim().to_lowercase())
+        .collect::<Vec<String>>();
+    let column = |names: &[&str]| first.iter().position(|h| names.contains(&h.as_str()));
+    let (artist, album, track, loved) = match (
+        column(&["artist", "artist name"]),
+        column(&["track", "name", "title"]),
+    ) {
+        (Some(artist), Some(track)) => {
+            lines.next();
+            (
+                artist,
+                column(&["album", "album name"]),
+                track,
+                column(&["loved"]),
+            )
+        }
+        _ => (0, Some(1), 2, None),
+    };
+    let mut scrobbles = Vec::new();
+    for line in lines {
+        let fields = csv_fields(line);
+        let field = |column: Option<usize>| {
+            column
+                .and_then(|c| fields.get(c))
+                .map(|f| f.trim().to_string())
+                .unwrap_or_default()
+        };
+        let scrobble = Scrobble {
+            artist: field(Some(artist)),
+            track: field(Some(track)),
+            album: field(album),
+            loved: matches!(field(loved).as_str(), "1" | "true"),
+        };
+        if scrobble.artist.is_empty() || scrobble.track.is_empty() {
+            continue;
+        }
+        scrobbles.push(scrobble);
+    }
+    Ok(scrobbles)
+}
+
+/// the text of a field, which the Last.fm API gives as a string, `{"#text": ...}` or
+/// `{"name": ...}` depending on the endpoint.
+fn text(value: &Value) -> String {
+    value
+        .as_str()
+        .or_else(|| value["#text"].as_str())
+        .or_else(|| value["name"].as_str())
+        .unwrap_or_default()
+        .to_string()
+}
+
+fn is_scrobble(object: &Map<String, Value>) -> bool {
+    object.contains_key("artist")
+        && (object.get("name").is_some_and(|n| n.is_string())
+            || object.get("track").is_some_and(|t| t.is_string()))
+}
+
+/// collect the scrobbles anywhere in the JSON. Exports are usually the pages of
+/// `user.getRecentTracks` or `user.getLovedTracks` as the API returned them, but flat lists of
+/// scrobbles work too.
+fn collect_json(value: &Value, loved: bool, scrobbles: &mut Vec<Scrobble>) {
+    match value {
+        Value::Array(items) => {
+            for item in items {
+                collect_json(item, loved, scrobbles);
+            }
+        }
+        Value::Object(object) if is_scrobble(object) => {
+            // the track that's playing right now shows up on the first page, but isn't a scrobble
+            if value["@attr"]["nowplaying"].as_str() == Some("true") {
+                return;
+            }
+            let track = object.get("name").or(object.get("track")).map(text);
+            scrobbles.push(Scrobble {
+                artist: text(&value["artist"]),
+                track: track.unwrap_or_default(),
+                album: text(&value["album"]),
+                loved: loved
+                    || value["loved"].as_str() == Some("1")
+                    || value["loved"].as_bool() == Some(true),
+            });
+        }
+        Value::Object(object) => {
+            for (key, value) in object {
+                if key != "@attr" {
+                    collect_json(value, loved || key == "lovedtracks", scrobbles);
+                }
+            }
+        }
+        _ => (),
+    }
+}
+
+/// read the scrobbles from a CSV or JSON export, going by its extension.
+pub fn parse_file(path: &Path) -> Result<Vec<Scrobble>, String> {
+    let contents =
+        std::fs::read_to_string(path).map_err(|e| format!("failed to read {:?}: {}", path, e))?;
+    let extension = path
+        .extension()
+        .and_then(|e| e.to_str())
+        .unwrap_or_default()
+        .to_lowercase();
+    let mut scrobbles = match extension.as_str() {
+        "json" => {
+            let json: Value = serde_json::from_str(&contents)
+                .map_err(|e| format!("failed to parse {:?}: {}", path, e))?;
+            let mut scrobbles = Vec::new();
+            collect_json(&json, false, &mut scrobbles);
+            scrobbles
+        }
+        _ => parse_csv(&contents)?,
+    };
+    scrobbles.retain(|s| !s.artist.is_empty() && !s.track.is_empty());
+    if scrobbles.is_empty() {
+        return Err(format!("{:?} has no scrobbles", path));
+    }
+    Ok(scrobbles)
+}
+
+/// count the scrobbles of each track, most scrobbled first. A track is loved if any of its
+/// scrobbles say so.
+pub fn scrobble_counts(scrobbles: &[Scrobble]) -> Vec<ScrobbleCount> {
+    let mut counts: HashMap<(String, String), ScrobbleCount> = HashMap::new();
+    for scrobble in scrobbles {
+        let count = counts
+            .entry((
+                scrobble.artist.to_lowercase(),
+                scrobble.track.to_lowercase(),
+            ))
+            .or_insert_with(|| ScrobbleCount {
+                artist: scrobble.artist.clone(),
+                track: scrobble.track.clone(),
+                album: scrobble.album.clone(),
+                scrobbles: 0,
+                loved: false,
+            });
+        count.scrobbles += 1;
+        count.loved |= scrobble.loved;
+        if count.album.is_empty() {
+            count.album = scrobble.album.clone();
+        }
+    }
+    let mut counts = counts.into_values().collect::<Vec<ScrobbleCount>>();
+    counts.sort_by(|a, b| {
+        b.scrobbles
+            .cmp(&a.scrobbles)
+            .then(a.artist.cmp(&b.artist))
+            .then(a.track.cmp(&b.track))
+    });
+    counts
+}
+
+/// find the Spotify tracks for the given counts, in order. Earlier matches are taken from the
+/// cache, so only tracks that were never matched before are searched for.
+pub fn resolve(acc: &mut Account, counts: &[ScrobbleCount]) -> Result<Vec<Track>, String> {
+    let mut cache = MatchCache::load()?;
+    let mut uris = Vec::new();
+    let mut unresolved = Vec::new();
+    for (i, count) in counts.iter().enumerate() {
+        let query = count.query();
+        if cache.get(&query).is_none() {
+            info!("Matching {}... ({}/{})", query, i + 1, counts.len());
+        }
+        match find_match_cached(&query, acc, &mut cache)? {
+            Some(uri) => uris.push(uri),
+            None => unresolved.push(query),
+        }
+    }
+    cache.save()?;
+    if !unresolved.is_empty() {
+        warn!("{} tracks could not be found on Spotify:", unresolved.len());
+        for query in unresolved {
+            println!("  {}", query);
+        }
+        info!("Tracks with no match aren't searched for again. Use 'matches forget-unmatched' to retry them");
+    }
+    let ids = uris
+        .iter()
+        .map(|uri| uri.rsplit(':').next().unwrap_or_default())
+        .collect::<Vec<&str>>();
+    Track::from_ids(&ids, acc)
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    fn summary(scrobbles: &[Scrobble]) -> Vec<(&str, &str, &str, bool)> {
+        scrobbles
+            .iter()
+            .map(|s| {
+                (
+                    s.artist.as_str(),
+                    s.track.as_str(),
+                    s.album.as_str(),
+                    s.loved,
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn parses_csv_without_header() {
+        let csv = "Queen,A Night at the Opera,Bohemian Rhapsody,31 Jan 2023 23:59\n\
+            \"Bowie, David\",Heroes,Heroes,01 Feb 2023 00:05\n\
+            Nobody,,,\n";
+        assert_eq!(
+            summary(&parse_csv(csv).unwrap()),
+            vec![
+                ("Queen", "Bohemian Rhapsody", "A Night at the Opera", false),
+                ("Bowie, David", "Heroes", "Heroes", false),
+            ]
+        );
+    }
+
+    #[test]
+    fn parses_csv_with_header() {
+        let csv = "\u{feff}uts,utc_time,artist,album,track,loved\n\
+            1675209540,31 Jan 2023,Queen,,Bohemian Rhapsody,1\n\
+            1675209900,01 Feb 2023,Queen,Innuendo,Innuendo,0\n";
+        assert_eq!(
+            summary(&parse_csv(csv).unwrap()),
+            vec![
+                ("Queen", "Bohemian Rhapsody", "", true),
+                ("Queen", "Innuendo", "Innuendo", false),
+            ]
+        );
+        assert!(parse_csv("").is_err());
+    }
+
+    #[test]
+    fn collects_scrobbles_from_api_pages() {
+        let pages = json!([
+            {"recenttracks": {"track": [
+                {"artist": {"#text": "Queen"}, "name": "Under Pressure",
+                 "album": {"#text": "Hot Space"}, "@attr": {"nowplaying": "true"}},
+                {"artist": {"#text": "Queen"}, "name": "Bohemian Rhapsody",
+                 "album": {"#text": "A Night at the Opera"}, "date": {"uts": "1675209540"}},
+                {"artist": {"name": "Queen"}, "name": "Innuendo", "loved": "1"}
+            ], "@attr": {"page": "1", "totalPages": "1"}}},
+            {"lovedtracks": {"track": [
+                {"artist": {"name": "David Bowie"}, "name": "Heroes"}
+            ]}},
+            [{"artist": "Sigur Rós", "track": "Hoppípolla"}]
+        ]);
+        let mut scrobbles = Vec::new();
+        collect_json(&pages, false, &mut scrobbles);
+        assert_eq!(
+            summary(&scrobbles),
+            vec![
+                ("Queen", "Bohemian Rhapsody", "A Night at the Opera", false),
+                ("Queen", "Innuendo", "", true),
+                ("David Bowie", "Heroes", "", true),
+                ("Sigur Rós", "Hoppípolla", "", false),
+            ]
+        );
+    }
+
+    #[test]
+    fn counts_scrobbles_of_each_track() {
+        let scrobble = |artist: &str, track: &str, album: &str, loved: bool| Scrobble {
+            artist: artist.to_string(),
+            track: track.to_string(),
+            album: album.to_string(),
+            loved,
+        };
+        let counts = scrobble_counts(&[
+            scrobble("Queen", "Innuendo", "", false),
+            scrobble("Bowie", "Heroes", "", false),
+            scrobble("queen", "innuendo", "Innuendo", true),
+            scrobble("Abba", "SOS", "", false),
+            scrobble("Bowie", "Heroes", "", false),
+        ]);
+        let counts = counts
+            .iter()
+            .map(|c| (c.artist.as_str(), c.album.as_str(), c.scrobbles, c.loved))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            counts,
+            vec![
+                ("Bowie", "", 2, false),
+                ("Queen", "Innuendo", 2, true),
+                ("Abba", "", 1, false),
+            ]
+        );
+    }
+}
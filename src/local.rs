@@ -0,0 +1,327 @@
+use std::collections::HashMap;
+use std::fmt::{Display, Formatter};
+use std::fs::File;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+
+use serde::{Deserialize, Serialize};
+
+use crate::account::Account;
+use crate::backup::{read_json, write_json};
+use crate::config::config_dir;
+use crate::data::{Content, Playlist, Stringify, Track};
+use crate::import::split_artist_title;
+use crate::matching::{
+    find_match_cached, normalize, score, MatchCache, TrackQuery, CONFIDENT_SCORE,
+};
+use crate::tags::{read_tags, EXTENSIONS};
+use crate::{info, user_yn, warn};
+
+/// a local audio file and its tags.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LocalTrack {
+    pub path: PathBuf,
+    /// unix time the file was last modified at, to know when to read its tags again.
+    pub modified: u64,
+    pub title: String,
+    pub artist: String,
+    #[serde(default)]
+    pub album: String,
+    #[serde(default)]
+    pub duration_ms: Option<u32>,
+    #[serde(default)]
+    pub isrc: Option<String>,
+}
+
+impl LocalTrack {
+    fn query(&self) -> TrackQuery {
+        TrackQuery {
+            title: self.title.clone(),
+            artist: self.artist.clone(),
+            album: Some(self.album.clone()).filter(|a| !a.is_empty()),
+            duration_ms: self.duration_ms,
+            isrc: self.isrc.clone(),
+        }
+    }
+}
+
+impl Display for LocalTrack {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&format!("{} ({})", self.query(), self.path.display()))
+    }
+}
+
+/// every local audio file that was scanned, sorted by path.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct LocalIndex {
+    pub tracks: Vec<LocalTrack>,
+}
+
+/// what a scan found.
+#[derive(Debug, Default)]
+pub struct ScanSummary {
+    pub files: usize,
+    /// files that were new or changed since the last scan, so their tags were read.
+    pub read: usize,
+    /// files and directories that couldn't be read.
+    pub failed: usize,
+    /// files that were in the index, but don't exist anymore.
+    pub removed: usize,
+}
+
+impl LocalIndex {
+    fn path() -> Result<PathBuf, String> {
+        Ok(config_dir()?.join("local.json"))
+    }
+
+    pub fn load() -> Result<LocalIndex, String> {
+        let path = LocalIndex::path()?;
+        if !path.exists() {
+            return Ok(LocalIndex::default());
+        }
+        read_json(&path)
+    }
+
+    pub fn save(&self) -> Result<(), String> {
+        write_json(&LocalIndex::path()?, self)
+    }
+
+    /// the scanned files in the given directory and the directories in it.
+    pub fn in_dir(&self, dir: &Path) -> Vec<&LocalTrack> {
+        self.tracks
+            .iter()
+            .filter(|t| t.path.starts_with(dir))
+            .collect()
+    }
+
+    /// scan the given directory for audio files and add them to the index. Files that haven't
+    /// changed since they were last scanned are not read again.
+    pub fn scan(&mut self, dir: &Path) -> Result<ScanSummary, String> {
+        let mut known = std::mem::take(&mut self.tracks)
+            .into_iter()
+            .map(|t| (t.path.clone(), t))
+            .collect::<HashMap<PathBuf, LocalTrack>>();
+        let mut files = Vec::new();
+        let mut failed = 0;
+        audio_files(dir, &mut files, &mut failed)?;
+        let mut summary = ScanSummary {
+            files: files.len(),
+            failed,
+            ..ScanSummary::default()
+        };
+        let mut scanned = Vec::new();
+        for (i, path) in files.into_iter().enumerate() {
+            let modified = std::fs::metadata(&path)
+                .and_then(|m| m.modified())
+                .ok()
+                .and_then(|m| m.duration_since(UNIX_EPOCH).ok())
+                .map(|d| d.as_secs())
+                .unwrap_or(0);
+            if let Some(track) = known.remove(&path).filter(|t| t.modified == modified) {
+                scanned.push(track);
+                continue;
+            }
+            if (i + 1) % 100 == 0 {
+                info!("Scanned {} of {} files...", i + 1, summary.files);
+            }
+            match local_track(&path, modified) {
+                Ok(track) => {
+                    scanned.push(track);
+                    summary.read += 1;
+                }
+                Err(e) => {
+                    warn!("{}", e);
+                    summary.failed += 1;
+                }
+            }
+        }
+        // files outside the scanned directory are kept, the ones inside it are gone
+        let (gone, kept): (Vec<LocalTrack>, Vec<LocalTrack>) =
+            known.into_values().partition(|t| t.path.starts_with(dir));
+        summary.removed = gone.len();
+        self.tracks = kept;
+        self.tracks.append(&mut scanned);
+        self.tracks.sort_by(|a, b| a.path.cmp(&b.path));
+        Ok(summary)
+    }
+}
+
+/// collect the audio files in the directory and the directories in it. Directories in it that can't
+/// be read are counted as failed. Symlinked directories are skipped, as they can link back to a
+/// directory they are in.
+fn audio_files(dir: &Path, files: &mut Vec<PathBuf>, failed: &mut usize) -> Result<(), String> {
+    for entry in std::fs::read_dir(dir).map_err(|e| format!("failed to read {:?}: {}", dir, e))? {
+        let entry = match entry {
+            Ok(entry) => entry,
+            Err(e) => {
+                warn!("failed to read {:?}: {}", dir, e);
+                *failed += 1;
+                continue;
+            }
+        };
+        let path = entry.path();
+        // unlike `Path::is_dir`, this doesn't follow symlinks
+        if entry.file_type().is_ok_and(|t| t.is_dir()) {
+            if let Err(e) = audio_files(&path, files, failed) {
+                warn!("{}", e);
+                *failed += 1;
+            }
+        } else if path
+            .extension()
+            .and_then(|e| e.to_str())
+            .is_some_and(|e| EXTENSIONS.contains(&e.to_lowercase().as_str()))
+        {
+            files.push(path);
+        }
+    }
+    Ok(())
+}
+
+/// read a file's tags. Files without a title are named like "Artist - Title.mp3".
+fn local_track(path: &Path, modified: u64) -> Result<LocalTrack, String> {
+    let tags = read_tags(path)?;
+    let (file_artist, file_title) = split_artist_title(
+        path.file_stem()
+            .and_then(|s| s.to_str())
+            .unwrap_or_default(),
+    );
+    Ok(LocalTrack {
+        path: path.to_path_buf(),
+        modified,
+        title: tags.title.unwrap_or(file_title),
+        artist: tags.artist.unwrap_or(file_artist),
+        album: tags.album.unwrap_or_default(),
+        duration_ms: tags.duration_ms,
+        isrc: tags.isrc,
+    })
+}
+
+/// the local file that is the given Spotify track, if there clearly is one.
+fn find_local<'a>(
+    track: &Track,
+    index: &'a LocalIndex,
+    by_title: &HashMap<String, Vec<&'a LocalTrack>>,
+) -> Option<&'a LocalTrack> {
+    if let Some(isrc) = track.isrc() {
+        let same = index.tracks.iter().find(|t| {
+            t.isrc
+                .as_ref()
+                .is_some_and(|i| i.eq_ignore_ascii_case(isrc))
+        });
+        if same.is_some() {
+            return same;
+        }
+    }
+    // comparing against every file is slow, so only do it if no file has the same title
+    let candidates = match by_title.get(&normalize(&track.name)) {
+        Some(candidates) => candidates.clone(),
+        None => index.tracks.iter().collect(),
+    };
+    candidates
+        .into_iter()
+        .map(|t| (t, score(&t.query(), track)))
+        .filter(|(_, score)| *score >= CONFIDENT_SCORE)
+        .max_by(|a, b| a.1.total_cmp(&b.1))
+        .map(|(t, _)| t)
+}
+
+/// write an M3U playlist of the local files of the playlist's tracks. Returns the tracks that
+/// have no local file.
+pub fn export_m3u<'a>(
+    playlist: &'a Playlist,
+    index: &LocalIndex,
+    path: &Path,
+) -> Result<Vec<&'a Track>, String> {
+    let mut by_title: HashMap<String, Vec<&LocalTrack>> = HashMap::new();
+    for track in &index.tracks {
+        by_title
+            .entry(normalize(&track.title))
+            .or_default()
+            .push(track);
+    }
+    let mut out = format!("#EXTM3U\n#PLAYLIST:{}\n", playlist.name);
+    let mut missing = Vec::new();
+    for track in playlist.tracks.iter().map(|t| &t.track) {
+        match find_local(track, index, &by_title) {
+            Some(local) => out.push_str(&format!(
+                "#EXTINF:{},{} - {}\n{}\n",
+                local
+                    .duration_ms
+                    .map(|d| d / 1000)
+                    .unwrap_or(track.duration()),
+                track.artists.stringify(),
+                track.name,
+                local.path.display()
+            )),
+            None => missing.push(track),
+        }
+    }
+    File::create(path)
+        .map_err(|e| format!("failed to create {:?}: {}", path, e))?
+        .write_all(out.as_bytes())
+        .map_err(|e| format!("failed to write {:?}: {}", path, e))?;
+    Ok(missing)
+}
+
+/// find the scanned files of a directory on Spotify and create a playlist of them, named after the
+/// directory. Files that weren't found are listed.
+pub fn create_from_dir(acc: &mut Account, index: &LocalIndex, dir: &Path) -> Result<(), String> {
+    let files = index.in_dir(dir);
+    if files.is_empty() {
+        return Err(format!(
+            "No scanned files in {:?}. Scan them with 'local scan' first",
+            dir
+        ));
+    }
+    let mut cache = MatchCache::load()?;
+    let mut uris = Vec::new();
+    let mut unmatched = Vec::new();
+    for (i, file) in files.iter().enumerate() {
+        let query = file.query();
+        if cache.get(&query).is_none() {
+            info!("Matching {}... ({}/{})", query, i + 1, files.len());
+        }
+        match find_match_cached(&query, acc, &mut cache)? {
+            Some(uri) => uris.push(uri),
+            None => unmatched.push(file),
+        }
+    }
+    cache.save()?;
+    if !unmatched.is_empty() {
+        warn!("{} files could not be matched:", unmatched.len());
+        for file in &unmatched {
+            println!("  {}", file);
+        }
+        info!("Tracks with no match aren't searched for again. Use 'matches forget-unmatched' to retry them");
+    }
+    if uris.is_empty() {
+        return Err("No files were matched, so no playlist was created".to_string());
+    }
+    let name = dir
+        .file_name()
+        .and_then(|n| n.to_str())
+        .unwrap_or("Local files")
+        .to_string();
+    if !user_yn(
+        format!("Create playlist {} with {} tracks?", name, uris.len()).as_str(),
+        true,
+    ) {
+        return Err("Aborted".to_string());
+    }
+    let ids = uris
+        .iter()
+        .map(|uri| uri.rsplit(':').next().unwrap_or_default())
+        .collect::<Vec<&str>>();
+    let tracks = Track::from_ids(&ids, acc)?;
+    Playlist::create_from_vec(
+        acc,
+        tracks,
+        name,
+        Some(format!(
+            "Made from the local files in {} by spotimine",
+            dir.display()
+        )),
+    )?;
+    Ok(())
+}
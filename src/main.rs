@@ -14,14 +14,53 @@ use crossterm::style::Stylize;
 use signal_hook::consts::SIGINT;
 
 use crate::account::Account;
-use crate::api::{do_api_json, get_liked_songs, get_playlists_for, spotify_api_search};
+use crate::api::{
+    do_api, do_api_json, get_followed_artists, get_liked_songs, get_playlists_for,
+    get_saved_albums, get_saved_shows, not_in_library, save_to_library, spotify_api_search,
+};
+use crate::backup::{backup, now, restore};
 use crate::config::{load, Config};
-use crate::data::{Album, Artist, ContentType, Playlist, Track};
+use crate::data::{
+    is_spotify_id, Album, Artist, Content, ContentType, Episode, Playlist, Show, SpotifyURI, Track,
+    User, Visibility,
+};
+use crate::export::{export, ExportFormat};
+use crate::history::{
+    add_plays, export_files, load_plays, parse_export, play_counts, recently_played, resolve,
+    PlayCount,
+};
+use crate::import::{import, parse_file};
+use crate::itunes::{import_library, parse_library};
+use crate::lastfm::scrobble_counts;
+use crate::local::{create_from_dir, export_m3u, LocalIndex};
+use crate::matching::MatchCache;
+use crate::migrate::migrate;
+use crate::smart::{Expr, SmartPlaylist};
+use crate::snapshots::{checkout, load_versions, print_log, record, TrackedPlaylist};
+use crate::sort::{sorted_order, SortKey};
+use crate::split::{format_name, split_tracks, SplitRule};
+use crate::stats::{Stats, TimeRange};
+use crate::utils::{encode_cover, epoch_time_to_rfc3339};
 
 mod account;
 mod api;
+mod backup;
 mod config;
 mod data;
+mod export;
+mod history;
+mod import;
+mod itunes;
+mod lastfm;
+mod local;
+mod matching;
+mod migrate;
+mod smart;
+mod snapshots;
+mod sort;
+mod split;
+mod stats;
+mod tags;
 mod utils;
 
 const VERSION: &str = env!("CARGO_PKG_VERSION");
@@ -91,7 +130,10 @@ fn main() {
 }
 
 fn dispatch(command: &str, this: &mut Spotimine) -> Result<(), String> {
-    let args = command.split(' ').collect::<Vec<&str>>();
+    let mut args = command.split(' ').collect::<Vec<&str>>();
+    if !LINK_COMMANDS.contains(&args[0]) {
+        reject_links(&args)?;
+    }
     match args[0] {
         "" => Ok(()),
         "exit" => {
@@ -132,7 +174,8 @@ fn dispatch(command: &str, this: &mut Spotimine) -> Result<(), String> {
             Ok(())
         }
         "copy" => {
-            check_args_len(&args, 2, "copy [source account] [dst account] [<optional> target_name, use liked to OVERWRITE liked songs]")?;
+            let link = take_playlist_link(&mut args, 3, false)?;
+            check_args_len(&args, 2, "copy [source account] [dst account] [<optional> playlist link] [<optional> target_name, use liked to OVERWRITE liked songs]")?;
             let acc = this.config.get_account(args[1]);
             if acc.is_none() {
                 return Err(format!(
@@ -150,9 +193,7 @@ fn dispatch(command: &str, this: &mut Spotimine) -> Result<(), String> {
                 ));
             }
             let acc2 = &mut acc2.unwrap();
-            let mut vec = get_playlists_for(acc)?;
-            vec.push(get_liked_songs(acc)?);
-            let p = user_choose("Choose a playlist to copy", vec, 0)?;
+            let p = choose_playlist(acc, "Choose a playlist to copy", link.as_ref(), true)?;
             if args.get(3).copied().unwrap_or_default() == "liked" {
                 p.copy_to_liked(acc2)?;
             } else {
@@ -160,6 +201,1144 @@ fn dispatch(command: &str, this: &mut Spotimine) -> Result<(), String> {
             }
             Ok(())
         }
+        "sort" => {
+            let link = take_playlist_link(&mut args, 2, false)?;
+            check_args_len(
+                &args,
+                2,
+                "sort [account] [<optional> playlist link] [key[:asc|:desc]...] (keys: name, artist, duration, added_at, release_date, popularity, album)",
+            )?;
+            let keys = args[2..]
+                .iter()
+                .map(|k| SortKey::from_str(k))
+                .collect::<Result<Vec<SortKey>, String>>()?;
+            let acc = this.config.get_account(args[1]).ok_or(format!(
+                "Account not found: {}. Try adding one with 'adduser'",
+                args[1]
+            ))?;
+            let mut p = choose_playlist(acc, "Choose a playlist to sort", link.as_ref(), false)?;
+            let order = sorted_order(&p.tracks, &keys);
+            p.reorder_online(acc, &order)
+        }
+        "split" => {
+            let link = take_playlist_link(&mut args, 2, false)?;
+            check_args_len(
+                &args,
+                2,
+                "split [account] [<optional> playlist link] [artist|decade|explicit|year_added|chunk:<size>] [<optional> name template, default '{source} — {group}']",
+            )?;
+            let rule = SplitRule::from_str(args[2])?;
+            let template = if args.len() > 3 {
+                args[3..].join(" ")
+            } else {
+                "{source} — {group}".to_string()
+            };
+            let acc = this.config.get_account(args[1]).ok_or(format!(
+                "Account not found: {}. Try adding one with 'adduser'",
+                args[1]
+            ))?;
+            let p = choose_playlist(acc, "Choose a playlist to split", link.as_ref(), true)?;
+            let groups = split_tracks(&p.tracks, rule);
+            for (group, tracks) in &groups {
+                println!(
+                    "{} ({} tracks)",
+                    format_name(&template, &p.name, group),
+                    tracks.len()
+                );
+            }
+            if !user_yn(
+                format!("Create these {} playlists?", groups.len()).as_str(),
+                true,
+            ) {
+                return Err("Aborted".to_string());
+            }
+            for (group, tracks) in groups {
+                let name = format_name(&template, &p.name, &group);
+                info!("Creating playlist {}", name);
+                Playlist::create_from_vec(
+                    acc,
+                    tracks,
+                    name,
+                    Some(format!("Split from {} by spotimine", p.name)),
+                )?;
+            }
+            info!("Split playlist");
+            Ok(())
+        }
+        "smart" => {
+            check_args_len(&args, 1, "smart [add|list|rm|refresh] ...")?;
+            match args[1] {
+                "add" => {
+                    check_args_len(&args, 4, "smart add [name] [account] [filter...]")?;
+                    let filter = args[4..].join(" ");
+                    Expr::parse(&filter)?;
+                    if this
+                        .config
+                        .smart_playlists
+                        .iter()
+                        .any(|s| s.name == args[2])
+                    {
+                        return Err(format!("Smart playlist {} already exists", args[2]));
+                    }
+                    let acc = this.config.get_account(args[3]).ok_or(format!(
+                        "Account not found: {}. Try adding one with 'adduser'",
+                        args[3]
+                    ))?;
+                    let mut vec = get_playlists_for(acc)?;
+                    vec.push(get_liked_songs(acc)?);
+                    let sources = user_choose_multi("Choose the source playlists", vec)?
+                        .into_iter()
+                        .map(|p| {
+                            if p.uri.uri.is_empty() {
+                                "liked".to_string()
+                            } else {
+                                p.uri.uri
+                            }
+                        })
+                        .collect::<Vec<String>>();
+                    if sources.is_empty() {
+                        return Err("At least one source playlist is required".to_string());
+                    }
+                    this.config.smart_playlists.push(SmartPlaylist {
+                        name: args[2].to_string(),
+                        account: args[3].to_string(),
+                        sources,
+                        filter,
+                        uri: String::new(),
+                    });
+                    this.config.save_to(&mut this.file)?;
+                    info!(
+                        "Added smart playlist {}. Use 'smart refresh' to create it",
+                        args[2]
+                    );
+                    Ok(())
+                }
+                "list" => {
+                    println!(
+                        "Found {} smart playlists:",
+                        this.config.smart_playlists.len()
+                    );
+                    for smart in &this.config.smart_playlists {
+                        println!("{}", smart);
+                    }
+                    Ok(())
+                }
+                "rm" => {
+                    check_args_len(&args, 2, "smart rm [name]")?;
+                    let len = this.config.smart_playlists.len();
+                    this.config.smart_playlists.retain(|s| s.name != args[2]);
+                    if len == this.config.smart_playlists.len() {
+                        return Err(format!("Smart playlist not found: {}", args[2]));
+                    }
+                    this.config.save_to(&mut this.file)?;
+                    info!(
+                        "Removed smart playlist {}. The Spotify playlist was kept",
+                        args[2]
+                    );
+                    Ok(())
+                }
+                "refresh" => {
+                    for i in 0..this.config.smart_playlists.len() {
+                        let mut smart = this.config.smart_playlists[i].clone();
+                        if args.len() > 2 && smart.name != args[2] {
+                            continue;
+                        }
+                        info!("Refreshing smart playlist {}", smart.name);
+                        let acc = this.config.get_account(&smart.account).ok_or(format!(
+                            "Account not found: {}. Try adding one with 'adduser'",
+                            smart.account
+                        ))?;
+                        let result = smart.refresh(acc);
+                        this.config.smart_playlists[i] = smart;
+                        this.config.save_to(&mut this.file)?;
+                        result?;
+                    }
+                    Ok(())
+                }
+                _ => Err(format!("Unknown smart command: {}", args[1])),
+            }
+        }
+        "rename" => {
+            let link = take_playlist_link(&mut args, 2, false)?;
+            check_args_len(
+                &args,
+                2,
+                "rename [account] [<optional> playlist link] [new name...]",
+            )?;
+            let acc = this.config.get_account(args[1]).ok_or(format!(
+                "Account not found: {}. Try adding one with 'adduser'",
+                args[1]
+            ))?;
+            let mut p = choose_playlist(acc, "Choose a playlist to rename", link.as_ref(), false)?;
+            let name = args[2..].join(" ");
+            if !user_yn(format!("Rename {} to {}?", p.name, name).as_str(), true) {
+                return Err("Aborted".to_string());
+            }
+            p.name = name;
+            p.update_details_online(acc)
+        }
+        "describe" => {
+            let link = take_playlist_link(&mut args, 2, false)?;
+            check_args_len(
+                &args,
+                1,
+                "describe [account] [<optional> playlist link] [<optional> new description...]",
+            )?;
+            let acc = this.config.get_account(args[1]).ok_or(format!(
+                "Account not found: {}. Try adding one with 'adduser'",
+                args[1]
+            ))?;
+            let mut p =
+                choose_playlist(acc, "Choose a playlist to describe", link.as_ref(), false)?;
+            let description = args[2..].join(" ");
+            if !user_yn(
+                if description.is_empty() {
+                    format!("Clear the description of {}?", p.name)
+                } else {
+                    format!("Set the description of {} to '{}'?", p.name, description)
+                }
+                .as_str(),
+                true,
+            ) {
+                return Err("Aborted".to_string());
+            }
+            p.description = description;
+            p.update_details_online(acc)
+        }
+        "visibility" => {
+            let link = take_playlist_link(&mut args, 2, false)?;
+            check_args_len(
+                &args,
+                2,
+                "visibility [account] [<optional> playlist link] [public|private|collaborative]",
+            )?;
+            let visibility = Visibility::from_str(args[2]).ok_or(
+                "Invalid visibility. Valid values are: 'public', 'private', 'collaborative'",
+            )?;
+            let acc = this.config.get_account(args[1]).ok_or(format!(
+                "Account not found: {}. Try adding one with 'adduser'",
+                args[1]
+            ))?;
+            let mut p = choose_playlist(acc, "Choose a playlist to change", link.as_ref(), false)?;
+            if visibility.is_collaborative() && p.visibility.is_public() {
+                warn!("Collaborative playlists cannot be public, so this playlist will also be made private");
+            }
+            if !user_yn(
+                format!(
+                    "Make {} {} (currently {})?",
+                    p.name, visibility, p.visibility
+                )
+                .as_str(),
+                true,
+            ) {
+                return Err("Aborted".to_string());
+            }
+            p.visibility = visibility;
+            p.update_details_online(acc)
+        }
+        "delete" => {
+            let link = take_playlist_link(&mut args, 2, true)?;
+            check_args_len(&args, 1, "delete [account] [<optional> playlist link]")?;
+            let acc = this.config.get_account(args[1]).ok_or(format!(
+                "Account not found: {}. Try adding one with 'adduser'",
+                args[1]
+            ))?;
+            let p = choose_playlist(
+                acc,
+                "Choose a playlist to delete/unfollow",
+                link.as_ref(),
+                false,
+            )?;
+            if !user_yn(
+                format!(
+                    "Are you sure you want to delete/unfollow {} ({} tracks)?",
+                    p.name,
+                    p.tracks.len()
+                )
+                .as_str(),
+                false,
+            ) {
+                return Err("Aborted".to_string());
+            }
+            p.unfollow_online(acc)
+        }
+        "contributors" => {
+            let link = take_playlist_link(&mut args, 2, true)?;
+            check_args_len(
+                &args,
+                1,
+                "contributors [account] [<optional> playlist link]",
+            )?;
+            let acc = this.config.get_account(args[1]).ok_or(format!(
+                "Account not found: {}. Try adding one with 'adduser'",
+                args[1]
+            ))?;
+            let p = choose_playlist(
+                acc,
+                "Choose a playlist to show the contributors of",
+                link.as_ref(),
+                false,
+            )?;
+            if let Some(owner) = &p.owner {
+                println!("{} is owned by {}", p.name, owner);
+            }
+            for contributor in p.contributors() {
+                let user = match &contributor.user {
+                    // tracks only name who added them by ID, so look up their display name
+                    Some(user) => User::from_id(user.get_id(), acc)
+                        .unwrap_or_else(|_| user.clone())
+                        .to_string(),
+                    None => "unknown".to_string(),
+                };
+                println!(
+                    "{}: {} tracks, first added {}, last added {}",
+                    user,
+                    contributor.tracks,
+                    epoch_time_to_rfc3339(contributor.first_added),
+                    epoch_time_to_rfc3339(contributor.last_added)
+                );
+            }
+            Ok(())
+        }
+        "cover" => {
+            let link = take_playlist_link(&mut args, 3, false)?;
+            check_args_len(
+                &args,
+                3,
+                "cover set [account] [<optional> playlist link] [file.jpg]",
+            )?;
+            if args[1] != "set" {
+                return Err(format!("Unknown cover command: {}", args[1]));
+            }
+            let path = args[3..].join(" ");
+            let bytes =
+                std::fs::read(&path).map_err(|e| format!("failed to read {}: {}", path, e))?;
+            let cover = encode_cover(&bytes)?;
+            let acc = this.config.get_account(args[2]).ok_or(format!(
+                "Account not found: {}. Try adding one with 'adduser'",
+                args[2]
+            ))?;
+            let p = choose_playlist(
+                acc,
+                "Choose a playlist to set the cover of",
+                link.as_ref(),
+                false,
+            )?;
+            p.upload_cover(acc, &cover)
+        }
+        "backup" => {
+            check_args_len(&args, 2, "backup [account] [directory]")?;
+            let dir = args[2..].join(" ");
+            let acc = this.config.get_account(args[1]).ok_or(format!(
+                "Account not found: {}. Try adding one with 'adduser'",
+                args[1]
+            ))?;
+            backup(acc, args[1], Path::new(&dir))?;
+            Ok(())
+        }
+        "restore" => {
+            check_args_len(
+                &args,
+                2,
+                "restore [backup directory or playlist file] [account]",
+            )?;
+            let acc = this.config.get_account(args[2]).ok_or(format!(
+                "Account not found: {}. Try adding one with 'adduser'",
+                args[2]
+            ))?;
+            restore(acc, Path::new(args[1]))
+        }
+        "export" => {
+            let link = take_playlist_link(&mut args, 2, false)?;
+            check_args_len(
+                &args,
+                3,
+                "export [account] [<optional> playlist link] [csv|m3u8|xspf|txt|json[:column,...]] [file] (columns: name, artists, album, duration, isrc, added_at, uri)",
+            )?;
+            let (format, columns) = ExportFormat::from_str(args[2])?;
+            let path = args[3..].join(" ");
+            let acc = this.config.get_account(args[1]).ok_or(format!(
+                "Account not found: {}. Try adding one with 'adduser'",
+                args[1]
+            ))?;
+            let p = choose_playlist(acc, "Choose a playlist to export", link.as_ref(), true)?;
+            export(&p, format, &columns, Path::new(&path))?;
+            info!(
+                "Exported {} tracks from {} to {}",
+                p.tracks.len(),
+                p.name,
+                path
+            );
+            Ok(())
+        }
+        "import" => {
+            check_args_len(&args, 2, "import [csv, m3u or txt file] [account]")?;
+            // the account is last, so that file names can have spaces
+            let path = args[1..args.len() - 1].join(" ");
+            let path = Path::new(&path);
+            let acc = this
+                .config
+                .get_account(args[args.len() - 1])
+                .ok_or(format!(
+                    "Account not found: {}. Try adding one with 'adduser'",
+                    args[args.len() - 1]
+                ))?;
+            let entries = parse_file(path)?;
+            let name = path
+                .file_stem()
+                .and_then(|s| s.to_str())
+                .unwrap_or("Imported")
+                .to_string();
+            let description = format!(
+                "Imported from {} by spotimine",
+                path.file_name()
+                    .and_then(|s| s.to_str())
+                    .unwrap_or_default()
+            );
+            import(acc, name, description, &entries)
+        }
+        "track" => {
+            let link = take_playlist_link(&mut args, 2, true)?;
+            check_args_len(&args, 1, "track [account] [<optional> playlist link]")?;
+            let acc = this.config.get_account(args[1]).ok_or(format!(
+                "Account not found: {}. Try adding one with 'adduser'",
+                args[1]
+            ))?;
+            let p = choose_playlist(acc, "Choose a playlist to track", link.as_ref(), false)?;
+            if this
+                .config
+                .tracked_playlists
+                .iter()
+                .any(|t| t.uri == p.uri.uri)
+            {
+                return Err(format!("{} is already being tracked", p.name));
+            }
+            record(&p)?;
+            this.config.tracked_playlists.push(TrackedPlaylist {
+                name: p.name.clone(),
+                account: args[1].to_string(),
+                uri: p.uri.uri.clone(),
+            });
+            this.config.save_to(&mut this.file)?;
+            info!(
+                "Tracking {}. Use 'snapshot' to record new versions of tracked playlists",
+                p.name
+            );
+            Ok(())
+        }
+        "untrack" => {
+            let link = take_playlist_link(&mut args, 2, true)?;
+            check_args_len(&args, 1, "untrack [account] [<optional> playlist link]")?;
+            let tracked = choose_tracked(
+                this,
+                Some(args[1]),
+                "Choose a playlist to stop tracking",
+                link.as_ref().map(|l| l.uri.as_str()),
+            )?;
+            this.config
+                .tracked_playlists
+                .retain(|t| t.uri != tracked.uri);
+            this.config.save_to(&mut this.file)?;
+            info!(
+                "Stopped tracking {}. Its recorded history was kept",
+                tracked.name
+            );
+            Ok(())
+        }
+        "snapshot" => {
+            for tracked in this.config.tracked_playlists.clone() {
+                let acc = this.config.get_account(&tracked.account).ok_or(format!(
+                    "Account not found: {}. Try adding one with 'adduser'",
+                    tracked.account
+                ))?;
+                let p = Playlist::from_id(SpotifyURI::from_str(tracked.uri).get_id(), acc)?;
+                match record(&p)? {
+                    Some(version) => info!("Recorded version {} of {}", version, p.name),
+                    None => info!("{} has not changed", p.name),
+                }
+            }
+            Ok(())
+        }
+        "log" => {
+            let playlist = args[1..].join(" ");
+            let tracked = choose_tracked(
+                this,
+                None,
+                "Choose a playlist to show the log of",
+                Some(playlist.as_str()).filter(|p| !p.is_empty()),
+            )?;
+            let acc = this.config.get_account(&tracked.account).ok_or(format!(
+                "Account not found: {}. Try adding one with 'adduser'",
+                tracked.account
+            ))?;
+            record(&Playlist::from_id(
+                SpotifyURI::from_str(tracked.uri.clone()).get_id(),
+                acc,
+            )?)?;
+            print_log(&load_versions(&SpotifyURI::from_str(tracked.uri))?);
+            Ok(())
+        }
+        "checkout" => {
+            check_args_len(
+                &args,
+                1,
+                "checkout [<optional> playlist link, URI or tracked name] [version]",
+            )?;
+            let last = args[args.len() - 1];
+            let version = last
+                .trim_start_matches('v')
+                .parse::<usize>()
+                .map_err(|_| format!("Invalid version: {}", last))?;
+            let playlist = args[1..args.len() - 1].join(" ");
+            let tracked = choose_tracked(
+                this,
+                None,
+                "Choose a playlist to check out",
+                Some(playlist.as_str()).filter(|p| !p.is_empty()),
+            )?;
+            let acc = this.config.get_account(&tracked.account).ok_or(format!(
+                "Account not found: {}. Try adding one with 'adduser'",
+                tracked.account
+            ))?;
+            let uri = SpotifyURI::from_str(tracked.uri);
+            let mut p = Playlist::from_id(uri.get_id(), acc)?;
+            // keep the current state, so that checking out can be undone too
+            record(&p)?;
+            let version = load_versions(&uri)?
+                .into_iter()
+                .find(|v| v.version == version)
+                .ok_or(format!("{} has no version {}", p.name, version))?;
+            checkout(acc, &mut p, &version)?;
+            record(&Playlist::from_id(uri.get_id(), acc)?)?;
+            Ok(())
+        }
+        "itunes" => {
+            check_args_len(&args, 2, "itunes [Library.xml] [account]")?;
+            // the account is last, so that file names can have spaces
+            let path = args[1..args.len() - 1].join(" ");
+            let acc = this
+                .config
+                .get_account(args[args.len() - 1])
+                .ok_or(format!(
+                    "Account not found: {}. Try adding one with 'adduser'",
+                    args[args.len() - 1]
+                ))?;
+            let playlists = parse_library(Path::new(&path))?;
+            if playlists.is_empty() {
+                return Err(format!("{} has no playlists", path));
+            }
+            import_library(acc, playlists)
+        }
+        "history" => {
+            check_args_len(&args, 2, "history [import|sync|top|unliked] [account] ...")?;
+            let acc = this.config.get_account(args[2]).ok_or(format!(
+                "Account not found: {}. Try adding one with 'adduser'",
+                args[2]
+            ))?;
+            match args[1] {
+                "import" => {
+                    check_args_len(&args, 3, "history import [account] [file or directory]")?;
+                    let path = args[3..].join(" ");
+                    let files = export_files(Path::new(&path))?;
+                    if files.is_empty() {
+                        return Err(format!("No listening history files found in {}", path));
+                    }
+                    let mut plays = Vec::new();
+                    for file in files {
+                        info!("Reading {:?}...", file);
+                        plays.append(&mut parse_export(&file)?);
+                    }
+                    let added = add_plays(args[2], &plays)?;
+                    info!(
+                        "Added {} new plays to the listening history of {} ({} were already there)",
+                        added,
+                        args[2],
+                        plays.len() - added
+                    );
+                    Ok(())
+                }
+                "sync" => {
+                    let since = load_plays(args[2])?
+                        .iter()
+                        .map(|p| p.played_at)
+                        .max()
+                        .unwrap_or(0);
+                    let plays = recently_played(acc, since)?;
+                    let added = add_plays(args[2], &plays)?;
+                    info!(
+                        "Added {} new plays to the listening history of {}. Spotify only remembers the last 50, so sync at least that often",
+                        added,
+                        args[2]
+                    );
+                    Ok(())
+                }
+                "top" => {
+                    let year = args.get(3).copied().filter(|y| *y != "all");
+                    let count = match args.get(4) {
+                        Some(n) => n
+                            .parse::<usize>()
+                            .map_err(|_| format!("Invalid count: {}", n))?,
+                        None => 50,
+                    };
+                    let plays = load_plays(args[2])?;
+                    if plays.is_empty() {
+                        return Err(format!(
+                            "{} has no listening history. Add some with 'history import'",
+                            args[2]
+                        ));
+                    }
+                    let mut counts =
+                        play_counts(plays.iter().filter(|p| year.is_none_or(|y| p.year() == y)));
+                    counts.truncate(count);
+                    let name = match year {
+                        Some(year) => format!("Most played in {}", year),
+                        None => "Most played of all time".to_string(),
+                    };
+                    create_from_counts(acc, &counts, name)
+                }
+                "unliked" => {
+                    let min = match args.get(3) {
+                        Some(n) => n
+                            .parse::<usize>()
+                            .map_err(|_| format!("Invalid count: {}", n))?,
+                        None => 20,
+                    };
+                    let plays = load_plays(args[2])?;
+                    if plays.is_empty() {
+                        return Err(format!(
+                            "{} has no listening history. Add some with 'history import'",
+                            args[2]
+                        ));
+                    }
+                    let liked = get_liked_songs(acc)?;
+                    let is_liked = |count: &PlayCount| {
+                        liked.tracks.iter().any(|t| {
+                            count.uri.as_ref() == Some(&t.track.uri.uri)
+                                || (t.track.name.to_lowercase() == count.track.to_lowercase()
+                                    && t.track.artists.iter().any(|a| {
+                                        a.name.to_lowercase() == count.artist.to_lowercase()
+                                    }))
+                        })
+                    };
+                    let counts = play_counts(plays.iter())
+                        .into_iter()
+                        .filter(|c| c.plays > min && !is_liked(c))
+                        .collect::<Vec<PlayCount>>();
+                    create_from_counts(
+                        acc,
+                        &counts,
+                        format!("Played more than {} times but never liked", min),
+                    )
+                }
+                _ => Err(format!("Unknown history command: {}", args[1])),
+            }
+        }
+        "lastfm" => {
+            check_args_len(
+                &args,
+                4,
+                "lastfm [top|loved] [account] [count] [csv or json export]",
+            )?;
+            let count = args[3]
+                .parse::<usize>()
+                .map_err(|_| format!("Invalid count: {}", args[3]))?;
+            let acc = this.config.get_account(args[2]).ok_or(format!(
+                "Account not found: {}. Try adding one with 'adduser'",
+                args[2]
+            ))?;
+            let scrobbles = lastfm::parse_file(Path::new(&args[4..].join(" ")))?;
+            info!("Read {} scrobbles", scrobbles.len());
+            let mut counts = scrobble_counts(&scrobbles);
+            let name = match args[1] {
+                "top" => format!("Top {} most scrobbled", count),
+                "loved" => {
+                    counts.retain(|c| c.loved);
+                    if counts.is_empty() {
+                        return Err(
+                            "The export has no loved tracks. Export them with the loved flag, or from user.getLovedTracks".to_string(),
+                        );
+                    }
+                    format!("Top {} loved", count)
+                }
+                _ => return Err(format!("Unknown lastfm command: {}", args[1])),
+            };
+            counts.truncate(count);
+            for count in &counts {
+                println!(
+                    "{} by {}: {} scrobbles",
+                    count.track.as_str().blue().bold(),
+                    count.artist.as_str().blue(),
+                    count.scrobbles
+                );
+            }
+            if !user_yn(
+                format!(
+                    "Create playlist {} with these {} tracks?",
+                    name,
+                    counts.len()
+                )
+                .as_str(),
+                true,
+            ) {
+                return Err("Aborted".to_string());
+            }
+            let tracks = lastfm::resolve(acc, &counts)?;
+            Playlist::create_from_vec(
+                acc,
+                tracks,
+                name,
+                Some("Made from Last.fm scrobbles by spotimine".to_string()),
+            )?;
+            Ok(())
+        }
+        "matches" => {
+            check_args_len(&args, 1, "matches [forget-unmatched]")?;
+            if args[1] != "forget-unmatched" {
+                return Err(format!("Unknown matches command: {}", args[1]));
+            }
+            let mut cache = MatchCache::load()?;
+            let forgotten = cache.forget_unmatched();
+            cache.save()?;
+            info!(
+                "Forgot {} tracks that had no match. They will be searched for again",
+                forgotten
+            );
+            Ok(())
+        }
+        "local" => {
+            check_args_len(&args, 2, "local [scan|export|playlist] ...")?;
+            if args[1] != "export" {
+                reject_links(&args)?;
+            }
+            match args[1] {
+                "scan" => {
+                    let dir = args[2..].join(" ");
+                    let dir = Path::new(&dir)
+                        .canonicalize()
+                        .map_err(|e| format!("failed to open {}: {}", dir, e))?;
+                    let mut index = LocalIndex::load()?;
+                    let summary = index.scan(&dir)?;
+                    index.save()?;
+                    info!(
+                        "Found {} audio files in {:?}: {} new or changed, {} unreadable, {} removed since the last scan",
+                        summary.files,
+                        dir,
+                        summary.read,
+                        summary.failed,
+                        summary.removed
+                    );
+                    Ok(())
+                }
+                "export" => {
+                    let link = take_playlist_link(&mut args, 3, false)?;
+                    check_args_len(
+                        &args,
+                        3,
+                        "local export [account] [<optional> playlist link] [m3u file]",
+                    )?;
+                    let index = LocalIndex::load()?;
+                    if index.tracks.is_empty() {
+                        return Err(
+                            "No local files are known. Scan them with 'local scan' first"
+                                .to_string(),
+                        );
+                    }
+                    let acc = this.config.get_account(args[2]).ok_or(format!(
+                        "Account not found: {}. Try adding one with 'adduser'",
+                        args[2]
+                    ))?;
+                    let p =
+                        choose_playlist(acc, "Choose a playlist to export", link.as_ref(), true)?;
+                    let path = args[3..].join(" ");
+                    let missing = export_m3u(&p, &index, Path::new(&path))?;
+                    info!(
+                        "Exported {} of {} tracks from {} to {}",
+                        p.tracks.len() - missing.len(),
+                        p.tracks.len(),
+                        p.name,
+                        path
+                    );
+                    if !missing.is_empty() {
+                        warn!("{} tracks have no local file:", missing.len());
+                        for track in missing {
+                            println!("  {}", track);
+                        }
+                    }
+                    Ok(())
+                }
+                "playlist" => {
+                    check_args_len(&args, 3, "local playlist [account] [directory]")?;
+                    let acc = this.config.get_account(args[2]).ok_or(format!(
+                        "Account not found: {}. Try adding one with 'adduser'",
+                        args[2]
+                    ))?;
+                    let dir = args[3..].join(" ");
+                    let dir = Path::new(&dir)
+                        .canonicalize()
+                        .map_err(|e| format!("failed to open {}: {}", dir, e))?;
+                    create_from_dir(acc, &LocalIndex::load()?, &dir)
+                }
+                _ => Err(format!("Unknown local command: {}", args[1])),
+            }
+        }
+        "stats" => {
+            check_args_len(
+                &args,
+                1,
+                "stats [account] [<optional> short|medium|long] [<optional> html report file]",
+            )?;
+            let range = match args.get(2) {
+                Some(range) => TimeRange::from_str(range)?,
+                None => TimeRange::Long,
+            };
+            let acc = this.config.get_account(args[1]).ok_or(format!(
+                "Account not found: {}. Try adding one with 'adduser'",
+                args[1]
+            ))?;
+            let plays = load_plays(args[1])?;
+            if plays.is_empty() {
+                warn!(
+                    "{} has no listening history. Add some with 'history import' or 'history sync'",
+                    args[1]
+                );
+            }
+            let mut stats = Stats::compute(&plays, range, now());
+            stats.fetch_top(acc)?;
+            stats.print();
+            if args.len() > 3 {
+                let path = args[3..].join(" ");
+                stats.save_html(Path::new(&path))?;
+                info!("Saved the report to {}", path);
+            }
+            Ok(())
+        }
+        "migrate" => {
+            check_args_len(&args, 2, "migrate [source account] [dst account]")?;
+            if args[1] == args[2] {
+                return Err("Source and destination accounts must be different".to_string());
+            }
+            let mut src = this
+                .config
+                .get_account(args[1])
+                .ok_or(format!(
+                    "Account not found: {}. Try adding one with 'adduser'",
+                    args[1]
+                ))?
+                .clone();
+            let dst = this.config.get_account(args[2]).ok_or(format!(
+                "Account not found: {}. Try adding one with 'adduser'",
+                args[2]
+            ))?;
+            if !user_yn(
+                format!(
+                    "This will copy everything from {} to {}. Continue?",
+                    args[1], args[2]
+                )
+                .as_str(),
+                true,
+            ) {
+                return Err("Aborted".to_string());
+            }
+            let result = migrate(args[1], &mut src, args[2], dst);
+            // keep any token refreshes that happened on the source account
+            this.config.accounts.insert(args[1].to_string(), src);
+            result
+        }
+        "albums" => {
+            check_args_len(&args, 2, "albums [list|save|rm|copy] [account] ...")?;
+            match args[1] {
+                "list" => {
+                    let acc = this.config.get_account(args[2]).ok_or(format!(
+                        "Account not found: {}. Try adding one with 'adduser'",
+                        args[2]
+                    ))?;
+                    let albums = get_saved_albums(acc)?;
+                    println!("Found {} saved albums:", albums.len());
+                    for album in albums {
+                        println!("{}", album);
+                    }
+                    Ok(())
+                }
+                "save" => {
+                    check_args_len(
+                        &args,
+                        3,
+                        "albums save [account] [album link or search query...]",
+                    )?;
+                    let query = args[3..].join(" ");
+                    let acc = this.config.get_account(args[2]).ok_or(format!(
+                        "Account not found: {}. Try adding one with 'adduser'",
+                        args[2]
+                    ))?;
+                    let album = match SpotifyURI::parse_as(&query, ContentType::Albums) {
+                        Ok(uri) => Album::from_id(uri.get_id(), acc)?,
+                        Err(_) => user_choose(
+                            "Choose an album to save",
+                            spotify_api_search::<Album>(query.as_str(), &ContentType::Albums, acc)?,
+                            0,
+                        )?,
+                    };
+                    save_to_library(acc, "me/albums?ids=", &[album.uri.get_id()], 20)?;
+                    info!("Saved album {}", album.name);
+                    Ok(())
+                }
+                "rm" => {
+                    let acc = this.config.get_account(args[2]).ok_or(format!(
+                        "Account not found: {}. Try adding one with 'adduser'",
+                        args[2]
+                    ))?;
+                    let albums =
+                        user_choose_multi("Choose albums to remove", get_saved_albums(acc)?)?;
+                    if albums.is_empty()
+                        || !user_yn(
+                            format!("Remove {} albums from your library?", albums.len()).as_str(),
+                            false,
+                        )
+                    {
+                        return Err("Aborted".to_string());
+                    }
+                    for chunk in albums.chunks(20) {
+                        do_api(
+                            "DELETE",
+                            format!(
+                                "me/albums?ids={}",
+                                chunk
+                                    .iter()
+                                    .map(|a| a.uri.get_id())
+                                    .collect::<Vec<&str>>()
+                                    .join(",")
+                            )
+                            .as_str(),
+                            acc,
+                            "",
+                        )?;
+                    }
+                    info!("Removed {} albums", albums.len());
+                    Ok(())
+                }
+                "copy" => {
+                    check_args_len(&args, 3, "albums copy [source account] [dst account]")?;
+                    let src = this.config.get_account(args[2]).ok_or(format!(
+                        "Account not found: {}. Try adding one with 'adduser'",
+                        args[2]
+                    ))?;
+                    let albums = get_saved_albums(src)?;
+                    let dst = this.config.get_account(args[3]).ok_or(format!(
+                        "Account not found: {}. Try adding one with 'adduser'",
+                        args[3]
+                    ))?;
+                    let albums = not_in_library(
+                        dst,
+                        &albums,
+                        |a| a.uri.get_id(),
+                        "me/albums/contains?ids=",
+                        20,
+                    )?;
+                    if !user_yn(
+                        format!("Save {} albums to {}?", albums.len(), args[3]).as_str(),
+                        true,
+                    ) {
+                        return Err("Aborted".to_string());
+                    }
+                    // saved albums are listed newest first, so save the oldest first to keep the order
+                    save_to_library(
+                        dst,
+                        "me/albums?ids=",
+                        &albums
+                            .iter()
+                            .rev()
+                            .map(|a| a.uri.get_id())
+                            .collect::<Vec<&str>>(),
+                        20,
+                    )?;
+                    info!("Copied {} albums", albums.len());
+                    Ok(())
+                }
+                _ => Err(format!("Unknown albums command: {}", args[1])),
+            }
+        }
+        "artists" => {
+            check_args_len(
+                &args,
+                2,
+                "artists [list|follow|unfollow|copy] [account] ...",
+            )?;
+            match args[1] {
+                "list" => {
+                    let acc = this.config.get_account(args[2]).ok_or(format!(
+                        "Account not found: {}. Try adding one with 'adduser'",
+                        args[2]
+                    ))?;
+                    let artists = get_followed_artists(acc)?;
+                    println!("Following {} artists:", artists.len());
+                    for artist in artists {
+                        println!("{}", artist);
+                    }
+                    Ok(())
+                }
+                "follow" => {
+                    check_args_len(
+                        &args,
+                        3,
+                        "artists follow [account] [artist link or search query...]",
+                    )?;
+                    let query = args[3..].join(" ");
+                    let acc = this.config.get_account(args[2]).ok_or(format!(
+                        "Account not found: {}. Try adding one with 'adduser'",
+                        args[2]
+                    ))?;
+                    let artist = match SpotifyURI::parse_as(&query, ContentType::Artists) {
+                        Ok(uri) => Artist::from_id(uri.get_id(), acc)?,
+                        Err(_) => user_choose(
+                            "Choose an artist to follow",
+                            spotify_api_search::<Artist>(
+                                query.as_str(),
+                                &ContentType::Artists,
+                                acc,
+                            )?,
+                            0,
+                        )?,
+                    };
+                    save_to_library(
+                        acc,
+                        "me/following?type=artist&ids=",
+                        &[artist.uri.get_id()],
+                        50,
+                    )?;
+                    info!("Followed {}", artist.name);
+                    Ok(())
+                }
+                "unfollow" => {
+                    let acc = this.config.get_account(args[2]).ok_or(format!(
+                        "Account not found: {}. Try adding one with 'adduser'",
+                        args[2]
+                    ))?;
+                    let artists = user_choose_multi(
+                        "Choose artists to unfollow",
+                        get_followed_artists(acc)?,
+                    )?;
+                    if artists.is_empty()
+                        || !user_yn(
+                            format!("Unfollow {} artists?", artists.len()).as_str(),
+                            false,
+                        )
+                    {
+                        return Err("Aborted".to_string());
+                    }
+                    for chunk in artists.chunks(50) {
+                        do_api(
+                            "DELETE",
+                            format!(
+                                "me/following?type=artist&ids={}",
+                                chunk
+                                    .iter()
+                                    .map(|a| a.uri.get_id())
+                                    .collect::<Vec<&str>>()
+                                    .join(",")
+                            )
+                            .as_str(),
+                            acc,
+                            "",
+                        )?;
+                    }
+                    info!("Unfollowed {} artists", artists.len());
+                    Ok(())
+                }
+                "copy" => {
+                    check_args_len(&args, 3, "artists copy [source account] [dst account]")?;
+                    let src = this.config.get_account(args[2]).ok_or(format!(
+                        "Account not found: {}. Try adding one with 'adduser'",
+                        args[2]
+                    ))?;
+                    let artists = get_followed_artists(src)?;
+                    let dst = this.config.get_account(args[3]).ok_or(format!(
+                        "Account not found: {}. Try adding one with 'adduser'",
+                        args[3]
+                    ))?;
+                    let artists = not_in_library(
+                        dst,
+                        &artists,
+                        |a| a.uri.get_id(),
+                        "me/following/contains?type=artist&ids=",
+                        50,
+                    )?;
+                    if !user_yn(
+                        format!("Follow {} artists on {}?", artists.len(), args[3]).as_str(),
+                        true,
+                    ) {
+                        return Err("Aborted".to_string());
+                    }
+                    save_to_library(
+                        dst,
+                        "me/following?type=artist&ids=",
+                        &artists
+                            .iter()
+                            .map(|a| a.uri.get_id())
+                            .collect::<Vec<&str>>(),
+                        50,
+                    )?;
+                    info!("Copied {} followed artists", artists.len());
+                    Ok(())
+                }
+                _ => Err(format!("Unknown artists command: {}", args[1])),
+            }
+        }
+        "shows" => {
+            check_args_len(&args, 2, "shows [list|copy] [account] ...")?;
+            match args[1] {
+                "list" => {
+                    let acc = this.config.get_account(args[2]).ok_or(format!(
+                        "Account not found: {}. Try adding one with 'adduser'",
+                        args[2]
+                    ))?;
+                    let shows = get_saved_shows(acc)?;
+                    println!("Found {} saved shows:", shows.len());
+                    for show in shows {
+                        println!("{}", show);
+                    }
+                    Ok(())
+                }
+                "copy" => {
+                    check_args_len(&args, 3, "shows copy [source account] [dst account]")?;
+                    let src = this.config.get_account(args[2]).ok_or(format!(
+                        "Account not found: {}. Try adding one with 'adduser'",
+                        args[2]
+                    ))?;
+                    let shows = get_saved_shows(src)?;
+                    let dst = this.config.get_account(args[3]).ok_or(format!(
+                        "Account not found: {}. Try adding one with 'adduser'",
+                        args[3]
+                    ))?;
+                    let shows = not_in_library(
+                        dst,
+                        &shows,
+                        |s| s.uri.get_id(),
+                        "me/shows/contains?ids=",
+                        50,
+                    )?;
+                    if !user_yn(
+                        format!("Save {} shows to {}?", shows.len(), args[3]).as_str(),
+                        true,
+                    ) {
+                        return Err("Aborted".to_string());
+                    }
+                    // saved shows are listed newest first, so save the oldest first to keep the order
+                    save_to_library(
+                        dst,
+                        "me/shows?ids=",
+                        &shows
+                            .iter()
+                            .rev()
+                            .map(|s| s.uri.get_id())
+                            .collect::<Vec<&str>>(),
+                        50,
+                    )?;
+                    info!("Copied {} shows", shows.len());
+                    Ok(())
+                }
+                _ => Err(format!("Unknown shows command: {}", args[1])),
+            }
+        }
         "search" => {
             check_args_len(&args, 2, "search [content_type] [query...]")?;
             let query = args[2..].join(" ");
@@ -193,11 +1372,21 @@ fn dispatch(command: &str, this: &mut Spotimine) -> Result<(), String> {
                                 .iter()
                                 .for_each(|x| println!("{}", x))
                         }
+                        ContentType::Shows => {
+                            spotify_api_search::<Show>(query.as_str(), &typ, account)?
+                                .iter()
+                                .for_each(|x| println!("{}", x))
+                        }
+                        ContentType::Episodes => {
+                            spotify_api_search::<Episode>(query.as_str(), &typ, account)?
+                                .iter()
+                                .for_each(|x| println!("{}", x))
+                        }
                     }
                     Ok(())
                 }
                 None => Err(
-                    "Invalid content type. Valid types are: 'track', 'album', 'artist', 'playlist'"
+                    "Invalid content type. Valid types are: 'track', 'album', 'artist', 'playlist', 'show', 'episode'"
                         .parse()
                         .unwrap(),
                 ),
@@ -234,6 +1423,155 @@ fn dispatch(command: &str, this: &mut Spotimine) -> Result<(), String> {
     }
 }
 
+/// the tracked playlist a command acts on: the one given by link, URI or name, or else one chosen
+/// from the tracked playlists of the account, or of every account if none is given.
+fn choose_tracked(
+    this: &Spotimine,
+    account: Option<&str>,
+    prompt: &str,
+    playlist: Option<&str>,
+) -> Result<TrackedPlaylist, String> {
+    let mut tracked = this
+        .config
+        .tracked_playlists
+        .iter()
+        .filter(|t| account.is_none_or(|a| t.account == a))
+        .cloned()
+        .collect::<Vec<TrackedPlaylist>>();
+    if let Some(playlist) = playlist {
+        if is_link(playlist) || is_spotify_id(playlist) {
+            let uri = SpotifyURI::parse_as(playlist, ContentType::Playlists)?;
+            tracked.retain(|t| t.uri == uri.uri);
+        } else {
+            tracked.retain(|t| t.name.eq_ignore_ascii_case(playlist));
+        }
+        // names don't have to be unique, so only ask if several playlists have it
+        match tracked.len() {
+            0 => {
+                return Err(format!(
+                    "{} is not a tracked playlist. Track it with 'track'",
+                    playlist
+                ))
+            }
+            1 => return Ok(tracked.remove(0)),
+            _ => (),
+        }
+    } else if tracked.is_empty() {
+        return Err(match account {
+            Some(account) => format!(
+                "{} has no tracked playlists. Track one with 'track'",
+                account
+            ),
+            None => "No playlists are tracked. Track one with 'track'".to_string(),
+        });
+    }
+    user_choose(prompt, tracked, 0)
+}
+
+/// show the given play counts, and create a playlist of them if the user wants.
+fn create_from_counts(acc: &mut Account, counts: &[PlayCount], name: String) -> Result<(), String> {
+    if counts.is_empty() {
+        return Err("No tracks in the listening history match".to_string());
+    }
+    for count in counts {
+        println!(
+            "{} by {}: {} plays",
+            count.track.as_str().blue().bold(),
+            count.artist.as_str().blue(),
+            count.plays
+        );
+    }
+    if !user_yn(
+        format!(
+            "Create playlist {} with these {} tracks?",
+            name,
+            counts.len()
+        )
+        .as_str(),
+        true,
+    ) {
+        return Err("Aborted".to_string());
+    }
+    let tracks = resolve(acc, counts)?;
+    Playlist::create_from_vec(
+        acc,
+        tracks,
+        name,
+        Some("Made from listening history by spotimine".to_string()),
+    )?;
+    Ok(())
+}
+
+/// commands that look for links in their arguments themselves. Any other command fails on a link,
+/// rather than ignoring it.
+const LINK_COMMANDS: [&str; 18] = [
+    "copy",
+    "sort",
+    "split",
+    "rename",
+    "describe",
+    "visibility",
+    "delete",
+    "contributors",
+    "cover",
+    "export",
+    "track",
+    "untrack",
+    "log",
+    "checkout",
+    "local",
+    "search",
+    "albums",
+    "artists",
+];
+
+fn is_link(arg: &str) -> bool {
+    arg.starts_with("spotify:") || arg.contains("open.spotify.com/")
+}
+
+/// take a pasted playlist link or URI out of the arguments at `pos`, so that commands can act on
+/// it instead of asking which playlist to use. Links anywhere else are an error. A bare ID is only
+/// taken if `bare_ids` is set, for commands that have nothing else at `pos` that could look like one.
+fn take_playlist_link(
+    args: &mut Vec<&str>,
+    pos: usize,
+    bare_ids: bool,
+) -> Result<Option<SpotifyURI>, String> {
+    let link = match args.get(pos) {
+        Some(arg) if is_link(arg) || (bare_ids && is_spotify_id(arg)) => Some(
+            SpotifyURI::parse_as(args.remove(pos), ContentType::Playlists)?,
+        ),
+        _ => None,
+    };
+    reject_links(args)?;
+    Ok(link)
+}
+
+fn reject_links(args: &[&str]) -> Result<(), String> {
+    match args.iter().skip(1).find(|a| is_link(a)) {
+        Some(link) => Err(format!("{} doesn't take a link here: {}", args[0], link)),
+        None => Ok(()),
+    }
+}
+
+/// the playlist a command acts on: the linked one, if a link was given, or else one chosen from the
+/// account's playlists, and liked songs if `liked` is set.
+fn choose_playlist(
+    acc: &mut Account,
+    prompt: &str,
+    link: Option<&SpotifyURI>,
+    liked: bool,
+) -> Result<Playlist, String> {
+    if let Some(uri) = link {
+        return Playlist::from_id(uri.get_id(), acc);
+    }
+    let mut vec = get_playlists_for(acc)?;
+    if liked {
+        vec.push(get_liked_songs(acc)?);
+    }
+    user_choose(prompt, vec, 0)
+}
+
 fn user_yn(prompt: &str, default: bool) -> bool {
     let mut input = String::new();
     print!("{} [{}]: ", prompt, if default { "Y/n" } else { "y/N" });
@@ -258,12 +1596,36 @@ fn user_choose<T: Display + Clone>(
     for (i, t) in (0_u16..).zip(data.iter()) {
         println!("[{}]: {}", i, t);
     }
-    let mut input = String::new();
-    print!("{} (default: {}): ", prompt, default);
-    io::stdout().flush().unwrap();
-    io::stdin().read_line(&mut input).unwrap();
-    let input = input.trim().parse::<usize>().map_err(|_| "Invalid input")?;
-    Ok(data.get(input).unwrap().clone())
+    if data.is_empty() {
+        return Err("Nothing to choose from".to_string());
+    }
+    loop {
+        let mut input = String::new();
+        print!("{} (default: {}): ", prompt, default);
+        io::stdout().flush().unwrap();
+        // stdin was closed, so asking again would never end
+        if io::stdin()
+            .read_line(&mut input)
+            .map_err(|e| e.to_string())?
+            == 0
+        {
+            return Err("Aborted".to_string());
+        }
+        let input = input.trim();
+        let choice = if input.is_empty() {
+            default
+        } else {
+            input.parse::<usize>().unwrap_or(usize::MAX)
+        };
+        match data.get(choice) {
+            Some(t) => return Ok(t.clone()),
+            None => warn!(
+                "Invalid input: {}. Enter a number from 0 to {}",
+                input,
+                data.len() - 1
+            ),
+        }
+    }
 }
 
 fn user_choose_multi<T: Display + Clone>(prompt: &str, data: Vec<T>) -> Result<Vec<T>, String> {
@@ -274,30 +1636,29 @@ fn user_choose_multi<T: Display + Clone>(prompt: &str, data: Vec<T>) -> Result<V
     print!("{} (eg: '1 2 3', '3-6'): ", prompt);
     io::stdout().flush().unwrap();
     io::stdin().read_line(&mut input).unwrap();
+    let index = |v: &str| -> Result<usize, String> {
+        v.parse::<usize>()
+            .ok()
+            .filter(|i| *i < data.len())
+            .ok_or(format!(
+                "Invalid input: {}. Enter numbers from 0 to {}",
+                v,
+                data.len().saturating_sub(1)
+            ))
+    };
     let mut out = Vec::new();
-    let _ = input
-        .trim()
-        .split(' ')
-        .try_for_each(|v| -> Result<(), String> {
-            if v.contains('-') {
-                let mut split = v.split('-');
-                let start = split
-                    .next()
-                    .unwrap()
-                    .parse::<usize>()
-                    .map_err(|_| "Invalid input")?;
-                let end = split
-                    .next()
-                    .unwrap()
-                    .parse::<usize>()
-                    .map_err(|_| "Invalid input")?;
-                (start..=end).for_each(|i| out.push(data[i].clone()));
-                Ok(())
-            } else {
-                out.push(data[v.parse::<usize>().map_err(|_| "Invalid input")?].clone());
-                Ok(())
+    for v in input.split_whitespace() {
+        match v.split_once('-') {
+            Some((start, end)) => {
+                let (start, end) = (index(start)?, index(end)?);
+                if start > end {
+                    return Err(format!("Invalid range: {}", v));
+                }
+                (start..=end).for_each(|i| out.push(data[i].clone()))
             }
-        });
+            None => out.push(data[index(v)?].clone()),
+        }
+    }
     Ok(out)
 }
 
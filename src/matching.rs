@@ -0,0 +1,497 @@
+use std::collections::HashMap;
+use std::fmt::{Display, Formatter};
+use std::path::PathBuf;
+
+use serde::{Deserialize, Serialize};
+
+use crate::account::Account;
+use crate::api::spotify_api_search;
+use crate::backup::{read_json, write_json};
+use crate::config::config_dir;
+use crate::data::{ContentType, Stringify, Track};
+use crate::user_choose;
+
+/// a track from outside Spotify, e.g. a line of a CSV file, to be found on Spotify.
+#[derive(Debug, Clone, Default)]
+pub struct TrackQuery {
+    pub title: String,
+    /// empty if unknown.
+    pub artist: String,
+    pub album: Option<String>,
+    pub duration_ms: Option<u32>,
+    pub isrc: Option<String>,
+}
+
+impl Display for TrackQuery {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        if self.artist.is_empty() {
+            f.write_str(&self.title)
+        } else {
+            f.write_str(&format!("{} - {}", self.artist, self.title))
+        }
+    }
+}
+
+/// the URIs that queries were matched to before, so that importing the same tracks again doesn't
+/// search for them again. `None` means the user decided there was no match.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct MatchCache {
+    matches: HashMap<String, Option<String>>,
+    /// how many matches were added since the cache was last saved.
+    #[serde(skip)]
+    unsaved: usize,
+}
+
+/// the cache is saved after this many new matches, so that little is lost if matching stops halfway.
+const SAVE_EVERY: usize = 20;
+
+impl MatchCache {
+    fn path() -> Result<PathBuf, String> {
+        Ok(config_dir()?.join("matches.json"))
+    }
+
+    pub fn load() -> Result<MatchCache, String> {
+        let path = MatchCache::path()?;
+        if !path.exists() {
+            return Ok(MatchCache::default());
+        }
+        read_json(&path)
+    }
+
+    pub fn save(&mut self) -> Result<(), String> {
+        self.unsaved = 0;
+        write_json(&MatchCache::path()?, self)
+    }
+
+    fn key(query: &TrackQuery) -> String {
+        format!(
+            "{}\t{}",
+            query.artist.to_lowercase(),
+            query.title.to_lowercase()
+        )
+    }
+
+    pub fn get(&self, query: &TrackQuery) -> Option<&Option<String>> {
+        self.matches.get(&MatchCache::key(query))
+    }
+
+    pub fn insert(&mut self, query: &TrackQuery, uri: Option<String>) {
+        self.matches.insert(MatchCache::key(query), uri);
+        self.unsaved += 1;
+    }
+
+    /// forget the queries that had no match, so that they are searched for again. Returns how
+    /// many there were.
+    pub fn forget_unmatched(&mut self) -> usize {
+        let before = self.matches.len();
+        self.matches.retain(|_, uri| uri.is_some());
+        before - self.matches.len()
+    }
+}
+
+/// a possible match for a query, and how confident we are in it, from 0 to 1.
+#[derive(Debug, Clone)]
+pub struct Candidate {
+    pub track: Option<Track>,
+    pub score: f64,
+}
+
+impl Display for Candidate {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match &self.track {
+            Some(track) => f.write_str(&format!(
+                "{} ({}, {:.0}% match)",
+                track,
+                track.album,
+                self.score * 100.0
+            )),
+            None => f.write_str("none of these"),
+        }
+    }
+}
+
+/// matches at least this good are taken without asking, if no other candidate comes close.
+pub const CONFIDENT_SCORE: f64 = 0.85;
+/// how far ahead of the next candidate a confident match has to be.
+const CONFIDENT_LEAD: f64 = 0.1;
+/// candidates worse than this are not considered at all.
+const MIN_SCORE: f64 = 0.4;
+
+/// lowercase, and drop anything in brackets or after " - ", which is usually something like
+/// "(feat. X)" or "- Remastered 2011", and any punctuation.
+pub(crate) fn normalize(s: &str) -> String {
+    let s = s.to_lowercase();
+    let s = s.split(" - ").next().unwrap_or_default();
+    let mut out = String::new();
+    let mut depth = 0;
+    for c in s.chars() {
+        match c {
+            '(' | '[' => depth += 1,
+            ')' | ']' => depth = (depth - 1).max(0),
+            _ if depth > 0 => (),
+            c if c.is_alphanumeric() => out.push(c),
+            _ => out.push(' '),
+        }
+    }
+    out.split_whitespace().collect::<Vec<&str>>().join(" ")
+}
+
+fn levenshtein(a: &[char], b: &[char]) -> usize {
+    let mut previous = (0..=b.len()).collect::<Vec<usize>>();
+    for (i, ca) in a.iter().enumerate() {
+        let mut current = vec![i + 1];
+        for (j, cb) in b.iter().enumerate() {
+            current.push(
+                (previous[j] + usize::from(ca != cb))
+                    .min(previous[j + 1] + 1)
+                    .min(current[j] + 1),
+            );
+        }
+        previous = current;
+    }
+    previous[b.len()]
+}
+
+/// how alike two strings are once normalized, from 0 to 1.
+pub fn similarity(a: &str, b: &str) -> f64 {
+    let a = normalize(a).chars().collect::<Vec<char>>();
+    let b = normalize(b).chars().collect::<Vec<char>>();
+    if a.is_empty() && b.is_empty() {
+        return 1.0;
+    }
+    1.0 - levenshtein(&a, &b) as f64 / a.len().max(b.len()) as f64
+}
+
+/// the artists named in a credit like "A feat. B & C".
+fn split_artists(s: &str) -> Vec<String> {
+    let mut s = s.to_lowercase();
+    for separator in [
+        " feat. ", " feat ", " ft. ", " & ", " and ", " x ", ";", "/",
+    ] {
+        s = s.replace(separator, ",");
+    }
+    s.split(',')
+        .map(|a| a.trim().to_string())
+        .filter(|a| !a.is_empty())
+        .collect()
+}
+
+/// how well the track matches the query, from 0 to 1. A matching ISRC is always a perfect match.
+pub fn score(query: &TrackQuery, track: &Track) -> f64 {
+    if let (Some(isrc), Some(other)) = (&query.isrc, track.isrc()) {
+        if isrc.eq_ignore_ascii_case(other) {
+            return 1.0;
+        }
+    }
+    let mut total = 0.5 * similarity(&query.title, &track.name);
+    let mut weight = 0.5;
+    if !query.artist.is_empty() {
+        let best = split_artists(&query.artist)
+            .iter()
+            .flat_map(|a| track.artists.iter().map(move |b| similarity(a, &b.name)))
+            .fold(
+                similarity(&query.artist, &track.artists.stringify()),
+                f64::max,
+            );
+        total += 0.35 * best;
+        weight += 0.35;
+    }
+    if let Some(duration) = query.duration_ms {
+        // within 2 seconds is the same, 30 seconds off is a different track
+        let diff = (duration as f64 - track.duration_ms as f64).abs() / 1000.0;
+        total += 0.15 * (1.0 - ((diff - 2.0) / 28.0).clamp(0.0, 1.0));
+        weight += 0.15;
+    }
+    if let Some(album) = &query.album {
+        total += 0.1 * similarity(album, &track.album);
+        weight += 0.1;
+    }
+    total / weight
+}
+
+/// whether two candidates are the same recording, e.g. released on an album and a compilation.
+fn same_recording(a: &Candidate, b: &Candidate) -> bool {
+    let (Some(a), Some(b)) = (&a.track, &b.track) else {
+        return false;
+    };
+    match (a.isrc(), b.isrc()) {
+        (Some(x), Some(y)) => x == y,
+        _ => {
+            normalize(&a.name) == normalize(&b.name)
+                && a.artists.first().map(|a| &a.name) == b.artists.first().map(|b| &b.name)
+        }
+    }
+}
+
+/// search Spotify for the query, and return the candidates worth considering, best first.
+pub fn candidates(query: &TrackQuery, acc: &mut Account) -> Result<Vec<Candidate>, String> {
+    let mut tracks = Vec::new();
+    if let Some(isrc) = &query.isrc {
+        tracks = spotify_api_search::<Track>(
+            format!("isrc:{}", isrc).as_str(),
+            &ContentType::Tracks,
+            acc,
+        )?;
+    }
+    if tracks.is_empty() && !query.artist.is_empty() {
+        tracks = spotify_api_search::<Track>(
+            format!("track:{} artist:{}", query.title, query.artist).as_str(),
+            &ContentType::Tracks,
+            acc,
+        )?;
+    }
+    if tracks.is_empty() {
+        tracks = spotify_api_search::<Track>(
+            format!("{} {}", query.artist, query.title).trim(),
+            &ContentType::Tracks,
+            acc,
+        )?;
+    }
+    let mut candidates = tracks
+        .into_iter()
+        .filter(|t| t.copy_problem().is_none())
+        .map(|t| Candidate {
+            score: score(query, &t),
+            track: Some(t),
+        })
+        .filter(|c| c.score >= MIN_SCORE)
+        .collect::<Vec<Candidate>>();
+    candidates.sort_by(|a, b| b.score.total_cmp(&a.score));
+    Ok(candidates)
+}
+
+/// whether the best of the candidates, which are sorted best first, is clearly the right one.
+fn is_clear(candidates: &[Candidate]) -> bool {
+    let Some(best) = candidates.first() else {
+        return false;
+    };
+    // the same recording is often on several albums, which shouldn't make the match unclear
+    let rival = candidates
+        .iter()
+        .skip(1)
+        .find(|c| !same_recording(best, c))
+        .map(|c| c.score);
+    best.score >= CONFIDENT_SCORE && best.score - rival.unwrap_or(0.0) >= CONFIDENT_LEAD
+}
+
+/// find the match for the query without asking, if there is a clear one.
+pub fn find_clear_match(
+    query: &TrackQuery,
+    acc: &mut Account,
+) -> Result<Option<Candidate>, String> {
+    let mut candidates = candidates(query, acc)?;
+    Ok(is_clear(&candidates).then(|| candidates.swap_remove(0)))
+}
+
+/// find the best match for the query. Clear matches are taken as they are; if it's unclear, the
+/// user chooses from the best few candidates.
+pub fn find_match(query: &TrackQuery, acc: &mut Account) -> Result<Option<Candidate>, String> {
+    let mut candidates = candidates(query, acc)?;
+    if candidates.is_empty() {
+        return Ok(None);
+    }
+    if is_clear(&candidates) {
+        return Ok(Some(candidates.swap_remove(0)));
+    }
+    candidates.truncate(5);
+    candidates.push(Candidate {
+        track: None,
+        score: 0.0,
+    });
+    let chosen = user_choose(
+        format!("Which of these is {}?", query).as_str(),
+        candidates,
+        0,
+    )?;
+    Ok(chosen.track.is_some().then_some(chosen))
+}
+
+/// like `find_match`, but looks the query up in the cache first, and stores the answer there, so
+/// the same track is only ever searched for once. Returns the URI of the match. The cache is saved
+/// every few matches, so save it once the last one is made.
+pub fn find_match_cached(
+    query: &TrackQuery,
+    acc: &mut Account,
+    cache: &mut MatchCache,
+) -> Result<Option<String>, String> {
+    if let Some(uri) = cache.get(query) {
+        return Ok(uri.clone());
+    }
+    let uri = match find_match(query, acc) {
+        Ok(found) => found.and_then(|c| c.track).map(|t| t.uri.uri),
+        Err(e) => {
+            // keep the matches made so far
+            cache.save()?;
+            return Err(e);
+        }
+    };
+    cache.insert(query, uri.clone());
+    if cache.unsaved >= SAVE_EVERY {
+        cache.save()?;
+    }
+    Ok(uri)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::data::Content;
+    use serde_json::json;
+
+    fn track(name: &str, artist: &str, album: &str, duration_ms: u32, isrc: Option<&str>) -> Track {
+        Track::from_json(&json!({
+            "name": name,
+            "artists": [{"name": artist, "uri": "spotify:artist:1"}],
+            "album": {"name": album},
+            "duration_ms": duration_ms,
+            "uri": "spotify:track:1",
+            "external_ids": isrc.map(|isrc| json!({"isrc": isrc})).unwrap_or(json!({})),
+        }))
+        .unwrap()
+    }
+
+    fn candidate(track: Track, score: f64) -> Candidate {
+        Candidate {
+            track: Some(track),
+            score,
+        }
+    }
+
+    #[test]
+    fn levenshtein_distances() {
+        let chars = |s: &str| s.chars().collect::<Vec<char>>();
+        for (a, b, distance) in [
+            ("", "", 0),
+            ("", "abc", 3),
+            ("abc", "abc", 0),
+            ("kitten", "sitting", 3),
+            ("flaw", "lawn", 2),
+        ] {
+            assert_eq!(levenshtein(&chars(a), &chars(b)), distance, "{} / {}", a, b);
+            assert_eq!(levenshtein(&chars(b), &chars(a)), distance, "{} / {}", b, a);
+        }
+    }
+
+    #[test]
+    fn similarity_ignores_case_features_and_versions() {
+        for (a, b, expected) in [
+            ("Hello", "HELLO", 1.0),
+            ("Hello", "Hello (feat. Someone)", 1.0),
+            ("Hello", "Hello [Live]", 1.0),
+            ("Yesterday", "Yesterday - Remastered 2009", 1.0),
+            ("Don't Stop", "dont stop", 0.9),
+            ("", "", 1.0),
+            ("abc", "xyz", 0.0),
+        ] {
+            assert!(
+                (similarity(a, b) - expected).abs() < 1e-9,
+                "{} / {}: {}",
+                a,
+                b,
+                similarity(a, b)
+            );
+        }
+        assert_eq!(normalize("  Hello,   World! (Remix) "), "hello world");
+    }
+
+    #[test]
+    fn splits_artist_credits() {
+        for (credit, expected) in [
+            ("A, B & C", vec!["a", "b", "c"]),
+            ("A feat. B", vec!["a", "b"]),
+            ("A ft. B and C", vec!["a", "b", "c"]),
+            ("A x B; C/D", vec!["a", "b", "c", "d"]),
+            ("Simon & Garfunkel", vec!["simon", "garfunkel"]),
+            ("", vec![]),
+        ] {
+            assert_eq!(split_artists(credit), expected, "{}", credit);
+        }
+    }
+
+    #[test]
+    fn isrc_and_duration_outrank_title_only_hits() {
+        let query = TrackQuery {
+            title: String::from("Hello"),
+            artist: String::from("Adele"),
+            album: None,
+            duration_ms: Some(295_000),
+            isrc: Some(String::from("GBBKS1500214")),
+        };
+        let title_only = track("Hello", "Lionel Richie", "Can't Slow Down", 250_000, None);
+        let by_isrc = track(
+            "Hello - Live",
+            "Adele",
+            "Live",
+            310_000,
+            Some("gbbks1500214"),
+        );
+        let close = track("Hello", "Adele", "25", 296_500, Some("GBBKS9999999"));
+        assert_eq!(score(&query, &by_isrc), 1.0);
+        assert!(score(&query, &close) > 0.95);
+        assert!(score(&query, &title_only) < score(&query, &close));
+        assert!(score(&query, &title_only) < CONFIDENT_SCORE);
+
+        let query = TrackQuery {
+            title: String::from("Hello"),
+            duration_ms: Some(295_000),
+            ..Default::default()
+        };
+        let close = track("Hello", "Adele", "25", 293_500, None);
+        let far = track("Hello", "Adele", "25", 355_000, None);
+        assert_eq!(score(&query, &close), 1.0);
+        assert!(score(&query, &far) < score(&query, &close));
+    }
+
+    #[test]
+    fn recognizes_the_same_recording() {
+        let original = track("Hello", "Adele", "25", 295_000, Some("GBBKS1500214"));
+        let compilation = track("Hello", "Adele", "Hits", 295_000, Some("GBBKS1500214"));
+        let live = track("Hello", "Adele", "Live", 310_000, Some("GBBKS1600001"));
+        let untagged = track("HELLO (Remastered)", "Adele", "Hits", 295_000, None);
+        let cover = track("Hello", "Someone Else", "Covers", 295_000, None);
+        for (a, b, expected) in [
+            (&original, &compilation, true),
+            (&original, &live, false),
+            (&original, &untagged, true),
+            (&original, &cover, false),
+        ] {
+            assert_eq!(
+                same_recording(&candidate(a.clone(), 1.0), &candidate(b.clone(), 1.0)),
+                expected,
+                "{} / {}",
+                a.album,
+                b.album
+            );
+        }
+        let none = Candidate {
+            track: None,
+            score: 0.0,
+        };
+        assert!(!same_recording(&none, &none));
+    }
+
+    #[test]
+    fn only_clear_matches_are_taken() {
+        let original = track("Hello", "Adele", "25", 295_000, Some("GBBKS1500214"));
+        let compilation = track("Hello", "Adele", "Hits", 295_000, Some("GBBKS1500214"));
+        let other = track("Hello", "Adele", "Live", 310_000, Some("GBBKS1600001"));
+        assert!(!is_clear(&[]));
+        assert!(is_clear(&[candidate(original.clone(), 0.9)]));
+        assert!(!is_clear(&[candidate(original.clone(), 0.8)]));
+        // two different recordings that score about the same
+        assert!(!is_clear(&[
+            candidate(original.clone(), 0.92),
+            candidate(other.clone(), 0.9),
+        ]));
+        assert!(is_clear(&[
+            candidate(original.clone(), 0.95),
+            candidate(other.clone(), 0.8),
+        ]));
+        // the same recording on another album isn't a rival
+        assert!(is_clear(&[
+            candidate(original.clone(), 0.95),
+            candidate(compilation, 0.95),
+            candidate(other, 0.5),
+        ]));
+    }
+}
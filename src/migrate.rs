@@ -0,0 +1,261 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+
+use crate::account::Account;
+use crate::api::{
+    do_api_json, get_all_pages, get_followed_artists, get_liked_songs, get_saved_albums,
+    get_saved_shows, not_in_library, save_liked_songs, save_to_library,
+};
+use crate::backup::{read_json, write_json};
+use crate::config::config_dir;
+use crate::data::{Content, Playlist, SpotifyURI};
+use crate::{info, user_yn, warn};
+
+/// how far a migration has got, saved after every step so an interrupted migration can carry on
+/// where it stopped.
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+pub struct Checkpoint {
+    pub started_at: u64,
+    /// URIs of the source playlists that have been copied or followed.
+    pub playlists: Vec<String>,
+    /// URIs of the copies that were created on the new account, by the URI of their source, until
+    /// their tracks are added. Resuming fills them instead of creating them again.
+    #[serde(default)]
+    pub created: HashMap<String, String>,
+    pub liked_songs: bool,
+    pub albums: bool,
+    pub shows: bool,
+    pub artists: bool,
+}
+
+/// what a migration did, for the summary at the end.
+#[derive(Debug, Default)]
+struct Summary {
+    copied: usize,
+    followed: usize,
+    liked_songs: usize,
+    albums: usize,
+    shows: usize,
+    artists: usize,
+    failed: Vec<String>,
+}
+
+fn checkpoint_file(src: &str, dst: &str) -> Result<PathBuf, String> {
+    let dir = config_dir()?.join("migrations");
+    std::fs::create_dir_all(&dir).map_err(|e| format!("failed to create {:?}: {}", dir, e))?;
+    Ok(dir.join(format!("{}-{}.json", src, dst)))
+}
+
+/// copy an owned playlist to the new account. The copy is saved in the checkpoint before its
+/// tracks are added, so that a migration that stops halfway doesn't create it twice.
+fn copy_playlist(
+    id: &str,
+    uri: &str,
+    src: &mut Account,
+    dst: &mut Account,
+    checkpoint: &mut Checkpoint,
+    path: &Path,
+) -> Result<(), String> {
+    let p = Playlist::from_id(id, src)?;
+    let copy = match checkpoint.created.get(uri) {
+        // some of the tracks may have been added already, so start over
+        Some(copy) => {
+            let copy = Playlist::from_id(SpotifyURI::from_str(copy.clone()).get_id(), dst)?;
+            copy.clear_tracks_online(dst, false)?;
+            copy.uri
+        }
+        None => {
+            let copy = p.create_copy(dst)?;
+            checkpoint
+                .created
+                .insert(uri.to_string(), copy.uri.uri.clone());
+            write_json(path, checkpoint)?;
+            copy.uri
+        }
+    };
+    p.fill_copy(&copy, dst)?;
+    checkpoint.created.remove(uri);
+    info!("recreated playlist {}", p.name);
+    Ok(())
+}
+
+/// move everything from one account to another: owned playlists are copied, followed playlists
+/// are followed, and liked songs, saved albums, saved shows and followed artists are saved.
+pub fn migrate(
+    src_name: &str,
+    src: &mut Account,
+    dst_name: &str,
+    dst: &mut Account,
+) -> Result<(), String> {
+    let path = checkpoint_file(src_name, dst_name)?;
+    let mut checkpoint = if path.exists() {
+        let checkpoint: Checkpoint = read_json(&path)?;
+        if user_yn(
+            format!(
+                "Found an unfinished migration from {} to {} ({} playlists done). Resume it?",
+                src_name,
+                dst_name,
+                checkpoint.playlists.len()
+            )
+            .as_str(),
+            true,
+        ) {
+            checkpoint
+        } else {
+            Checkpoint::default()
+        }
+    } else {
+        Checkpoint::default()
+    };
+    if checkpoint.started_at == 0 {
+        checkpoint.started_at = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs();
+        write_json(&path, &checkpoint)?;
+    }
+    let mut summary = Summary::default();
+
+    let src_id = src.get_id()?.to_string();
+    let page = do_api_json("GET", "me/playlists?limit=50", src, "")?;
+    let items = get_all_pages(src, &page)?;
+    // playlists are listed newest first, so go oldest first to keep the order on the new account
+    for (i, item) in items.iter().rev().enumerate() {
+        let uri = item["uri"].as_str().ok_or("no URI field")?;
+        let name = item["name"].as_str().unwrap_or(uri);
+        if checkpoint.playlists.iter().any(|p| p == uri) {
+            continue;
+        }
+        info!("Migrating playlist {}... ({}/{})", name, i + 1, items.len());
+        let id = item["id"].as_str().ok_or("no ID field")?;
+        let result = if item["owner"]["id"].as_str() == Some(src_id.as_str()) {
+            copy_playlist(id, uri, src, dst, &mut checkpoint, &path).map(|_| summary.copied += 1)
+        } else {
+            Playlist::from_id(id, src)
+                .and_then(|p| p.follow_online(dst))
+                .map(|_| summary.followed += 1)
+        };
+        match result {
+            Ok(()) => {
+                checkpoint.playlists.push(uri.to_string());
+                write_json(&path, &checkpoint)?;
+            }
+            Err(e) => {
+                warn!("failed to migrate playlist {}: {}", name, e);
+                summary.failed.push(name.to_string());
+            }
+        }
+    }
+
+    if !checkpoint.liked_songs {
+        info!("Migrating liked songs...");
+        let liked = get_liked_songs(src)?;
+        let tracks = not_in_library(
+            dst,
+            &liked.tracks,
+            |t| t.track.uri.get_id(),
+            "me/tracks/contains?ids=",
+            50,
+        )?;
+        save_liked_songs(dst, &tracks)?;
+        summary.liked_songs = tracks.len();
+        checkpoint.liked_songs = true;
+        write_json(&path, &checkpoint)?;
+    }
+
+    if !checkpoint.albums {
+        info!("Migrating saved albums...");
+        let albums = get_saved_albums(src)?;
+        let albums = not_in_library(
+            dst,
+            &albums,
+            |a| a.uri.get_id(),
+            "me/albums/contains?ids=",
+            20,
+        )?;
+        save_to_library(
+            dst,
+            "me/albums?ids=",
+            &albums
+                .iter()
+                .rev()
+                .map(|a| a.uri.get_id())
+                .collect::<Vec<&str>>(),
+            20,
+        )?;
+        summary.albums = albums.len();
+        checkpoint.albums = true;
+        write_json(&path, &checkpoint)?;
+    }
+
+    if !checkpoint.shows {
+        info!("Migrating saved shows...");
+        let shows = get_saved_shows(src)?;
+        let shows = not_in_library(
+            dst,
+            &shows,
+            |s| s.uri.get_id(),
+            "me/shows/contains?ids=",
+            50,
+        )?;
+        save_to_library(
+            dst,
+            "me/shows?ids=",
+            &shows
+                .iter()
+                .rev()
+                .map(|s| s.uri.get_id())
+                .collect::<Vec<&str>>(),
+            50,
+        )?;
+        summary.shows = shows.len();
+        checkpoint.shows = true;
+        write_json(&path, &checkpoint)?;
+    }
+
+    if !checkpoint.artists {
+        info!("Migrating followed artists...");
+        let artists = get_followed_artists(src)?;
+        let artists = not_in_library(
+            dst,
+            &artists,
+            |a| a.uri.get_id(),
+            "me/following/contains?type=artist&ids=",
+            50,
+        )?;
+        save_to_library(
+            dst,
+            "me/following?type=artist&ids=",
+            &artists
+                .iter()
+                .map(|a| a.uri.get_id())
+                .collect::<Vec<&str>>(),
+            50,
+        )?;
+        summary.artists = artists.len();
+        checkpoint.artists = true;
+        write_json(&path, &checkpoint)?;
+    }
+
+    println!("Migration from {} to {}:", src_name, dst_name);
+    println!("  copied {} playlists", summary.copied);
+    println!("  followed {} playlists", summary.followed);
+    println!("  saved {} liked songs", summary.liked_songs);
+    println!("  saved {} albums", summary.albums);
+    println!("  saved {} shows", summary.shows);
+    println!("  followed {} artists", summary.artists);
+    if !summary.failed.is_empty() {
+        warn!(
+            "{} playlists failed to migrate: {}. Run migrate again to retry them",
+            summary.failed.len(),
+            summary.failed.join(", ")
+        );
+        return Ok(());
+    }
+    std::fs::remove_file(&path).map_err(|e| format!("failed to remove checkpoint: {}", e))?;
+    info!("Migration complete");
+    Ok(())
+}
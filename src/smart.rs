@@ -0,0 +1,529 @@
+use std::fmt::{Display, Formatter};
+
+use crossterm::style::Stylize;
+use serde::{Deserialize, Serialize};
+
+use crate::account::Account;
+use crate::api::get_liked_songs;
+use crate::data::{Content, Playlist, PlaylistTrack, SpotifyURI};
+use crate::import::parse_duration;
+use crate::utils::rfc3339_to_epoch_time;
+use crate::{info, user_yn, warn};
+
+/// a playlist whose tracks are picked from its sources by a filter expression, e.g.
+/// `artist ~ "daft punk" and duration < 300 and not explicit`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SmartPlaylist {
+    pub name: String,
+    pub account: String,
+    /// URIs of the source playlists, or `liked` for the account's liked songs.
+    pub sources: Vec<String>,
+    pub filter: String,
+    /// the URI of the real playlist this syncs into. Empty until the first refresh creates it.
+    #[serde(default)]
+    pub uri: String,
+}
+
+impl Display for SmartPlaylist {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&format!(
+            "{} on {} ({} sources): {}",
+            self.name.as_str().blue().bold(),
+            self.account,
+            self.sources.len(),
+            self.filter.as_str().blue()
+        ))
+    }
+}
+
+/// a track from one or more of a smart playlist's sources.
+struct Candidate {
+    track: PlaylistTrack,
+    sources: Vec<String>,
+}
+
+impl SmartPlaylist {
+    /// evaluate the filter over the sources and sync the result into the real playlist, creating
+    /// it if it doesn't exist yet.
+    pub fn refresh(&mut self, acc: &mut Account) -> Result<(), String> {
+        let filter = Expr::parse(&self.filter)?;
+        let mut candidates: Vec<Candidate> = Vec::new();
+        for source in &self.sources {
+            let playlist = if source == "liked" {
+                get_liked_songs(acc)?
+            } else {
+                Playlist::from_id(SpotifyURI::from_str(source.clone()).get_id(), acc)?
+            };
+            for track in playlist.tracks {
+                match candidates
+                    .iter_mut()
+                    .find(|c| c.track.track.uri.uri == track.track.uri.uri)
+                {
+                    Some(c) => c.sources.push(playlist.name.clone()),
+                    None => candidates.push(Candidate {
+                        track,
+                        sources: vec![playlist.name.clone()],
+                    }),
+                }
+            }
+        }
+        let matched = candidates
+            .into_iter()
+            .filter(|c| filter.matches(c))
+            .map(|c| c.track)
+            .collect::<Vec<PlaylistTrack>>();
+        info!(
+            "{} tracks match smart playlist {}",
+            matched.len(),
+            self.name
+        );
+        if self.uri.is_empty() {
+            let playlist = Playlist::create_from_vec(
+                acc,
+                matched.into_iter().map(|t| t.track).collect(),
+                self.name.clone(),
+                Some(format!("smart playlist: {}", self.filter)),
+            )?;
+            self.uri = playlist.uri.uri;
+            return Ok(());
+        }
+        let mut target = Playlist::from_id(SpotifyURI::from_str(self.uri.clone()).get_id(), acc)?;
+        let current = target.tracks.clone();
+        // a filter that suddenly matches much less, e.g. because a source was emptied, shouldn't
+        // wipe the playlist without asking
+        let removed = current
+            .iter()
+            .filter(|t| !matched.iter().any(|m| m.track.uri.uri == t.track.uri.uri))
+            .count();
+        if removed > 0
+            && (matched.is_empty() || matched.len() * 2 < current.len())
+            && !user_yn(
+                format!(
+                    "Refreshing {} would remove {} of its {} tracks and leave {}. Continue?",
+                    self.name,
+                    removed,
+                    current.len(),
+                    matched.len()
+                )
+                .as_str(),
+                false,
+            )
+        {
+            warn!("Skipped refreshing smart playlist {}", self.name);
+            return Ok(());
+        }
+        target.tracks = current
+            .iter()
+            .filter(|t| !matched.iter().any(|m| m.track.uri.uri == t.track.uri.uri))
+            .cloned()
+            .collect();
+        if !target.tracks.is_empty() {
+            target.clear_tracks_online(acc, false)?;
+        }
+        target.tracks = matched
+            .into_iter()
+            .filter(|m| !current.iter().any(|t| t.track.uri.uri == m.track.uri.uri))
+            .collect();
+        if !target.tracks.is_empty() {
+            target.put_tracks_online(acc, false)?;
+        }
+        info!("Refreshed smart playlist {}", self.name);
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Field {
+    Name,
+    Artist,
+    Album,
+    Duration,
+    Explicit,
+    AddedAt,
+    Year,
+    Source,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Op {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    Contains,
+    NotContains,
+}
+
+#[derive(Debug)]
+pub enum Expr {
+    And(Box<Expr>, Box<Expr>),
+    Or(Box<Expr>, Box<Expr>),
+    Not(Box<Expr>),
+    /// a bare `explicit`, the only field that is true or false on its own.
+    Explicit,
+    Compare(Field, Op, String),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Word(String),
+    Quoted(String),
+    Op(Op),
+    And,
+    Or,
+    Not,
+    Open,
+    Close,
+}
+
+impl Field {
+    fn from_str(s: &str) -> Option<Field> {
+        match s.to_lowercase().as_str() {
+            "name" | "title" => Some(Field::Name),
+            "artist" | "artists" => Some(Field::Artist),
+            "album" => Some(Field::Album),
+            "duration" | "length" => Some(Field::Duration),
+            "explicit" => Some(Field::Explicit),
+            "added" | "added_at" => Some(Field::AddedAt),
+            "year" | "release_year" => Some(Field::Year),
+            "source" | "playlist" => Some(Field::Source),
+            _ => None,
+        }
+    }
+}
+
+fn tokenize(s: &str) -> Result<Vec<Token>, String> {
+    let mut tokens = Vec::new();
+    let mut chars = s.chars().peekable();
+    while let Some(&c) = chars.peek() {
+        match c {
+            ' ' | '\t' => {
+                chars.next();
+            }
+            '(' => {
+                chars.next();
+                tokens.push(Token::Open);
+            }
+            ')' => {
+                chars.next();
+                tokens.push(Token::Close);
+            }
+            '"' | '\'' => {
+                chars.next();
+                let mut quoted = String::new();
+                loop {
+                    match chars.next() {
+                        Some(q) if q == c => break,
+                        Some(q) => quoted.push(q),
+                        None => return Err(format!("Unterminated string in filter: {}", s)),
+                    }
+                }
+                tokens.push(Token::Quoted(quoted));
+            }
+            '=' | '!' | '<' | '>' | '~' | '&' | '|' => {
+                let mut op = String::new();
+                while let Some(&o) = chars.peek() {
+                    if !"=!<>~&|".contains(o) {
+                        break;
+                    }
+                    op.push(o);
+                    chars.next();
+                }
+                tokens.push(match op.as_str() {
+                    "=" | "==" => Token::Op(Op::Eq),
+                    "!=" => Token::Op(Op::Ne),
+                    "<" => Token::Op(Op::Lt),
+                    "<=" => Token::Op(Op::Le),
+                    ">" => Token::Op(Op::Gt),
+                    ">=" => Token::Op(Op::Ge),
+                    "~" => Token::Op(Op::Contains),
+                    "!~" => Token::Op(Op::NotContains),
+                    "&&" => Token::And,
+                    "||" => Token::Or,
+                    "!" => Token::Not,
+                    _ => return Err(format!("Unknown operator in filter: {}", op)),
+                });
+            }
+            _ => {
+                let mut word = String::new();
+                while let Some(&w) = chars.peek() {
+                    if " \t()\"'=!<>~&|".contains(w) {
+                        break;
+                    }
+                    word.push(w);
+                    chars.next();
+                }
+                tokens.push(match word.to_lowercase().as_str() {
+                    "and" => Token::And,
+                    "or" => Token::Or,
+                    "not" => Token::Not,
+                    _ => Token::Word(word),
+                });
+            }
+        }
+    }
+    Ok(tokens)
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+}
+
+impl Parser {
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.pos).cloned();
+        self.pos += 1;
+        token
+    }
+
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+
+    fn or(&mut self) -> Result<Expr, String> {
+        let mut left = self.and()?;
+        while self.peek() == Some(&Token::Or) {
+            self.pos += 1;
+            left = Expr::Or(Box::new(left), Box::new(self.and()?));
+        }
+        Ok(left)
+    }
+
+    fn and(&mut self) -> Result<Expr, String> {
+        let mut left = self.unary()?;
+        while self.peek() == Some(&Token::And) {
+            self.pos += 1;
+            left = Expr::And(Box::new(left), Box::new(self.unary()?));
+        }
+        Ok(left)
+    }
+
+    fn unary(&mut self) -> Result<Expr, String> {
+        match self.next() {
+            Some(Token::Not) => Ok(Expr::Not(Box::new(self.unary()?))),
+            Some(Token::Open) => {
+                let expr = self.or()?;
+                match self.next() {
+                    Some(Token::Close) => Ok(expr),
+                    _ => Err("Expected ')' in filter".to_string()),
+                }
+            }
+            Some(Token::Word(word)) => {
+                let field = Field::from_str(&word).ok_or(format!(
+                    "Unknown field in filter: {}. Valid fields are: 'name', 'artist', 'album', 'duration', 'explicit', 'added_at', 'year', 'source'",
+                    word
+                ))?;
+                match self.peek() {
+                    Some(Token::Op(op)) => {
+                        let op = *op;
+                        self.pos += 1;
+                        match self.next() {
+                            Some(Token::Word(value)) | Some(Token::Quoted(value)) => {
+                                Ok(Expr::Compare(field, op, parse_value(field, &value)?))
+                            }
+                            _ => Err(format!("Expected a value after {}", word)),
+                        }
+                    }
+                    _ if field == Field::Explicit => Ok(Expr::Explicit),
+                    _ => Err(format!(
+                        "Expected an operator after {}, as in '{} ~ value'",
+                        word, word
+                    )),
+                }
+            }
+            Some(token) => Err(format!("Unexpected {:?} in filter", token)),
+            None => Err("Unexpected end of filter".to_string()),
+        }
+    }
+}
+
+impl Expr {
+    pub fn parse(s: &str) -> Result<Expr, String> {
+        let mut parser = Parser {
+            tokens: tokenize(s)?,
+            pos: 0,
+        };
+        let expr = parser.or()?;
+        match parser.peek() {
+            None => Ok(expr),
+            Some(token) => Err(format!("Unexpected {:?} in filter", token)),
+        }
+    }
+
+    fn matches(&self, c: &Candidate) -> bool {
+        let track = &c.track.track;
+        match self {
+            Expr::And(a, b) => a.matches(c) && b.matches(c),
+            Expr::Or(a, b) => a.matches(c) || b.matches(c),
+            Expr::Not(a) => !a.matches(c),
+            Expr::Explicit => track.explicit,
+            Expr::Compare(field, op, value) => match field {
+                Field::Name => compare_str(&track.name, *op, value),
+                Field::Album => compare_str(&track.album, *op, value),
+                Field::Artist => any_str(track.artists.iter().map(|a| &a.name), *op, value),
+                Field::Source => any_str(c.sources.iter(), *op, value),
+                Field::Explicit => compare_str(&track.explicit.to_string(), *op, value),
+                Field::Duration => compare_num(track.duration() as u64, *op, value),
+                Field::Year => compare_num(
+                    track
+                        .release_date
+                        .get(..4)
+                        .and_then(|y| y.parse::<u64>().ok())
+                        .unwrap_or(0),
+                    *op,
+                    value,
+                ),
+                Field::AddedAt => compare_num(c.track.added_at, *op, value),
+            },
+        }
+    }
+}
+
+/// dates in filters are written as `YYYY-MM-DD`, and compared against `added_at` as timestamps.
+fn date_to_epoch(date: &str) -> Result<u64, String> {
+    rfc3339_to_epoch_time(&format!("{}T00:00:00Z", date))
+        .map_err(|_| format!("Invalid date in filter: {}. Use YYYY-MM-DD", date))
+}
+
+/// check the value a field is compared to, and turn it into the form it's compared in: dates into
+/// timestamps and durations into seconds. A value that can never match is an error, rather than a
+/// filter that quietly matches nothing.
+fn parse_value(field: Field, value: &str) -> Result<String, String> {
+    match field {
+        Field::AddedAt => Ok(date_to_epoch(value)?.to_string()),
+        Field::Duration => parse_duration(value)
+            .map(|ms| (ms / 1000).to_string())
+            .ok_or(format!(
+                "Invalid duration in filter: {}. Use seconds or m:ss",
+                value
+            )),
+        Field::Year => value
+            .parse::<u64>()
+            .ok()
+            .filter(|y| (1000..=9999).contains(y))
+            .map(|y| y.to_string())
+            .ok_or(format!(
+                "Invalid year in filter: {}. Use a year like 1999",
+                value
+            )),
+        Field::Explicit => match value.to_lowercase().as_str() {
+            "true" | "false" => Ok(value.to_lowercase()),
+            _ => Err(format!(
+                "Invalid value for explicit in filter: {}. Use true or false",
+                value
+            )),
+        },
+        _ => Ok(value.to_string()),
+    }
+}
+
+/// for fields with several values, like artists, `!=` and `!~` hold only if no value matches.
+fn any_str<'a>(mut values: impl Iterator<Item = &'a String>, op: Op, value: &str) -> bool {
+    match op {
+        Op::Ne => !values.any(|v| compare_str(v, Op::Eq, value)),
+        Op::NotContains => !values.any(|v| compare_str(v, Op::Contains, value)),
+        _ => values.any(|v| compare_str(v, op, value)),
+    }
+}
+
+fn compare_str(a: &str, op: Op, b: &str) -> bool {
+    let a = a.to_lowercase();
+    let b = b.to_lowercase();
+    match op {
+        Op::Eq => a == b,
+        Op::Ne => a != b,
+        Op::Lt => a < b,
+        Op::Le => a <= b,
+        Op::Gt => a > b,
+        Op::Ge => a >= b,
+        Op::Contains => a.contains(&b),
+        Op::NotContains => !a.contains(&b),
+    }
+}
+
+fn compare_num(a: u64, op: Op, b: &str) -> bool {
+    let b = match b.parse::<u64>() {
+        Ok(b) => b,
+        Err(_) => return false,
+    };
+    match op {
+        Op::Eq | Op::Contains => a == b,
+        Op::Ne | Op::NotContains => a != b,
+        Op::Lt => a < b,
+        Op::Le => a <= b,
+        Op::Gt => a > b,
+        Op::Ge => a >= b,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parsed(s: &str) -> String {
+        format!("{:?}", Expr::parse(s).unwrap())
+    }
+
+    #[test]
+    fn parses_comparisons() {
+        assert_eq!(
+            parsed("artist ~ queen"),
+            r#"Compare(Artist, Contains, "queen")"#
+        );
+        assert_eq!(
+            parsed("name != 'Under Pressure'"),
+            r#"Compare(Name, Ne, "Under Pressure")"#
+        );
+        assert_eq!(parsed("duration < 3:30"), r#"Compare(Duration, Lt, "210")"#);
+        assert_eq!(parsed("length>=90"), r#"Compare(Duration, Ge, "90")"#);
+        assert_eq!(parsed("year <= 1999"), r#"Compare(Year, Le, "1999")"#);
+        assert_eq!(
+            parsed("added_at > 2020-01-01"),
+            r#"Compare(AddedAt, Gt, "1577836800")"#
+        );
+        assert_eq!(
+            parsed("explicit == FALSE"),
+            r#"Compare(Explicit, Eq, "false")"#
+        );
+        assert_eq!(
+            parsed("source !~ \"old stuff\""),
+            r#"Compare(Source, NotContains, "old stuff")"#
+        );
+    }
+
+    #[test]
+    fn and_binds_tighter_than_or() {
+        assert_eq!(
+            parsed("album = a or artist = b and not explicit"),
+            r#"Or(Compare(Album, Eq, "a"), And(Compare(Artist, Eq, "b"), Not(Explicit)))"#
+        );
+        assert_eq!(
+            parsed("(album = a || artist = b) && !explicit"),
+            r#"And(Or(Compare(Album, Eq, "a"), Compare(Artist, Eq, "b")), Not(Explicit))"#
+        );
+    }
+
+    #[test]
+    fn rejects_invalid_filters() {
+        for s in [
+            "",
+            "album",
+            "genre ~ rock",
+            "artist ~",
+            "artist ~ 'queen",
+            "artist => queen",
+            "(artist ~ queen",
+            "artist ~ queen)",
+            "artist ~ queen and",
+            "duration < 3m30",
+            "year > 90s",
+            "added_at > yesterday",
+            "explicit = yes",
+        ] {
+            assert!(Expr::parse(s).is_err(), "{} should be rejected", s);
+        }
+    }
+}
@@ -0,0 +1,312 @@
+use std::fmt::{Display, Formatter};
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::path::PathBuf;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crossterm::style::Stylize;
+use serde::{Deserialize, Serialize};
+
+use crate::account::Account;
+use crate::config::config_dir;
+use crate::data::{Playlist, SpotifyURI, Stringify};
+use crate::utils::epoch_time_to_rfc3339;
+use crate::{info, user_yn};
+
+/// a playlist whose history is being kept, so that it can be restored if it gets vandalized.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TrackedPlaylist {
+    pub name: String,
+    pub account: String,
+    pub uri: String,
+}
+
+impl Display for TrackedPlaylist {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&format!(
+            "{} on {}",
+            self.name.as_str().blue().bold(),
+            self.account
+        ))
+    }
+}
+
+/// the state of a playlist at one point in time. Versions are appended to a file per playlist and
+/// never rewritten.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PlaylistVersion {
+    pub version: usize,
+    pub snapshot_id: String,
+    pub recorded_at: u64,
+    pub name: String,
+    pub description: String,
+    pub tracks: Vec<VersionTrack>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct VersionTrack {
+    pub uri: String,
+    pub name: String,
+    pub artists: String,
+    pub added_at: u64,
+}
+
+impl Display for VersionTrack {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&format!(
+            "{} by {}",
+            self.name.as_str().blue().bold(),
+            self.artists.as_str().blue()
+        ))
+    }
+}
+
+fn history_file(uri: &SpotifyURI) -> Result<PathBuf, String> {
+    let dir = config_dir()?.join("history");
+    std::fs::create_dir_all(&dir).map_err(|e| format!("failed to create {:?}: {}", dir, e))?;
+    Ok(dir.join(format!("{}.jsonl", uri.get_id())))
+}
+
+/// load every recorded version of the given playlist, oldest first.
+pub fn load_versions(uri: &SpotifyURI) -> Result<Vec<PlaylistVersion>, String> {
+    let path = history_file(uri)?;
+    if !path.exists() {
+        return Ok(Vec::new());
+    }
+    BufReader::new(File::open(&path).map_err(|e| format!("failed to open {:?}: {}", path, e))?)
+        .lines()
+        .filter(|line| !line.as_ref().map(|l| l.trim().is_empty()).unwrap_or(false))
+        .map(|line| {
+            serde_json::from_str(&line.map_err(|e| e.to_string())?)
+                .map_err(|e| format!("corrupt history in {:?}: {}", path, e))
+        })
+        .collect()
+}
+
+/// record the current state of the playlist if it differs from the last recorded version.
+/// returns the number of the new version, if one was recorded.
+pub fn record(playlist: &Playlist) -> Result<Option<usize>, String> {
+    let versions = load_versions(&playlist.uri)?;
+    let tracks = playlist
+        .tracks
+        .iter()
+        .map(|t| VersionTrack {
+            uri: t.track.uri.uri.clone(),
+            name: t.track.name.clone(),
+            artists: t.track.artists.stringify(),
+            added_at: t.added_at,
+        })
+        .collect::<Vec<VersionTrack>>();
+    if let Some(last) = versions.last() {
+        if last.snapshot_id == playlist.snapshot_id
+            || (last.name == playlist.name
+                && last.description == playlist.description
+                && last
+                    .tracks
+                    .iter()
+                    .map(|t| &t.uri)
+                    .eq(tracks.iter().map(|t| &t.uri)))
+        {
+            return Ok(None);
+        }
+    }
+    let version = PlaylistVersion {
+        version: versions.len() + 1,
+        snapshot_id: playlist.snapshot_id.clone(),
+        recorded_at: SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs(),
+        name: playlist.name.clone(),
+        description: playlist.description.clone(),
+        tracks,
+    };
+    let path = history_file(&playlist.uri)?;
+    let mut file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&path)
+        .map_err(|e| format!("failed to open {:?}: {}", path, e))?;
+    writeln!(
+        file,
+        "{}",
+        serde_json::to_string(&version).map_err(|e| e.to_string())?
+    )
+    .map_err(|e| format!("failed to write {:?}: {}", path, e))?;
+    Ok(Some(version.version))
+}
+
+/// the tracks added and removed between two versions. Duplicates are counted, so adding a second
+/// copy of a track shows up as an addition.
+pub fn diff<'a>(
+    old: &'a PlaylistVersion,
+    new: &'a PlaylistVersion,
+) -> (Vec<&'a VersionTrack>, Vec<&'a VersionTrack>) {
+    let mut removed = old.tracks.iter().collect::<Vec<&VersionTrack>>();
+    let mut added = Vec::new();
+    for track in &new.tracks {
+        match removed.iter().position(|t| t.uri == track.uri) {
+            Some(i) => {
+                removed.remove(i);
+            }
+            None => added.push(track),
+        }
+    }
+    (added, removed)
+}
+
+/// print what changed in each version of a playlist.
+pub fn print_log(versions: &[PlaylistVersion]) {
+    for (i, version) in versions.iter().enumerate() {
+        println!(
+            "{} recorded {} ({} tracks, snapshot {})",
+            format!("version {}", version.version).bold(),
+            epoch_time_to_rfc3339(version.recorded_at),
+            version.tracks.len(),
+            version.snapshot_id
+        );
+        let previous = match i {
+            0 => continue,
+            _ => &versions[i - 1],
+        };
+        if previous.name != version.name {
+            println!("  renamed from {} to {}", previous.name, version.name);
+        }
+        if previous.description != version.description {
+            println!("  description changed to '{}'", version.description);
+        }
+        let (added, removed) = diff(previous, version);
+        let reordered = added.is_empty()
+            && removed.is_empty()
+            && previous
+                .tracks
+                .iter()
+                .zip(&version.tracks)
+                .any(|(a, b)| a.uri != b.uri);
+        for track in added {
+            println!("  {} {}", "+".green().bold(), track);
+        }
+        for track in removed {
+            println!("  {} {}", "-".red().bold(), track);
+        }
+        if reordered {
+            println!("  tracks reordered");
+        }
+    }
+}
+
+/// restore the online playlist to the given version: its name, description and tracks in order.
+pub fn checkout(
+    user: &mut Account,
+    playlist: &mut Playlist,
+    version: &PlaylistVersion,
+) -> Result<(), String> {
+    let current = load_versions(&playlist.uri)?;
+    if let Some(last) = current.last() {
+        let (added, removed) = diff(last, version);
+        println!(
+            "Checking out version {} will add {} and remove {} tracks:",
+            version.version,
+            added.len(),
+            removed.len()
+        );
+        for track in added {
+            println!("  {} {}", "+".green().bold(), track);
+        }
+        for track in removed {
+            println!("  {} {}", "-".red().bold(), track);
+        }
+    }
+    if !user_yn(
+        format!(
+            "Restore {} to version {} ({})?",
+            playlist.name,
+            version.version,
+            epoch_time_to_rfc3339(version.recorded_at)
+        )
+        .as_str(),
+        false,
+    ) {
+        return Err("Aborted".to_string());
+    }
+    playlist.name = version.name.clone();
+    playlist.description = version.description.clone();
+    playlist.update_details_online(user)?;
+    playlist.replace_tracks_online(
+        user,
+        &version
+            .tracks
+            .iter()
+            .map(|t| t.uri.as_str())
+            .collect::<Vec<&str>>(),
+    )?;
+    info!("Restored {} to version {}", playlist.name, version.version);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// a version with tracks of the given URIs, each added at its index.
+    fn version(uris: &[&str]) -> PlaylistVersion {
+        PlaylistVersion {
+            version: 0,
+            snapshot_id: String::new(),
+            recorded_at: 0,
+            name: String::from("Playlist"),
+            description: String::new(),
+            tracks: uris
+                .iter()
+                .enumerate()
+                .map(|(i, uri)| VersionTrack {
+                    uri: uri.to_string(),
+                    name: uri.to_uppercase(),
+                    artists: String::from("Artist"),
+                    added_at: i as u64,
+                })
+                .collect(),
+        }
+    }
+
+    /// the URIs and positions of the tracks, as they were added to their version.
+    fn summary(tracks: &[&VersionTrack]) -> Vec<(String, u64)> {
+        tracks.iter().map(|t| (t.uri.clone(), t.added_at)).collect()
+    }
+
+    #[test]
+    fn diffs_versions() {
+        for (old, new, added, removed) in [
+            (vec![], vec![], vec![], vec![]),
+            (vec!["a", "b"], vec!["a", "b"], vec![], vec![]),
+            (vec![], vec!["a"], vec![("a", 0)], vec![]),
+            (vec!["a"], vec![], vec![], vec![("a", 0)]),
+            // a move alone changes nothing
+            (vec!["a", "b", "c"], vec!["c", "b", "a"], vec![], vec![]),
+            // a second copy of a track is an addition
+            (vec!["a", "b"], vec!["a", "b", "a"], vec![("a", 2)], vec![]),
+            // and removing one of two copies is a removal
+            (vec!["a", "b", "a"], vec!["b", "a"], vec![], vec![("a", 2)]),
+            // a move, a duplicate and a removal at once
+            (
+                vec!["a", "b", "c", "d"],
+                vec!["d", "a", "c", "a"],
+                vec![("a", 3)],
+                vec![("b", 1)],
+            ),
+        ] {
+            let (old, new) = (version(&old), version(&new));
+            let expected = |tracks: Vec<(&str, u64)>| {
+                tracks
+                    .into_iter()
+                    .map(|(uri, i)| (uri.to_string(), i))
+                    .collect::<Vec<(String, u64)>>()
+            };
+            let (a, r) = diff(&old, &new);
+            assert_eq!(
+                (summary(&a), summary(&r)),
+                (expected(added), expected(removed))
+            );
+        }
+    }
+}
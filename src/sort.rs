@@ -0,0 +1,215 @@
+use std::cmp::Ordering;
+
+use crate::data::PlaylistTrack;
+
+#[derive(Debug, Clone, Copy)]
+pub enum SortField {
+    Name,
+    Artist,
+    Duration,
+    AddedAt,
+    ReleaseDate,
+    Popularity,
+    /// album name, then disc and track number, so albums play in order.
+    Album,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct SortKey {
+    pub field: SortField,
+    pub descending: bool,
+}
+
+impl SortField {
+    pub fn from_str(s: &str) -> Option<SortField> {
+        match s.to_lowercase().as_str() {
+            "name" | "title" => Some(SortField::Name),
+            "artist" | "artists" => Some(SortField::Artist),
+            "duration" | "length" => Some(SortField::Duration),
+            "added" | "added_at" => Some(SortField::AddedAt),
+            "release" | "release_date" | "released" => Some(SortField::ReleaseDate),
+            "popularity" | "popular" => Some(SortField::Popularity),
+            "album" => Some(SortField::Album),
+            _ => None,
+        }
+    }
+
+    fn compare(&self, a: &PlaylistTrack, b: &PlaylistTrack) -> Ordering {
+        match self {
+            SortField::Name => a
+                .track
+                .name
+                .to_lowercase()
+                .cmp(&b.track.name.to_lowercase()),
+            SortField::Artist => primary_artist(a).cmp(&primary_artist(b)),
+            SortField::Duration => a.track.duration_ms.cmp(&b.track.duration_ms),
+            SortField::AddedAt => a.added_at.cmp(&b.added_at),
+            SortField::ReleaseDate => a.track.release_date.cmp(&b.track.release_date),
+            SortField::Popularity => a.track.popularity.cmp(&b.track.popularity),
+            SortField::Album => a
+                .track
+                .album
+                .to_lowercase()
+                .cmp(&b.track.album.to_lowercase())
+                .then(a.track.disc_number.cmp(&b.track.disc_number))
+                .then(a.track.track_number.cmp(&b.track.track_number)),
+        }
+    }
+}
+
+impl SortKey {
+    /// parse a sort key such as `name`, `duration:desc` or `-added_at`. Keys are ascending unless
+    /// they are suffixed with `:desc` or prefixed with `-`.
+    pub fn from_str(s: &str) -> Result<SortKey, String> {
+        let (s, mut descending) = match s.strip_prefix('-') {
+            Some(s) => (s, true),
+            None => (s, false),
+        };
+        let mut split = s.split(':');
+        let field = split.next().unwrap_or_default();
+        match split.next() {
+            Some("asc") => descending = false,
+            Some("desc") => descending = true,
+            Some(other) => return Err(format!("Invalid sort direction: {}", other)),
+            None => (),
+        }
+        Ok(SortKey {
+            field: SortField::from_str(field).ok_or(format!(
+                "Invalid sort key: {}. Valid keys are: 'name', 'artist', 'duration', 'added_at', 'release_date', 'popularity', 'album'",
+                field
+            ))?,
+            descending,
+        })
+    }
+}
+
+fn primary_artist(track: &PlaylistTrack) -> String {
+    track
+        .track
+        .artists
+        .first()
+        .map(|a| a.name.to_lowercase())
+        .unwrap_or_default()
+}
+
+/// returns the order the tracks should be in according to the given keys, as a list of the
+/// current positions. The sort is stable, so tracks that compare equal keep their relative order.
+pub fn sorted_order(tracks: &[PlaylistTrack], keys: &[SortKey]) -> Vec<usize> {
+    let mut order = (0..tracks.len()).collect::<Vec<usize>>();
+    order.sort_by(|&a, &b| {
+        keys.iter()
+            .map(|key| {
+                let ord = key.field.compare(&tracks[a], &tracks[b]);
+                if key.descending {
+                    ord.reverse()
+                } else {
+                    ord
+                }
+            })
+            .find(|ord| ord.is_ne())
+            .unwrap_or(Ordering::Equal)
+    });
+    order
+}
+
+/// plan the moves needed to put a playlist into the given order, as `(range_start, insert_before)`
+/// pairs for the reorder endpoint, each to be applied after the previous one. <br>
+/// tracks in the longest run that is already in order stay where they are, so only the minimum
+/// number of tracks are moved.
+pub fn plan_moves(order: &[usize]) -> Vec<(usize, usize)> {
+    let mut current = vec![0; order.len()];
+    for (new, &old) in order.iter().enumerate() {
+        current[old] = new;
+    }
+    let mut placed = vec![false; current.len()];
+    for i in longest_increasing(&current) {
+        placed[current[i]] = true;
+    }
+    let mut moves = Vec::new();
+    for rank in 0..current.len() {
+        if placed[rank] {
+            continue;
+        }
+        let from = current.iter().position(|&r| r == rank).unwrap();
+        let to = (0..rank)
+            .rev()
+            .find(|&r| placed[r])
+            .map(|r| current.iter().position(|&x| x == r).unwrap() + 1)
+            .unwrap_or(0);
+        if to != from && to != from + 1 {
+            moves.push((from, to));
+            current.remove(from);
+            current.insert(if to > from { to - 1 } else { to }, rank);
+        }
+        placed[rank] = true;
+    }
+    moves
+}
+
+/// the indices of a longest strictly increasing subsequence of the given values.
+fn longest_increasing(values: &[usize]) -> Vec<usize> {
+    let mut tails: Vec<usize> = Vec::new();
+    let mut prev = vec![None; values.len()];
+    for i in 0..values.len() {
+        let pos = tails.partition_point(|&t| values[t] < values[i]);
+        if pos > 0 {
+            prev[i] = Some(tails[pos - 1]);
+        }
+        if pos == tails.len() {
+            tails.push(i);
+        } else {
+            tails[pos] = i;
+        }
+    }
+    let mut out = Vec::new();
+    let mut next = tails.last().copied();
+    while let Some(i) = next {
+        out.push(i);
+        next = prev[i];
+    }
+    out.reverse();
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::rngs::StdRng;
+    use rand::seq::SliceRandom;
+    use rand::SeedableRng;
+
+    use super::*;
+
+    /// apply the moves the way the reorder endpoint does, to a playlist whose tracks are numbered
+    /// by their current position.
+    fn apply(len: usize, moves: &[(usize, usize)]) -> Vec<usize> {
+        let mut tracks = (0..len).collect::<Vec<usize>>();
+        for &(from, to) in moves {
+            let track = tracks.remove(from);
+            tracks.insert(if to > from { to - 1 } else { to }, track);
+        }
+        tracks
+    }
+
+    #[test]
+    fn moves_reproduce_the_order() {
+        let mut rng = StdRng::seed_from_u64(26);
+        for len in 0..40 {
+            for _ in 0..20 {
+                let mut order = (0..len).collect::<Vec<usize>>();
+                order.shuffle(&mut rng);
+                let moves = plan_moves(&order);
+                assert_eq!(apply(len, &moves), order, "order {:?}", order);
+                // every track outside the longest run that is in order is moved once
+                assert_eq!(moves.len(), len - longest_increasing(&order).len());
+            }
+        }
+    }
+
+    #[test]
+    fn moves_as_few_tracks_as_needed() {
+        assert!(plan_moves(&[0, 1, 2, 3]).is_empty());
+        assert_eq!(plan_moves(&[1, 2, 3, 0]), vec![(0, 4)]);
+        assert_eq!(plan_moves(&[3, 0, 1, 2]), vec![(3, 0)]);
+        assert_eq!(plan_moves(&[4, 3, 2, 1, 0]).len(), 4);
+    }
+}
@@ -0,0 +1,94 @@
+use crate::data::{PlaylistTrack, Track};
+use crate::utils::epoch_time_to_rfc3339;
+
+#[derive(Debug, Clone, Copy)]
+pub enum SplitRule {
+    Artist,
+    Decade,
+    Explicit,
+    YearAdded,
+    Chunk(usize),
+}
+
+impl SplitRule {
+    pub fn from_str(s: &str) -> Result<SplitRule, String> {
+        match s.to_lowercase().as_str() {
+            "artist" | "artists" => Ok(SplitRule::Artist),
+            "decade" | "decades" => Ok(SplitRule::Decade),
+            "explicit" => Ok(SplitRule::Explicit),
+            "year" | "added" | "year_added" => Ok(SplitRule::YearAdded),
+            s if s.starts_with("chunk:") => {
+                let size = s["chunk:".len()..]
+                    .parse::<usize>()
+                    .map_err(|_| format!("Invalid chunk size: {}", &s["chunk:".len()..]))?;
+                if size == 0 {
+                    return Err("Chunk size must be at least 1".to_string());
+                }
+                Ok(SplitRule::Chunk(size))
+            }
+            _ => Err(format!(
+                "Invalid split rule: {}. Valid rules are: 'artist', 'decade', 'explicit', 'year_added', 'chunk:<size>'",
+                s
+            )),
+        }
+    }
+
+    fn group_of(&self, track: &PlaylistTrack) -> String {
+        match self {
+            SplitRule::Artist => track
+                .track
+                .artists
+                .first()
+                .map(|a| a.name.clone())
+                .unwrap_or_else(|| "Unknown Artist".to_string()),
+            SplitRule::Decade => match track.track.release_date.get(..3) {
+                Some(decade) if decade.chars().all(|c| c.is_ascii_digit()) => {
+                    format!("{}0s", decade)
+                }
+                _ => "Unknown Decade".to_string(),
+            },
+            SplitRule::Explicit => {
+                if track.track.explicit {
+                    "Explicit".to_string()
+                } else {
+                    "Clean".to_string()
+                }
+            }
+            SplitRule::YearAdded => epoch_time_to_rfc3339(track.added_at)[..4].to_string(),
+            SplitRule::Chunk(_) => unreachable!("chunks are not grouped by track"),
+        }
+    }
+}
+
+/// split the given tracks into groups according to the rule. Groups are returned in the order
+/// they first appear in, and tracks keep their order within each group.
+pub fn split_tracks(tracks: &[PlaylistTrack], rule: SplitRule) -> Vec<(String, Vec<Track>)> {
+    if let SplitRule::Chunk(size) = rule {
+        return tracks
+            .chunks(size)
+            .enumerate()
+            .map(|(i, chunk)| {
+                (
+                    format!("Part {}", i + 1),
+                    chunk.iter().map(|t| t.track.clone()).collect(),
+                )
+            })
+            .collect();
+    }
+    let mut groups: Vec<(String, Vec<Track>)> = Vec::new();
+    for track in tracks {
+        let group = rule.group_of(track);
+        match groups.iter_mut().find(|(name, _)| *name == group) {
+            Some((_, vec)) => vec.push(track.track.clone()),
+            None => groups.push((group, vec![track.track.clone()])),
+        }
+    }
+    groups
+}
+
+/// fill in a playlist name template, replacing `{source}` and `{group}`.
+pub fn format_name(template: &str, source: &str, group: &str) -> String {
+    template
+        .replace("{source}", source)
+        .replace("{group}", group)
+}
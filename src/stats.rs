@@ -0,0 +1,613 @@
+use std::collections::{HashMap, HashSet};
+use std::fmt::{Display, Formatter};
+use std::fs::File;
+use std::io::Write;
+use std::path::Path;
+
+use crossterm::style::Stylize;
+
+use crate::account::Account;
+use crate::api::do_api_json;
+use crate::data::{Artist, Content, Stringify, Track};
+use crate::export::xml_escape;
+use crate::history::{Play, MIN_PLAY_MS};
+use crate::utils::{epoch_time_to_rfc3339, StringSized};
+
+const DAY: u64 = 86400;
+const WEEKDAYS: [&str; 7] = [
+    "Monday",
+    "Tuesday",
+    "Wednesday",
+    "Thursday",
+    "Friday",
+    "Saturday",
+    "Sunday",
+];
+/// how many entries the top lists have.
+const TOP: usize = 10;
+/// the width of the longest bar in the terminal charts, in characters.
+const BAR_WIDTH: usize = 40;
+
+/// the time ranges Spotify keeps top tracks and artists for.
+#[derive(Debug, Clone, PartialEq)]
+pub enum TimeRange {
+    Short,
+    Medium,
+    Long,
+}
+
+impl TimeRange {
+    pub fn from_str(s: &str) -> Result<TimeRange, String> {
+        match s.to_lowercase().as_str() {
+            "short" | "month" => Ok(TimeRange::Short),
+            "medium" => Ok(TimeRange::Medium),
+            "long" | "all" => Ok(TimeRange::Long),
+            _ => Err(format!(
+                "Unknown time range: {}. Use short, medium or long",
+                s
+            )),
+        }
+    }
+
+    fn api_name(&self) -> &str {
+        match self {
+            TimeRange::Short => "short_term",
+            TimeRange::Medium => "medium_term",
+            TimeRange::Long => "long_term",
+        }
+    }
+
+    /// how many days back the range goes, or `None` for all time.
+    fn days(&self) -> Option<u64> {
+        match self {
+            TimeRange::Short => Some(28),
+            TimeRange::Medium => Some(182),
+            TimeRange::Long => None,
+        }
+    }
+}
+
+impl Display for TimeRange {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            TimeRange::Short => "the last 4 weeks",
+            TimeRange::Medium => "the last 6 months",
+            TimeRange::Long => "all time",
+        })
+    }
+}
+
+/// a track, artist or album, and how much it was listened to.
+#[derive(Debug, Clone)]
+pub struct Ranked {
+    pub name: String,
+    /// the artist of a track or album. Empty for artists.
+    pub by: String,
+    pub plays: usize,
+    pub ms_played: u64,
+}
+
+/// statistics of the listening history over a time range.
+#[derive(Debug, Clone)]
+pub struct Stats {
+    pub range: TimeRange,
+    pub plays: usize,
+    pub ms_played: u64,
+    /// the part of `ms_played` that is the length of synced tracks, as the API doesn't say how
+    /// long they were played.
+    pub estimated_ms: u64,
+    pub tracks: usize,
+    pub artists: usize,
+    pub days: usize,
+    pub top_tracks: Vec<Ranked>,
+    pub top_artists: Vec<Ranked>,
+    pub top_albums: Vec<Ranked>,
+    /// Spotify's own top tracks and artists, which also count plays from before the local history.
+    pub spotify_tracks: Vec<Track>,
+    pub spotify_artists: Vec<Artist>,
+    /// time listened at each hour of the day, in UTC.
+    pub hours: [u64; 24],
+    /// time listened on each day of the week, Monday first.
+    pub weekdays: [u64; 7],
+    /// the longest run of consecutive days with plays, and the day it started.
+    pub longest_streak: (usize, u64),
+    /// the run of days with plays up to today, or up to yesterday if nothing was played today yet.
+    pub current_streak: usize,
+}
+
+/// the most played of the plays, grouped by the given key. Plays without one are left out.
+fn rank(plays: &[&Play], key: impl Fn(&Play) -> Option<(String, String)>) -> Vec<Ranked> {
+    let mut ranked: HashMap<(String, String), Ranked> = HashMap::new();
+    for play in plays {
+        let Some((name, by)) = key(play) else {
+            continue;
+        };
+        let entry = ranked
+            .entry((name.to_lowercase(), by.to_lowercase()))
+            .or_insert_with(|| Ranked {
+                name,
+                by,
+                plays: 0,
+                ms_played: 0,
+            });
+        entry.plays += 1;
+        entry.ms_played += play.ms_played as u64;
+    }
+    let mut ranked = ranked.into_values().collect::<Vec<Ranked>>();
+    ranked.sort_by(|a, b| {
+        b.plays
+            .cmp(&a.plays)
+            .then(b.ms_played.cmp(&a.ms_played))
+            .then(a.name.cmp(&b.name))
+    });
+    ranked.truncate(TOP);
+    ranked
+}
+
+/// the longest run of consecutive days in the sorted days, and the run that ends on `today` or
+/// the day before.
+fn streaks(days: &[u64], today: u64) -> ((usize, u64), usize) {
+    let mut longest = (0, 0);
+    let mut run = (0, 0);
+    for (i, day) in days.iter().enumerate() {
+        run = match i > 0 && days[i - 1] + 1 == *day {
+            true => (run.0 + 1, run.1),
+            false => (1, *day),
+        };
+        if run.0 > longest.0 {
+            longest = run;
+        }
+    }
+    let current = match days.last() {
+        Some(last) if *last + 1 >= today => run.0,
+        _ => 0,
+    };
+    (longest, current)
+}
+
+impl Stats {
+    /// work out the statistics of the plays in the time range ending at `now`. Spotify's top
+    /// tracks and artists are left empty; see <code>[Stats::fetch_top]</code>.
+    pub fn compute(plays: &[Play], range: TimeRange, now: u64) -> Stats {
+        let start = range
+            .days()
+            .map(|days| now.saturating_sub(days * DAY))
+            .unwrap_or(0);
+        let plays = plays
+            .iter()
+            .filter(|p| p.played_at >= start && p.played_at <= now)
+            .collect::<Vec<&Play>>();
+        // everything counts towards the time listened, but only plays of 30 seconds or more are
+        // counted as plays, as Spotify does
+        let counted = plays
+            .iter()
+            .copied()
+            .filter(|p| p.ms_played >= MIN_PLAY_MS)
+            .collect::<Vec<&Play>>();
+        let mut hours = [0; 24];
+        let mut weekdays = [0; 7];
+        for play in &plays {
+            hours[(play.played_at % DAY / 3600) as usize] += play.ms_played as u64;
+            // 1970-01-01 was a Thursday
+            weekdays[((play.played_at / DAY + 3) % 7) as usize] += play.ms_played as u64;
+        }
+        let mut days = counted
+            .iter()
+            .map(|p| p.played_at / DAY)
+            .collect::<HashSet<u64>>()
+            .into_iter()
+            .collect::<Vec<u64>>();
+        days.sort();
+        let (longest_streak, current_streak) = streaks(&days, now / DAY);
+        Stats {
+            plays: counted.len(),
+            ms_played: plays.iter().map(|p| p.ms_played as u64).sum(),
+            estimated_ms: plays
+                .iter()
+                .filter(|p| p.estimated)
+                .map(|p| p.ms_played as u64)
+                .sum(),
+            tracks: counted
+                .iter()
+                .map(|p| (p.artist.to_lowercase(), p.track.to_lowercase()))
+                .collect::<HashSet<(String, String)>>()
+                .len(),
+            artists: counted
+                .iter()
+                .map(|p| p.artist.to_lowercase())
+                .collect::<HashSet<String>>()
+                .len(),
+            days: days.len(),
+            top_tracks: rank(&counted, |p| Some((p.track.clone(), p.artist.clone()))),
+            top_artists: rank(&counted, |p| Some((p.artist.clone(), String::new()))),
+            top_albums: rank(&counted, |p| {
+                (!p.album.is_empty()).then(|| (p.album.clone(), p.artist.clone()))
+            }),
+            spotify_tracks: Vec::new(),
+            spotify_artists: Vec::new(),
+            hours,
+            weekdays,
+            longest_streak,
+            current_streak,
+            range,
+        }
+    }
+
+    /// get Spotify's top tracks and artists of the account for the time range.
+    pub fn fetch_top(&mut self, acc: &mut Account) -> Result<(), String> {
+        if !acc.scope.contains("user-top-read") {
+            return Err("This account can't read its top tracks, as it was added before spotimine supported them. Re-add it with 'adduser' to allow it".to_string());
+        }
+        let top = |kind: &str| {
+            format!(
+                "me/top/{}?time_range={}&limit={}",
+                kind,
+                self.range.api_name(),
+                TOP
+            )
+        };
+        self.spotify_tracks =
+            Track::from_json_array(&do_api_json("GET", &top("tracks"), acc, "")?["items"])?;
+        self.spotify_artists =
+            Artist::from_json_array(&do_api_json("GET", &top("artists"), acc, "")?["items"])?;
+        Ok(())
+    }
+
+    /// print the statistics as tables and bar charts.
+    pub fn print(&self) {
+        println!(
+            "{}",
+            format!("Listening statistics for {}", self.range).bold()
+        );
+        println!(
+            "{} plays of {} tracks by {} artists, {} listened over {} days",
+            self.plays,
+            self.tracks,
+            self.artists,
+            format_time(self.ms_played),
+            self.days
+        );
+        if self.estimated_ms > 0 {
+            println!(
+                "{} of that is estimated from the lengths of tracks synced with 'history sync', as Spotify doesn't say how long they were played",
+                format_time(self.estimated_ms)
+            );
+        }
+        if self.days > 0 {
+            println!(
+                "Longest streak: {} days, from {}. Current streak: {} days",
+                self.longest_streak.0,
+                day_to_date(self.longest_streak.1),
+                self.current_streak
+            );
+        }
+        for (title, ranked) in [
+            ("Top tracks", &self.top_tracks),
+            ("Top artists", &self.top_artists),
+            ("Top albums", &self.top_albums),
+        ] {
+            if ranked.is_empty() {
+                continue;
+            }
+            println!("\n{}", title.bold());
+            for (i, r) in ranked.iter().enumerate() {
+                println!(
+                    "{:>3}. {} {} {:>5} plays {:>9}",
+                    i + 1,
+                    r.name.sized(40).blue(),
+                    r.by.sized(30),
+                    r.plays,
+                    format_time(r.ms_played)
+                );
+            }
+        }
+        if !self.spotify_tracks.is_empty() {
+            println!("\n{}", "Top tracks according to Spotify".bold());
+            for (i, track) in self.spotify_tracks.iter().enumerate() {
+                println!("{:>3}. {}", i + 1, track);
+            }
+        }
+        if !self.spotify_artists.is_empty() {
+            println!("\n{}", "Top artists according to Spotify".bold());
+            for (i, artist) in self.spotify_artists.iter().enumerate() {
+                println!("{:>3}. {}", i + 1, artist.name.as_str().blue());
+            }
+        }
+        println!("\n{}", "Time listened by hour of the day (UTC)".bold());
+        print_chart(
+            &self
+                .hours
+                .iter()
+                .enumerate()
+                .map(|(hour, ms)| (format!("{:02}:00", hour), *ms))
+                .collect::<Vec<(String, u64)>>(),
+        );
+        println!("\n{}", "Time listened by day of the week (UTC)".bold());
+        print_chart(
+            &WEEKDAYS
+                .iter()
+                .zip(self.weekdays)
+                .map(|(day, ms)| (day.to_string(), ms))
+                .collect::<Vec<(String, u64)>>(),
+        );
+    }
+
+    /// the statistics as a self-contained HTML page.
+    pub fn to_html(&self) -> String {
+        let mut out = format!(
+            "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>Listening statistics for {0}</title>\n<style>\n{1}\n</style>\n</head>\n<body>\n<h1>Listening statistics for {0}</h1>\n",
+            self.range, HTML_STYLE
+        );
+        out.push_str(&format!(
+            "<p>{} plays of {} tracks by {} artists, {} listened over {} days.</p>\n",
+            self.plays,
+            self.tracks,
+            self.artists,
+            format_time(self.ms_played),
+            self.days
+        ));
+        if self.estimated_ms > 0 {
+            out.push_str(&format!(
+                "<p>{} of that is estimated from the lengths of tracks synced with 'history sync', as Spotify doesn't say how long they were played.</p>\n",
+                format_time(self.estimated_ms)
+            ));
+        }
+        if self.days > 0 {
+            out.push_str(&format!(
+                "<p>Longest streak: {} days, from {}. Current streak: {} days.</p>\n",
+                self.longest_streak.0,
+                day_to_date(self.longest_streak.1),
+                self.current_streak
+            ));
+        }
+        for (title, ranked) in [
+            ("Top tracks", &self.top_tracks),
+            ("Top artists", &self.top_artists),
+            ("Top albums", &self.top_albums),
+        ] {
+            if ranked.is_empty() {
+                continue;
+            }
+            out.push_str(&format!(
+                "<h2>{}</h2>\n<table>\n<tr><th>#</th><th>Name</th><th></th><th>Plays</th><th>Time</th></tr>\n",
+                title
+            ));
+            for (i, r) in ranked.iter().enumerate() {
+                out.push_str(&format!(
+                    "<tr><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td></tr>\n",
+                    i + 1,
+                    xml_escape(&r.name),
+                    xml_escape(&r.by),
+                    r.plays,
+                    format_time(r.ms_played)
+                ));
+            }
+            out.push_str("</table>\n");
+        }
+        if !self.spotify_tracks.is_empty() {
+            out.push_str("<h2>Top tracks according to Spotify</h2>\n<ol>\n");
+            for track in &self.spotify_tracks {
+                out.push_str(&format!(
+                    "<li>{} <span class=\"by\">by {}</span></li>\n",
+                    xml_escape(&track.name),
+                    xml_escape(&track.artists.stringify())
+                ));
+            }
+            out.push_str("</ol>\n");
+        }
+        if !self.spotify_artists.is_empty() {
+            out.push_str("<h2>Top artists according to Spotify</h2>\n<ol>\n");
+            for artist in &self.spotify_artists {
+                out.push_str(&format!("<li>{}</li>\n", xml_escape(&artist.name)));
+            }
+            out.push_str("</ol>\n");
+        }
+        out.push_str("<h2>Time listened by hour of the day (UTC)</h2>\n");
+        out.push_str(&html_chart(
+            &self
+                .hours
+                .iter()
+                .enumerate()
+                .map(|(hour, ms)| (format!("{:02}:00", hour), *ms))
+                .collect::<Vec<(String, u64)>>(),
+        ));
+        out.push_str("<h2>Time listened by day of the week (UTC)</h2>\n");
+        out.push_str(&html_chart(
+            &WEEKDAYS
+                .iter()
+                .zip(self.weekdays)
+                .map(|(day, ms)| (day.to_string(), ms))
+                .collect::<Vec<(String, u64)>>(),
+        ));
+        out.push_str("</body>\n</html>\n");
+        out
+    }
+
+    pub fn save_html(&self, path: &Path) -> Result<(), String> {
+        File::create(path)
+            .map_err(|e| format!("failed to create {:?}: {}", path, e))?
+            .write_all(self.to_html().as_bytes())
+            .map_err(|e| format!("failed to write {:?}: {}", path, e))
+    }
+}
+
+const HTML_STYLE: &str =
+    "body { font-family: sans-serif; max-width: 60em; margin: 2em auto; color: #222; }
+table { border-collapse: collapse; }
+td, th { padding: 0.2em 0.8em; text-align: left; }
+tr:nth-child(even) { background: #f2f2f2; }
+.by { color: #666; }
+.chart td { padding: 0.1em 0.5em; }
+.bar { background: #1db954; height: 1em; }";
+
+fn format_time(ms: u64) -> String {
+    let minutes = ms / 60_000;
+    match minutes / 60 {
+        0 => format!("{}m", minutes),
+        hours => format!("{}h {}m", hours, minutes % 60),
+    }
+}
+
+fn day_to_date(day: u64) -> String {
+    epoch_time_to_rfc3339(day * DAY)[..10].to_string()
+}
+
+fn print_chart(rows: &[(String, u64)]) {
+    let max = rows.iter().map(|(_, ms)| *ms).max().unwrap_or(0).max(1);
+    for (label, ms) in rows {
+        println!(
+            "{} {} {}",
+            label.sized(9),
+            "█".repeat((*ms * BAR_WIDTH as u64 / max) as usize).green(),
+            format_time(*ms)
+        );
+    }
+}
+
+fn html_chart(rows: &[(String, u64)]) -> String {
+    let max = rows.iter().map(|(_, ms)| *ms).max().unwrap_or(0).max(1);
+    let mut out = "<table class=\"chart\">\n".to_string();
+    for (label, ms) in rows {
+        out.push_str(&format!(
+            "<tr><td>{}</td><td style=\"width: 30em\"><div class=\"bar\" style=\"width: {:.1}%\"></div></td><td>{}</td></tr>\n",
+            label,
+            *ms as f64 * 100.0 / max as f64,
+            format_time(*ms)
+        ));
+    }
+    out.push_str("</table>\n");
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TODAY: u64 = 19675;
+    /// noon of `TODAY`, 2023-11-14.
+    const NOW: u64 = TODAY * DAY + DAY / 2;
+
+    fn play(played_at: u64, ms_played: u32, track: &str, artist: &str, album: &str) -> Play {
+        Play {
+            played_at,
+            ms_played,
+            track: track.to_string(),
+            artist: artist.to_string(),
+            album: album.to_string(),
+            uri: None,
+            estimated: false,
+        }
+    }
+
+    /// the names and play counts of the ranked entries, in order.
+    fn names(ranked: &[Ranked]) -> Vec<(&str, usize)> {
+        ranked.iter().map(|r| (r.name.as_str(), r.plays)).collect()
+    }
+
+    #[test]
+    fn finds_streaks() {
+        for (days, today, expected) in [
+            (vec![], 10, ((0, 0), 0)),
+            (vec![10], 10, ((1, 10), 1)),
+            (vec![9], 10, ((1, 9), 1)),
+            (vec![5], 10, ((1, 5), 0)),
+            (vec![1, 2, 3, 7, 8], 9, ((3, 1), 2)),
+            (vec![1, 2, 5, 6, 7], 20, ((3, 5), 0)),
+            // the first of two equally long streaks is the longest
+            (vec![1, 2, 4, 5], 5, ((2, 1), 2)),
+        ] {
+            assert_eq!(streaks(&days, today), expected, "{:?}", days);
+        }
+    }
+
+    #[test]
+    fn computes_stats() {
+        let mut synced = play((TODAY - 2) * DAY + 30, 200_000, "A", "X", "Album 1");
+        synced.estimated = true;
+        let plays = vec![
+            // a minute apart, but on two days
+            play((TODAY - 3) * DAY + DAY - 30, 200_000, "A", "X", "Album 1"),
+            synced,
+            play((TODAY - 2) * DAY + DAY / 2, 100_000, "B", "X", ""),
+            play((TODAY - 1) * DAY + 3600, 150_000, "a", "x", "album 1"),
+            // too short to count as a play
+            play(TODAY * DAY + 60, 10_000, "C", "Y", "Album 2"),
+            play((TODAY - 100) * DAY, 300_000, "D", "Z", "Album 3"),
+            play(NOW + 60, 300_000, "E", "Z", "Album 3"),
+        ];
+
+        let stats = Stats::compute(&plays, TimeRange::Short, NOW);
+        assert_eq!(
+            (stats.plays, stats.ms_played, stats.estimated_ms),
+            (4, 660_000, 200_000)
+        );
+        assert_eq!((stats.tracks, stats.artists, stats.days), (2, 1, 3));
+        assert_eq!(stats.longest_streak, (3, TODAY - 3));
+        assert_eq!(stats.current_streak, 3);
+        assert_eq!(names(&stats.top_tracks), [("A", 3), ("B", 1)]);
+        assert_eq!(names(&stats.top_artists), [("X", 4)]);
+        assert_eq!(names(&stats.top_albums), [("Album 1", 3)]);
+        assert_eq!(stats.hours[23], 200_000);
+        assert_eq!(stats.hours[0], 210_000);
+        // 2023-11-14 was a Tuesday
+        assert_eq!(stats.weekdays[1], 10_000);
+
+        let stats = Stats::compute(&plays, TimeRange::Long, NOW);
+        assert_eq!((stats.plays, stats.days), (5, 4));
+        assert_eq!(stats.longest_streak, (3, TODAY - 3));
+        assert_eq!(names(&stats.top_artists), [("X", 4), ("Z", 1)]);
+    }
+
+    #[test]
+    fn computes_stats_of_a_single_day() {
+        let plays = [play(NOW - 60, 200_000, "A", "X", "")];
+        let stats = Stats::compute(&plays, TimeRange::Short, NOW);
+        assert_eq!(
+            (stats.longest_streak, stats.current_streak),
+            ((1, TODAY), 1)
+        );
+        // a day later, the streak still goes on until nothing is played today either
+        let stats = Stats::compute(&plays, TimeRange::Short, NOW + DAY);
+        assert_eq!(stats.current_streak, 1);
+        let stats = Stats::compute(&plays, TimeRange::Short, NOW + 2 * DAY);
+        assert_eq!(stats.current_streak, 0);
+    }
+
+    #[test]
+    fn computes_stats_of_no_plays() {
+        let stats = Stats::compute(&[], TimeRange::Long, NOW);
+        assert_eq!((stats.plays, stats.ms_played, stats.days), (0, 0, 0));
+        assert_eq!((stats.longest_streak, stats.current_streak), ((0, 0), 0));
+        assert!(stats.top_tracks.is_empty() && stats.top_artists.is_empty());
+    }
+
+    #[test]
+    fn ranks_the_most_played_first() {
+        let mut plays = vec![
+            play(0, 60_000, "Short", "X", ""),
+            play(0, 60_000, "Short", "X", ""),
+            play(0, 200_000, "Long", "X", ""),
+            play(0, 200_000, "Long", "X", ""),
+            play(0, 60_000, "Once", "X", ""),
+            play(0, 60_000, "Also once", "X", ""),
+        ];
+        // more tracks than fit in the top list, each played once
+        for i in 0..TOP {
+            plays.push(play(0, 30_000, &format!("Filler {}", i), "Y", ""));
+        }
+        let plays = plays.iter().collect::<Vec<&Play>>();
+        let ranked = rank(&plays, |p| Some((p.track.clone(), p.artist.clone())));
+        assert_eq!(ranked.len(), TOP);
+        // equal plays by time listened, and then by name
+        assert_eq!(
+            names(&ranked[..4]),
+            [("Long", 2), ("Short", 2), ("Also once", 1), ("Once", 1)]
+        );
+        assert!(ranked[4..].iter().all(|r| r.by == "Y"));
+        // plays without a key are left out
+        let ranked = rank(&plays, |p| {
+            (p.artist == "X").then(|| (p.track.clone(), String::new()))
+        });
+        assert_eq!(ranked.len(), 4);
+    }
+}
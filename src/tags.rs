@@ -0,0 +1,747 @@
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom};
+use std::path::Path;
+
+/// the extensions of the audio files whose tags can be read.
+pub const EXTENSIONS: [&str; 7] = ["mp3", "flac", "ogg", "oga", "opus", "m4a", "mp4"];
+
+/// tags bigger than this are corrupt, or mostly cover art that isn't needed.
+const MAX_TAG_SIZE: u64 = 64 * 1024 * 1024;
+/// how deep MP4 atoms with tags are nested at most; deeper ones are from a corrupt file.
+const MAX_MP4_DEPTH: u32 = 8;
+
+/// the tags of a local audio file that are needed to find it on Spotify.
+#[derive(Debug, Clone, Default)]
+pub struct Tags {
+    pub title: Option<String>,
+    pub artist: Option<String>,
+    pub album: Option<String>,
+    pub duration_ms: Option<u32>,
+    pub isrc: Option<String>,
+}
+
+impl Tags {
+    /// set a field from a tag, unless it was already set by an earlier one.
+    fn set(field: &mut Option<String>, value: String) {
+        let value = value.trim_matches(|c: char| c == '\0' || c.is_whitespace());
+        if field.is_none() && !value.is_empty() {
+            *field = Some(value.to_string());
+        }
+    }
+
+    /// set the field a Vorbis comment (`TITLE=...`) is for. Repeated artists are joined.
+    fn set_comment(&mut self, comment: &str) {
+        let Some((key, value)) = comment.split_once('=') else {
+            return;
+        };
+        match key.to_uppercase().as_str() {
+            "TITLE" => Tags::set(&mut self.title, value.to_string()),
+            "ARTIST" => match &mut self.artist {
+                Some(artist) => *artist = format!("{}, {}", artist, value.trim()),
+                None => Tags::set(&mut self.artist, value.to_string()),
+            },
+            "ALBUM" => Tags::set(&mut self.album, value.to_string()),
+            "ISRC" => Tags::set(&mut self.isrc, value.to_string()),
+            _ => (),
+        }
+    }
+}
+
+fn read_bytes(file: &mut File, len: u64) -> Result<Vec<u8>, String> {
+    if len > MAX_TAG_SIZE {
+        return Err("tag is too big".to_string());
+    }
+    let mut buf = vec![0; len as usize];
+    file.read_exact(&mut buf)
+        .map_err(|_| "unexpected end of file".to_string())?;
+    Ok(buf)
+}
+
+fn seek(file: &mut File, pos: SeekFrom) -> Result<u64, String> {
+    file.seek(pos).map_err(|e| e.to_string())
+}
+
+fn u32_be(b: &[u8]) -> u32 {
+    u32::from_be_bytes([b[0], b[1], b[2], b[3]])
+}
+
+fn u32_le(b: &[u8]) -> u32 {
+    u32::from_le_bytes([b[0], b[1], b[2], b[3]])
+}
+
+/// a size in an ID3v2 header, which only uses the lower 7 bits of each byte.
+fn syncsafe(b: &[u8]) -> u32 {
+    b[..4].iter().fold(0, |n, b| (n << 7) | (*b as u32 & 0x7f))
+}
+
+/// undo ID3v2 unsynchronisation, which puts a zero after every 0xff.
+fn remove_unsync(data: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(data.len());
+    for (i, b) in data.iter().enumerate() {
+        if *b == 0 && i > 0 && data[i - 1] == 0xff {
+            continue;
+        }
+        out.push(*b);
+    }
+    out
+}
+
+/// the text of an ID3v2 text frame. Several values, as ID3v2.4 allows, are joined with commas.
+fn id3_text(frame: &[u8]) -> String {
+    let Some((encoding, text)) = frame.split_first() else {
+        return String::new();
+    };
+    let utf16 = |text: &[u8], big_endian: bool| {
+        let units = text
+            .chunks_exact(2)
+            .map(|c| match big_endian {
+                true => u16::from_be_bytes([c[0], c[1]]),
+                false => u16::from_le_bytes([c[0], c[1]]),
+            })
+            .collect::<Vec<u16>>();
+        String::from_utf16_lossy(&units)
+    };
+    let text = match encoding {
+        0 => text.iter().map(|b| *b as char).collect(),
+        1 => match text {
+            [0xff, 0xfe, rest @ ..] => utf16(rest, false),
+            [0xfe, 0xff, rest @ ..] => utf16(rest, true),
+            _ => utf16(text, true),
+        },
+        2 => utf16(text, true),
+        _ => String::from_utf8_lossy(text).to_string(),
+    };
+    text.split(['\0', '\u{feff}'])
+        .filter(|s| !s.is_empty())
+        .collect::<Vec<&str>>()
+        .join(", ")
+}
+
+/// read the ID3v2 tag at the start of the file, if there is one. Returns where the tag ends.
+fn read_id3v2(file: &mut File, tags: &mut Tags) -> Result<u64, String> {
+    seek(file, SeekFrom::Start(0))?;
+    let header = read_bytes(file, 10)?;
+    if &header[..3] != b"ID3" {
+        return Ok(0);
+    }
+    let (major, flags) = (header[3], header[5]);
+    let size = syncsafe(&header[6..]) as u64;
+    let end = 10 + size + if flags & 0x10 != 0 { 10 } else { 0 };
+    let mut data = read_bytes(file, size)?;
+    if major < 4 && flags & 0x80 != 0 {
+        data = remove_unsync(&data);
+    }
+    let mut pos = 0;
+    if flags & 0x40 != 0 && data.len() >= 4 {
+        pos = match major {
+            3 => u32_be(&data) as usize + 4,
+            _ => syncsafe(&data) as usize,
+        };
+    }
+    let header_len = if major == 2 { 6 } else { 10 };
+    while pos + header_len <= data.len() && data[pos] != 0 {
+        let (id, size) = match major {
+            2 => (
+                &data[pos..pos + 3],
+                u32_be(&[0, data[pos + 3], data[pos + 4], data[pos + 5]]),
+            ),
+            3 => (&data[pos..pos + 4], u32_be(&data[pos + 4..])),
+            _ => (&data[pos..pos + 4], syncsafe(&data[pos + 4..])),
+        };
+        let start = pos + header_len;
+        let Some(body) = data.get(start..start + size as usize) else {
+            break;
+        };
+        pos = start + size as usize;
+        let format = if major == 2 { 0 } else { data[start - 1] };
+        let body = match major {
+            // compressed and encrypted frames aren't worth supporting
+            3 if format & 0xc0 != 0 => continue,
+            3 if format & 0x20 != 0 => body.get(1..).unwrap_or_default().to_vec(),
+            4 if format & 0x0c != 0 => continue,
+            4 => {
+                let body = match format & 0x01 != 0 {
+                    true => body.get(4..).unwrap_or_default(),
+                    false => body,
+                };
+                match format & 0x02 != 0 {
+                    true => remove_unsync(body),
+                    false => body.to_vec(),
+                }
+            }
+            _ => body.to_vec(),
+        };
+        match id {
+            b"TIT2" | b"TT2" => Tags::set(&mut tags.title, id3_text(&body)),
+            b"TPE1" | b"TP1" => Tags::set(&mut tags.artist, id3_text(&body)),
+            b"TALB" | b"TAL" => Tags::set(&mut tags.album, id3_text(&body)),
+            b"TSRC" | b"TRC" => Tags::set(&mut tags.isrc, id3_text(&body)),
+            b"TLEN" | b"TLE" if tags.duration_ms.is_none() => {
+                tags.duration_ms = id3_text(&body).trim().parse().ok().filter(|d| *d > 0);
+            }
+            _ => (),
+        }
+    }
+    Ok(end)
+}
+
+/// read the ID3v1 tag at the end of the file, if there is one. Returns whether there was.
+fn read_id3v1(file: &mut File, tags: &mut Tags) -> Result<bool, String> {
+    if seek(file, SeekFrom::End(-128)).is_err() {
+        return Ok(false);
+    }
+    let tag = read_bytes(file, 128)?;
+    if &tag[..3] != b"TAG" {
+        return Ok(false);
+    }
+    let text = |b: &[u8]| b.iter().map(|b| *b as char).collect::<String>();
+    Tags::set(&mut tags.title, text(&tag[3..33]));
+    Tags::set(&mut tags.artist, text(&tag[33..63]));
+    Tags::set(&mut tags.album, text(&tag[63..93]));
+    Ok(true)
+}
+
+/// estimate the duration of an MP3 from its first frame: exactly, if it has a Xing or VBRI header
+/// with the number of frames, or else from the bitrate, as if it was constant.
+fn mp3_duration(file: &mut File, start: u64, end: u64) -> Result<Option<u32>, String> {
+    seek(file, SeekFrom::Start(start))?;
+    let mut buf = Vec::new();
+    file.take(16 * 1024)
+        .read_to_end(&mut buf)
+        .map_err(|e| e.to_string())?;
+    for i in 0..buf.len().saturating_sub(4) {
+        let h = &buf[i..i + 4];
+        let version = (h[1] >> 3) & 3;
+        let (bitrate, rate) = ((h[2] >> 4) as usize, ((h[2] >> 2) & 3) as usize);
+        // only Layer III frames, which is what MP3 is
+        if h[0] != 0xff || h[1] & 0xe0 != 0xe0 || (h[1] >> 1) & 3 != 1 {
+            continue;
+        }
+        if version == 1 || bitrate == 0 || bitrate == 15 || rate == 3 {
+            continue;
+        }
+        let mpeg1 = version == 3;
+        let bitrate = match mpeg1 {
+            true => [
+                0, 32, 40, 48, 56, 64, 80, 96, 112, 128, 160, 192, 224, 256, 320,
+            ][bitrate],
+            false => [0, 8, 16, 24, 32, 40, 48, 56, 64, 80, 96, 112, 128, 144, 160][bitrate],
+        } as u64;
+        let rate = [44100, 48000, 32000][rate] as u64
+            / match version {
+                3 => 1,
+                2 => 2,
+                _ => 4,
+            };
+        let samples_per_frame: u64 = if mpeg1 { 1152 } else { 576 };
+        let mono = h[3] >> 6 == 3;
+        let side_info = match (mpeg1, mono) {
+            (true, true) => 17,
+            (true, false) => 32,
+            (false, true) => 9,
+            (false, false) => 17,
+        };
+        let xing = i + 4 + side_info;
+        let frames = match (buf.get(xing..xing + 12), buf.get(i + 36..i + 54)) {
+            (Some(x), _) if (&x[..4] == b"Xing" || &x[..4] == b"Info") && x[7] & 1 != 0 => {
+                Some(u32_be(&x[8..]) as u64)
+            }
+            (_, Some(v)) if &v[..4] == b"VBRI" => Some(u32_be(&v[14..]) as u64),
+            _ => None,
+        };
+        let ms = match frames {
+            Some(frames) => frames * samples_per_frame * 1000 / rate,
+            None => end.saturating_sub(start + i as u64) * 8 / bitrate,
+        };
+        return Ok(Some(ms as u32).filter(|ms| *ms > 0));
+    }
+    Ok(None)
+}
+
+fn read_mp3(file: &mut File, tags: &mut Tags) -> Result<(), String> {
+    let start = read_id3v2(file, tags)?;
+    let mut end = seek(file, SeekFrom::End(0))?;
+    if read_id3v1(file, tags)? {
+        end -= 128;
+    }
+    if tags.duration_ms.is_none() {
+        tags.duration_ms = mp3_duration(file, start, end)?;
+    }
+    Ok(())
+}
+
+/// read a Vorbis comment block, as used by FLAC, Ogg Vorbis and Opus.
+fn read_vorbis_comment(data: &[u8], tags: &mut Tags) {
+    let read_u32 = |pos: usize| data.get(pos..pos + 4).map(u32_le);
+    let Some(vendor) = read_u32(0) else {
+        return;
+    };
+    let mut pos = 4 + vendor as usize;
+    let Some(count) = read_u32(pos) else {
+        return;
+    };
+    pos += 4;
+    for _ in 0..count {
+        let Some(len) = read_u32(pos) else {
+            return;
+        };
+        let Some(comment) = data.get(pos + 4..pos + 4 + len as usize) else {
+            return;
+        };
+        tags.set_comment(&String::from_utf8_lossy(comment));
+        pos += 4 + len as usize;
+    }
+}
+
+fn read_flac(file: &mut File, tags: &mut Tags) -> Result<(), String> {
+    // FLAC files sometimes have an ID3v2 tag in front, even though they shouldn't
+    let start = read_id3v2(file, tags)?;
+    seek(file, SeekFrom::Start(start))?;
+    if read_bytes(file, 4)? != b"fLaC" {
+        return Err("not a FLAC file".to_string());
+    }
+    loop {
+        let header = read_bytes(file, 4)?;
+        let len = u32_be(&[0, header[1], header[2], header[3]]) as u64;
+        match header[0] & 0x7f {
+            0 => {
+                let info = read_bytes(file, len)?;
+                if info.len() >= 18 {
+                    let rate = ((info[10] as u64) << 12)
+                        | ((info[11] as u64) << 4)
+                        | (info[12] as u64 >> 4);
+                    let samples = ((info[13] as u64 & 0x0f) << 32) | u32_be(&info[14..]) as u64;
+                    if rate > 0 && samples > 0 {
+                        tags.duration_ms = Some((samples * 1000 / rate) as u32);
+                    }
+                }
+            }
+            4 => read_vorbis_comment(&read_bytes(file, len)?, tags),
+            _ => {
+                seek(file, SeekFrom::Current(len as i64))?;
+            }
+        }
+        if header[0] & 0x80 != 0 {
+            return Ok(());
+        }
+    }
+}
+
+/// the first packets of an Ogg stream, put together from its pages.
+fn ogg_packets(file: &mut File, count: usize) -> Result<Vec<Vec<u8>>, String> {
+    let mut packets = Vec::new();
+    let mut packet = Vec::new();
+    while packets.len() < count {
+        let header = read_bytes(file, 27)?;
+        if &header[..4] != b"OggS" {
+            return Err("not an Ogg file".to_string());
+        }
+        let segments = read_bytes(file, header[26] as u64)?;
+        for segment in segments {
+            packet.append(&mut read_bytes(file, segment as u64)?);
+            if packet.len() as u64 > MAX_TAG_SIZE {
+                return Err("tag is too big".to_string());
+            }
+            if segment < 255 {
+                packets.push(std::mem::take(&mut packet));
+            }
+        }
+    }
+    Ok(packets)
+}
+
+fn read_ogg(file: &mut File, tags: &mut Tags) -> Result<(), String> {
+    let packets = ogg_packets(file, 2)?;
+    let (head, comment) = (&packets[0], &packets[1]);
+    // the granule position counts samples, which Opus always has 48000 of a second
+    let (rate, pre_skip) = if head.starts_with(b"\x01vorbis") && head.len() >= 16 {
+        read_vorbis_comment(comment.get(7..).unwrap_or_default(), tags);
+        (u32_le(&head[12..]) as u64, 0)
+    } else if head.starts_with(b"OpusHead") && head.len() >= 12 {
+        read_vorbis_comment(comment.get(8..).unwrap_or_default(), tags);
+        (48000, u16::from_le_bytes([head[10], head[11]]) as u64)
+    } else {
+        return Err("only Vorbis and Opus are supported in Ogg files".to_string());
+    };
+    // the last page has the position of the last sample
+    let len = seek(file, SeekFrom::End(0))?;
+    let tail = len.min(64 * 1024);
+    seek(file, SeekFrom::Start(len - tail))?;
+    let buf = read_bytes(file, tail)?;
+    let last_page = (0..buf.len().saturating_sub(14))
+        .rev()
+        .find(|i| &buf[*i..*i + 4] == b"OggS");
+    if let (Some(i), true) = (last_page, rate > 0) {
+        let granule = u64::from_le_bytes(buf[i + 6..i + 14].try_into().unwrap());
+        if granule != u64::MAX && granule > pre_skip {
+            tags.duration_ms = Some(((granule - pre_skip) * 1000 / rate) as u32);
+        }
+    }
+    Ok(())
+}
+
+/// the atoms in a slice of an MP4 file that's in memory, as (type, content).
+fn mp4_atoms(mut data: &[u8]) -> Vec<(&[u8], &[u8])> {
+    let mut atoms = Vec::new();
+    while data.len() >= 8 {
+        let size = (u32_be(data) as usize).clamp(8, data.len());
+        atoms.push((&data[4..8], &data[8..size]));
+        data = &data[size..];
+    }
+    atoms
+}
+
+/// the text of an item in an `ilst` atom, which is in its `data` atom after 8 bytes of type and
+/// locale.
+fn mp4_text(item: &[u8]) -> String {
+    mp4_atoms(item)
+        .into_iter()
+        .find(|(kind, _)| *kind == b"data")
+        .and_then(|(_, data)| data.get(8..))
+        .map(|text| String::from_utf8_lossy(text).to_string())
+        .unwrap_or_default()
+}
+
+fn read_ilst(data: &[u8], tags: &mut Tags) {
+    for (kind, item) in mp4_atoms(data) {
+        match kind {
+            b"\xa9nam" => Tags::set(&mut tags.title, mp4_text(item)),
+            b"\xa9ART" => Tags::set(&mut tags.artist, mp4_text(item)),
+            b"\xa9alb" => Tags::set(&mut tags.album, mp4_text(item)),
+            // freeform items, like the ISRC, are named by a `name` atom
+            b"----" => {
+                let name = mp4_atoms(item)
+                    .into_iter()
+                    .find(|(kind, _)| *kind == b"name")
+                    .map(|(_, name)| String::from_utf8_lossy(name.get(4..).unwrap_or_default()));
+                if name.is_some_and(|n| n.eq_ignore_ascii_case("isrc")) {
+                    Tags::set(&mut tags.isrc, mp4_text(item));
+                }
+            }
+            _ => (),
+        }
+    }
+}
+
+/// walk the atoms of an MP4 file between the given offsets, reading only the ones with tags or
+/// the duration, so that the audio isn't read. `depth` is how many parents the atoms have.
+fn read_mp4_atoms(
+    file: &mut File,
+    start: u64,
+    end: u64,
+    depth: u32,
+    tags: &mut Tags,
+) -> Result<(), String> {
+    if depth > MAX_MP4_DEPTH {
+        return Err("corrupt MP4 file".to_string());
+    }
+    let mut pos = start;
+    while pos + 8 <= end {
+        seek(file, SeekFrom::Start(pos))?;
+        let header = read_bytes(file, 8)?;
+        let (mut size, mut header_len) = (u32_be(&header) as u64, 8);
+        if size == 1 {
+            size = u64::from_be_bytes(read_bytes(file, 8)?.try_into().unwrap());
+            header_len = 16;
+        } else if size == 0 {
+            size = end - pos;
+        }
+        // the longer header of an atom with a 64-bit size can run past the end of its parent
+        if size < header_len || pos + header_len > end {
+            return Err("corrupt MP4 file".to_string());
+        }
+        let content = (pos + header_len, pos.saturating_add(size).min(end));
+        match &header[4..] {
+            b"moov" | b"udta" => read_mp4_atoms(file, content.0, content.1, depth + 1, tags)?,
+            // in MP4 files, `meta` has 4 bytes of version and flags before its children
+            b"meta" => {
+                let full = read_bytes(file, 4)? == [0, 0, 0, 0];
+                let start = if full { content.0 + 4 } else { content.0 };
+                read_mp4_atoms(file, start, content.1, depth + 1, tags)?;
+            }
+            b"ilst" => read_ilst(&read_bytes(file, content.1 - content.0)?, tags),
+            b"mvhd" => {
+                let mvhd = read_bytes(file, (content.1 - content.0).min(32))?;
+                let (scale, duration) = match mvhd.first() {
+                    Some(1) if mvhd.len() >= 32 => (
+                        u32_be(&mvhd[20..]) as u64,
+                        u64::from_be_bytes(mvhd[24..32].try_into().unwrap()),
+                    ),
+                    Some(0) if mvhd.len() >= 20 => {
+                        (u32_be(&mvhd[12..]) as u64, u32_be(&mvhd[16..]) as u64)
+                    }
+                    _ => (0, 0),
+                };
+                if scale > 0 && duration > 0 {
+                    tags.duration_ms = Some((duration * 1000 / scale) as u32);
+                }
+            }
+            _ => (),
+        }
+        pos = content.1;
+    }
+    Ok(())
+}
+
+/// read the tags of an MP3, FLAC, Ogg Vorbis, Opus or MP4 file, going by its extension.
+pub fn read_tags(path: &Path) -> Result<Tags, String> {
+    let mut file = File::open(path).map_err(|e| format!("failed to open {:?}: {}", path, e))?;
+    let extension = path
+        .extension()
+        .and_then(|e| e.to_str())
+        .unwrap_or_default()
+        .to_lowercase();
+    let mut tags = Tags::default();
+    match extension.as_str() {
+        "mp3" => read_mp3(&mut file, &mut tags),
+        "flac" => read_flac(&mut file, &mut tags),
+        "ogg" | "oga" | "opus" => read_ogg(&mut file, &mut tags),
+        "m4a" | "mp4" => {
+            let len = seek(&mut file, SeekFrom::End(0))?;
+            read_mp4_atoms(&mut file, 0, len, 0, &mut tags)
+        }
+        _ => Err("unsupported file type".to_string()),
+    }
+    .map_err(|e| format!("failed to read the tags of {:?}: {}", path, e))?;
+    Ok(tags)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// write the bytes to a file with the given name, and read its tags.
+    fn tags_of(name: &str, bytes: &[u8]) -> Result<Tags, String> {
+        let path = std::env::temp_dir().join(format!("spotimine-{}-{}", std::process::id(), name));
+        std::fs::write(&path, bytes).unwrap();
+        let tags = read_tags(&path);
+        std::fs::remove_file(&path).unwrap();
+        tags
+    }
+
+    fn id3_frame(id: &[u8], body: &[u8]) -> Vec<u8> {
+        [id, &(body.len() as u32).to_be_bytes(), &[0, 0], body].concat()
+    }
+
+    fn id3v2(frames: &[Vec<u8>]) -> Vec<u8> {
+        let frames = frames.concat();
+        let size = frames.len() as u32;
+        let syncsafe = [21, 14, 7, 0].map(|shift| ((size >> shift) & 0x7f) as u8);
+        [b"ID3\x03\x00\x00".as_slice(), &syncsafe, &frames].concat()
+    }
+
+    /// the header of a 128 kbps, 44.1 kHz MPEG-1 Layer III frame.
+    const MP3_FRAME: [u8; 4] = [0xff, 0xfb, 0x90, 0x00];
+
+    fn vorbis_comment(comments: &[&str]) -> Vec<u8> {
+        let mut out = [&3u32.to_le_bytes(), b"foo".as_slice()].concat();
+        out.extend((comments.len() as u32).to_le_bytes());
+        for comment in comments {
+            out.extend((comment.len() as u32).to_le_bytes());
+            out.extend(comment.as_bytes());
+        }
+        out
+    }
+
+    fn ogg_page(granule: u64, packets: &[&[u8]]) -> Vec<u8> {
+        let mut out = [b"OggS\x00\x00".as_slice(), &granule.to_le_bytes(), &[0; 12]].concat();
+        out.push(packets.len() as u8);
+        out.extend(packets.iter().map(|p| p.len() as u8));
+        out.extend(packets.concat());
+        out
+    }
+
+    fn atom(kind: &[u8], content: &[u8]) -> Vec<u8> {
+        [&(content.len() as u32 + 8).to_be_bytes(), kind, content].concat()
+    }
+
+    fn mp4_item(kind: &[u8], text: &str) -> Vec<u8> {
+        atom(
+            kind,
+            &atom(
+                b"data",
+                &[&[0, 0, 0, 1, 0, 0, 0, 0], text.as_bytes()].concat(),
+            ),
+        )
+    }
+
+    fn mp4() -> Vec<u8> {
+        // version 0 mvhd: version and flags, two dates, then the time scale and duration
+        let mvhd = [
+            [0; 12].as_slice(),
+            &1000u32.to_be_bytes(),
+            &4500u32.to_be_bytes(),
+        ]
+        .concat();
+        let isrc = atom(
+            b"----",
+            &[
+                atom(b"mean", b"\0\0\0\0com.apple.iTunes"),
+                atom(b"name", b"\0\0\0\0ISRC"),
+                atom(b"data", b"\0\0\0\x01\0\0\0\0USRC17607839"),
+            ]
+            .concat(),
+        );
+        let ilst = atom(
+            b"ilst",
+            &[
+                mp4_item(b"\xa9nam", "Title"),
+                mp4_item(b"\xa9ART", "Artist"),
+                mp4_item(b"\xa9alb", "Album"),
+                isrc,
+            ]
+            .concat(),
+        );
+        let meta = atom(b"meta", &[[0; 4].as_slice(), &ilst].concat());
+        let moov = atom(
+            b"moov",
+            &[atom(b"mvhd", &mvhd), atom(b"udta", &meta)].concat(),
+        );
+        [
+            atom(b"ftyp", b"M4A \0\0\0\0"),
+            moov,
+            atom(b"mdat", &[0; 64]),
+        ]
+        .concat()
+    }
+
+    #[test]
+    fn reads_id3v2_frames() {
+        let utf16 = [b"\x01\xff\xfe".as_slice(), &[b'A', 0, b'r', 0, b't', 0]].concat();
+        let mut file = id3v2(&[
+            id3_frame(b"TIT2", b"\x00Title"),
+            id3_frame(b"TPE1", &utf16),
+            id3_frame(b"TALB", b"\x03Album\x00"),
+            id3_frame(b"TSRC", b"\x00USRC17607839"),
+            id3_frame(b"TLEN", b"\x00215000"),
+        ]);
+        file.extend(MP3_FRAME);
+        file.extend([0; 1000]);
+        let tags = tags_of("id3v2.mp3", &file).unwrap();
+        assert_eq!(tags.title.as_deref(), Some("Title"));
+        assert_eq!(tags.artist.as_deref(), Some("Art"));
+        assert_eq!(tags.album.as_deref(), Some("Album"));
+        assert_eq!(tags.isrc.as_deref(), Some("USRC17607839"));
+        assert_eq!(tags.duration_ms, Some(215000));
+    }
+
+    #[test]
+    fn reads_id3v1_and_estimates_mp3_duration() {
+        // a second of audio at 128 kbps is 16000 bytes
+        let mut file = MP3_FRAME.to_vec();
+        file.extend([0; 15996]);
+        let mut id3v1 = [0; 128];
+        id3v1[..3].copy_from_slice(b"TAG");
+        id3v1[3..8].copy_from_slice(b"Title");
+        id3v1[33..39].copy_from_slice(b"Artist");
+        file.extend(id3v1);
+        let tags = tags_of("id3v1.mp3", &file).unwrap();
+        assert_eq!(tags.title.as_deref(), Some("Title"));
+        assert_eq!(tags.artist.as_deref(), Some("Artist"));
+        assert_eq!(tags.album, None);
+        assert_eq!(tags.duration_ms, Some(1000));
+    }
+
+    #[test]
+    fn reads_flac() {
+        let mut info = [0; 34];
+        // 44100 Hz in 20 bits, then 3 seconds of samples in 36
+        info[10..13].copy_from_slice(&[0x0a, 0xc4, 0x42]);
+        info[14..18].copy_from_slice(&132300u32.to_be_bytes());
+        let comment = vorbis_comment(&["TITLE=Title", "artist=One", "ARTIST=Two", "ISRC=X"]);
+        let file = [
+            b"fLaC\x00\x00\x00\x22".as_slice(),
+            &info,
+            &[0x84, 0, 0, comment.len() as u8],
+            &comment,
+        ]
+        .concat();
+        let tags = tags_of("test.flac", &file).unwrap();
+        assert_eq!(tags.title.as_deref(), Some("Title"));
+        assert_eq!(tags.artist.as_deref(), Some("One, Two"));
+        assert_eq!(tags.isrc.as_deref(), Some("X"));
+        assert_eq!(tags.duration_ms, Some(3000));
+    }
+
+    #[test]
+    fn reads_ogg_vorbis_and_opus() {
+        let head = [
+            b"\x01vorbis\0\0\0\0\x02".as_slice(),
+            &48000u32.to_le_bytes(),
+            &[0; 14],
+        ]
+        .concat();
+        let comment = [b"\x03vorbis".as_slice(), &vorbis_comment(&["TITLE=Vorbis"])].concat();
+        let file = [
+            ogg_page(0, &[&head]),
+            ogg_page(0, &[&comment]),
+            ogg_page(96000, &[&[0; 100]]),
+        ]
+        .concat();
+        let tags = tags_of("test.ogg", &file).unwrap();
+        assert_eq!(tags.title.as_deref(), Some("Vorbis"));
+        assert_eq!(tags.duration_ms, Some(2000));
+
+        let head = [
+            b"OpusHead\x01\x02".as_slice(),
+            &312u16.to_le_bytes(),
+            &[0; 7],
+        ]
+        .concat();
+        let comment = [b"OpusTags".as_slice(), &vorbis_comment(&["TITLE=Opus"])].concat();
+        let file = [
+            ogg_page(0, &[&head]),
+            ogg_page(0, &[&comment]),
+            ogg_page(48312, &[&[0; 100]]),
+        ]
+        .concat();
+        let tags = tags_of("test.opus", &file).unwrap();
+        assert_eq!(tags.title.as_deref(), Some("Opus"));
+        assert_eq!(tags.duration_ms, Some(1000));
+    }
+
+    #[test]
+    fn reads_mp4() {
+        let tags = tags_of("test.m4a", &mp4()).unwrap();
+        assert_eq!(tags.title.as_deref(), Some("Title"));
+        assert_eq!(tags.artist.as_deref(), Some("Artist"));
+        assert_eq!(tags.album.as_deref(), Some("Album"));
+        assert_eq!(tags.isrc.as_deref(), Some("USRC17607839"));
+        assert_eq!(tags.duration_ms, Some(4500));
+    }
+
+    #[test]
+    fn fails_on_truncated_files() {
+        // these only must not panic, as cutting at some atoms leaves a valid file
+        let mp4 = mp4();
+        for len in 0..mp4.len() {
+            let _ = tags_of("truncated.m4a", &mp4[..len]);
+        }
+        // an atom with a 64-bit size, whose header runs past the end of its parent
+        let file = [
+            atom(b"moov", b"\0\0\0\x01ilst"),
+            24u64.to_be_bytes().to_vec(),
+            vec![0; 8],
+        ]
+        .concat();
+        assert!(tags_of("long.m4a", &file).is_err());
+        // a 64-bit size that points past the end of the file
+        let file = [
+            b"\0\0\0\x01ilst".as_slice(),
+            &u64::MAX.to_be_bytes(),
+            &[0; 8],
+        ]
+        .concat();
+        assert!(tags_of("huge.m4a", &file).is_ok());
+        // atoms nested far deeper than any real file's
+        let file = (0..100).fold(Vec::new(), |inner, _| atom(b"moov", &inner));
+        assert!(tags_of("nested.m4a", &file).is_err());
+
+        let mut flac = b"fLaC\x00\x00\x00\x22".to_vec();
+        flac.extend([0; 10]);
+        assert!(tags_of("truncated.flac", &flac).is_err());
+        assert!(tags_of("truncated.ogg", b"OggS\0\0").is_err());
+        let id3 = id3v2(&[id3_frame(b"TIT2", b"\x00Title")]);
+        assert!(tags_of("truncated.mp3", &id3[..id3.len() - 3]).is_err());
+    }
+}
@@ -1,3 +1,7 @@
+use std::io::Read;
+
+use image::codecs::jpeg::JpegEncoder;
+use image::imageops::FilterType;
 use rand::distributions::Alphanumeric;
 use rand::Rng;
 use sha2::Digest;
@@ -46,57 +50,216 @@ pub(crate) fn base64ify_raw(s: Box<[u8]>) -> String {
     base64::encode_engine(s, &URL_SAFE_ENGINE)
 }
 
+/// percent-encode a string for use in a URL query, e.g. a search query.
+pub(crate) fn url_encode(s: &str) -> String {
+    s.bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => {
+                (b as char).to_string()
+            }
+            _ => format!("%{:02X}", b),
+        })
+        .collect()
+}
+
 pub(crate) fn gen_code_challenge(s: &String) -> String {
     let result = base64ify_raw(sha_256ify_raw(s));
     info!("code_challenge: {}", result);
     result
 }
 
-/// convert a timestamp in RFC3339 format to a unix timestamp.
-/// e.g. 2020-01-01T00:00:00Z -> 1577836800
-pub(crate) fn rfc3339_to_epoch_time(s: &str) -> u64 {
-    let s = s.replace('Z', "");
-    let s = s.split('T').collect::<Vec<&str>>();
-    let date = s[0]
-        .split('-')
-        .collect::<Vec<&str>>()
-        .iter()
-        .map(|s| s.parse::<u64>().unwrap())
-        .collect::<Vec<u64>>();
-    let time = s[1]
-        .split(':')
-        .collect::<Vec<&str>>()
-        .iter()
-        .map(|s| s.parse::<u64>().unwrap())
-        .collect::<Vec<u64>>();
-    time[0] * 3600
-        + time[1] * 60
-        + time[2]
-        + date[2] * 86400
-        + date[1] * 2592000
-        + date[0] * 31104000_u64
+fn is_leap_year(year: i64) -> bool {
+    (year % 4 == 0 && year % 100 != 0) || year % 400 == 0
+}
+
+fn days_in_month(year: i64, month: i64) -> i64 {
+    match month {
+        2 if is_leap_year(year) => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+/// the number of days from 1970-01-01 to the given date in the Gregorian calendar.
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    // count years from March, so that the leap day is the last day of the year
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146097 + day_of_era - 719468
+}
+
+/// inverse of <code>[days_from_civil]</code>
+fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    let days = days + 719468;
+    let era = days.div_euclid(146097);
+    let day_of_era = days - era * 146097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month + 2) / 5 + 1;
+    let month = if month < 10 { month + 3 } else { month - 9 };
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
+
+/// convert a timestamp in RFC3339 format to a unix timestamp. Fractional seconds are dropped, and
+/// time zone offsets are applied. e.g. 2020-01-01T01:00:00.5+01:00 -> 1577836800
+pub(crate) fn rfc3339_to_epoch_time(s: &str) -> Result<u64, String> {
+    let invalid = || format!("Invalid RFC3339 timestamp: {}", s);
+    let number = |part: &str| -> Result<i64, String> {
+        if part.is_empty() || !part.chars().all(|c| c.is_ascii_digit()) {
+            return Err(invalid());
+        }
+        part.parse::<i64>().map_err(|_| invalid())
+    };
+    let b = s.as_bytes();
+    if !s.is_ascii()
+        || b.len() < 20
+        || b[4] != b'-'
+        || b[7] != b'-'
+        || !matches!(b[10], b'T' | b't' | b' ')
+        || b[13] != b':'
+        || b[16] != b':'
+    {
+        return Err(invalid());
+    }
+    let year = number(&s[0..4])?;
+    let month = number(&s[5..7])?;
+    let day = number(&s[8..10])?;
+    let hour = number(&s[11..13])?;
+    let minute = number(&s[14..16])?;
+    // 60 is a leap second
+    let second = number(&s[17..19])?;
+    if !(1..=12).contains(&month)
+        || day < 1
+        || day > days_in_month(year, month)
+        || hour > 23
+        || minute > 59
+        || second > 60
+    {
+        return Err(invalid());
+    }
+
+    let mut rest = &s[19..];
+    if let Some(fraction) = rest.strip_prefix('.') {
+        let digits = fraction
+            .find(|c: char| !c.is_ascii_digit())
+            .unwrap_or(fraction.len());
+        if digits == 0 {
+            return Err(invalid());
+        }
+        rest = &fraction[digits..];
+    }
+    let offset = match rest {
+        "Z" | "z" => 0,
+        _ if rest.len() == 6 && rest.as_bytes()[3] == b':' => {
+            let hours = number(&rest[1..3])?;
+            let minutes = number(&rest[4..6])?;
+            if hours > 23 || minutes > 59 {
+                return Err(invalid());
+            }
+            match rest.as_bytes()[0] {
+                b'+' => hours * 3600 + minutes * 60,
+                b'-' => -(hours * 3600 + minutes * 60),
+                _ => return Err(invalid()),
+            }
+        }
+        _ => return Err(invalid()),
+    };
+
+    let t = days_from_civil(year, month, day) * 86400 + hour * 3600 + minute * 60 + second - offset;
+    u64::try_from(t).map_err(|_| format!("Timestamp is before 1970: {}", s))
 }
 
-/// inverse of <code>[rfc3339_to_epoch_time]</code>
+/// inverse of <code>[rfc3339_to_epoch_time]</code>, always in UTC.
 pub(crate) fn epoch_time_to_rfc3339(t: u64) -> String {
-    let mut t = t;
-    let mut s = String::new();
-    let years = t / 31104000;
-    t -= years * 31104000;
-    let months = t / 2592000;
-    t -= months * 2592000;
-    let days = t / 86400;
-    t -= days * 86400;
-    let hours = t / 3600;
-    t -= hours * 3600;
-    let minutes = t / 60;
-    t -= minutes * 60;
-    let seconds = t;
-    s.push_str(&format!(
+    let (year, month, day) = civil_from_days((t / 86400) as i64);
+    format!(
         "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
-        years, months, days, hours, minutes, seconds
-    ));
-    s
+        year,
+        month,
+        day,
+        t % 86400 / 3600,
+        t % 3600 / 60,
+        t % 60
+    )
+}
+
+/// timestamps before 3000-01-01 are real unix times. Anything larger was saved by an older
+/// version, which counted every month as 30 days and every year as 360 days from year 0.
+const LEGACY_EPOCH_TIME: u64 = 32503680000;
+
+/// convert a timestamp saved by an older version to a real unix timestamp. Real timestamps are
+/// returned as they are. The old format can't tell the 31st of a month from the 1st of the next,
+/// so those come back as the 1st.
+pub(crate) fn from_legacy_epoch_time(t: u64) -> u64 {
+    if t < LEGACY_EPOCH_TIME {
+        return t;
+    }
+    let mut years = (t / 31104000) as i64;
+    let mut months = (t % 31104000 / 2592000) as i64;
+    let mut days = (t % 2592000 / 86400) as i64;
+    // the 30th of a month, and all of December, spill over into the next month or year
+    if days == 0 {
+        months -= 1;
+        days = 30;
+    }
+    if months <= 0 {
+        years -= 1;
+        months += 12;
+    }
+    let days = days_from_civil(years, months, 1) + days - 1;
+    (days.max(0) * 86400) as u64 + t % 86400
+}
+
+/// the largest base64 payload Spotify accepts for a playlist cover image.
+const MAX_COVER_SIZE: usize = 256 * 1024;
+
+/// download an image, e.g. a playlist cover, from the given URL.
+pub(crate) fn fetch_image(url: &str) -> Result<Vec<u8>, String> {
+    let mut bytes = Vec::new();
+    ureq::get(url)
+        .call()
+        .map_err(|e| format!("failed to download image: {}", e))?
+        .into_reader()
+        .read_to_end(&mut bytes)
+        .map_err(|e| format!("failed to read image: {}", e))?;
+    Ok(bytes)
+}
+
+/// re-encode an image as a base64 JPEG that fits in Spotify's cover size limit, lowering the
+/// quality and then the resolution until it does.
+pub(crate) fn encode_cover(bytes: &[u8]) -> Result<String, String> {
+    let mut image = image::load_from_memory(bytes)
+        .map_err(|e| format!("failed to read image: {}", e))?
+        .into_rgb8();
+    loop {
+        for quality in [90, 80, 70, 60, 50] {
+            let mut jpeg = Vec::new();
+            JpegEncoder::new_with_quality(&mut jpeg, quality)
+                .encode_image(&image)
+                .map_err(|e| format!("failed to encode image: {}", e))?;
+            let encoded = base64::encode(jpeg);
+            if encoded.len() <= MAX_COVER_SIZE {
+                return Ok(encoded);
+            }
+        }
+        if image.width() < 64 || image.height() < 64 {
+            return Err("image is too large to use as a cover".to_string());
+        }
+        info!("cover image is too large, downscaling");
+        image = image::imageops::resize(
+            &image,
+            image.width() * 3 / 4,
+            image.height() * 3 / 4,
+            FilterType::Triangle,
+        );
+    }
 }
 
 pub(crate) fn strip_html_tags(str: &str) -> String {
@@ -140,12 +303,125 @@ pub trait StringSized {
 
 impl StringSized for String {
     fn sized(&self, limit: usize) -> String {
-        if self.len() > limit {
-            format!("{}...", &self[..limit - 3])
+        let len = self.chars().count();
+        if len > limit {
+            format!("{}...", self.chars().take(limit - 3).collect::<String>())
         } else {
             let mut s = self.clone();
-            s.push_str(&" ".repeat(limit - self.len()));
+            s.push_str(&" ".repeat(limit - len));
             s
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+
+    use super::*;
+
+    /// 9999-12-31T23:59:59Z, the last time with a four digit year.
+    const MAX_TIME: u64 = 253402300799;
+
+    #[test]
+    fn parses_known_timestamps() {
+        assert_eq!(rfc3339_to_epoch_time("1970-01-01T00:00:00Z"), Ok(0));
+        assert_eq!(
+            rfc3339_to_epoch_time("2020-01-01T00:00:00Z"),
+            Ok(1577836800)
+        );
+        assert_eq!(
+            rfc3339_to_epoch_time("2020-02-29T12:34:56Z"),
+            Ok(1582979696)
+        );
+        assert_eq!(rfc3339_to_epoch_time("2000-03-01T00:00:00Z"), Ok(951868800));
+        assert_eq!(
+            rfc3339_to_epoch_time("2020-01-01T00:00:00.999Z"),
+            Ok(1577836800)
+        );
+        assert_eq!(
+            rfc3339_to_epoch_time("2020-01-01T01:30:00+01:30"),
+            Ok(1577836800)
+        );
+        assert_eq!(
+            rfc3339_to_epoch_time("2019-12-31T19:00:00-05:00"),
+            Ok(1577836800)
+        );
+        assert_eq!(
+            rfc3339_to_epoch_time("2020-01-01t00:00:00z"),
+            Ok(1577836800)
+        );
+    }
+
+    #[test]
+    fn rejects_malformed_timestamps() {
+        for s in [
+            "",
+            "2020-01-01",
+            "2020-01-01T00:00:00",
+            "2020-01-01T00:00Z",
+            "2020-13-01T00:00:00Z",
+            "2020-00-01T00:00:00Z",
+            "2021-02-29T00:00:00Z",
+            "2020-04-31T00:00:00Z",
+            "2020-01-01T24:00:00Z",
+            "2020-01-01T00:00:00.Z",
+            "2020-01-01T00:00:00+24:00",
+            "2020-01-01T00:00:00+0100",
+            "2020-01-01T00:00:00Z ",
+            "1969-12-31T23:59:59Z",
+            "2020-01-01T00:00:00+00:0\u{e9}",
+            "+020-01-01T00:00:00Z",
+        ] {
+            assert!(rfc3339_to_epoch_time(s).is_err(), "accepted {:?}", s);
+        }
+    }
+
+    #[test]
+    fn formats_known_timestamps() {
+        assert_eq!(epoch_time_to_rfc3339(0), "1970-01-01T00:00:00Z");
+        assert_eq!(epoch_time_to_rfc3339(1582979696), "2020-02-29T12:34:56Z");
+        assert_eq!(epoch_time_to_rfc3339(MAX_TIME), "9999-12-31T23:59:59Z");
+    }
+
+    #[test]
+    fn epoch_time_round_trips() {
+        let mut rng = StdRng::seed_from_u64(0);
+        for _ in 0..100_000 {
+            let t = rng.gen_range(0..=MAX_TIME);
+            assert_eq!(rfc3339_to_epoch_time(&epoch_time_to_rfc3339(t)), Ok(t));
+        }
+    }
+
+    #[test]
+    fn rfc3339_round_trips() {
+        let mut rng = StdRng::seed_from_u64(0);
+        for _ in 0..100_000 {
+            let year = rng.gen_range(1970..=9999);
+            let month = rng.gen_range(1..=12);
+            let s = format!(
+                "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+                year,
+                month,
+                rng.gen_range(1..=days_in_month(year, month)),
+                rng.gen_range(0..24),
+                rng.gen_range(0..60),
+                rng.gen_range(0..60)
+            );
+            assert_eq!(epoch_time_to_rfc3339(rfc3339_to_epoch_time(&s).unwrap()), s);
+        }
+    }
+
+    #[test]
+    fn converts_legacy_timestamps() {
+        let legacy = |y: u64, m: u64, d: u64| y * 31104000 + m * 2592000 + d * 86400 + 3661;
+        assert_eq!(from_legacy_epoch_time(1577836800), 1577836800);
+        for (y, m, d) in [(2020, 6, 15), (2020, 1, 30), (2020, 12, 25), (2020, 12, 30)] {
+            assert_eq!(
+                epoch_time_to_rfc3339(from_legacy_epoch_time(legacy(y, m, d))),
+                format!("{:04}-{:02}-{:02}T01:01:01Z", y, m, d)
+            );
+        }
+    }
+}